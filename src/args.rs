@@ -1,8 +1,11 @@
 use clap::Parser;
 use crate::cmd::Cmd;
 use crate::output::Output;
+use crate::tokenizer;
+use crate::elastic;
+use crate::source_data;
 use std::fmt::{self, Display, Formatter};
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 use serde::{Deserialize, Serialize};
 use serde_json;
 use std::fs::File;
@@ -11,11 +14,11 @@ use std::io::BufReader;
 #[derive(Parser)]
 struct Args {
     /// Command to run: Available commands: 
-    /// 'build-vocab', 'build-dataset-vectors', 'match-json-zip', 'build-source-data', 'dump-source-data' (Default: 'match-json-zip')
+    /// 'build-vocab', 'build-dataset-vectors', 'match-json-zip', 'match-single-json', 'serve', 'build-source-data', 'build-all', 'dump-source-data', 'inspect-vocab', 'self-check' (Default: 'match-json-zip')
     #[clap(short = 'c', long = "command")]
     command: Option<String>,
     /// Source name, required with: 
-    /// 'build-vocab', 'build-dataset-vectors', 'match-json-zip', 'build-source-data', 'dump-source-data'
+    /// 'build-vocab', 'build-dataset-vectors', 'match-json-zip', 'match-single-json', 'serve', 'build-source-data', 'build-all', 'dump-source-data', 'self-check'
     #[clap(short = 's', long = "source")]
     source: Option<String>,
     /// File to save the vocab to with 'build-vocab' command, later for loading the vocab as well
@@ -24,20 +27,24 @@ struct Args {
     vocab_file: Option<String>,
     /// File to save the dataset vectors to with 'build-dataset-vectors' command, later for loading the dataset vectors as well
     /// [Defaults to 'data/<source-name>-dataset-vectors.bin']
-    #[clap(short = 'D', long = "dataset-vector-file")]
-    dataset_vector_file: Option<String>,
+    /// The match commands accept several files (-D a.bin -D b.bin), matched as one dataset
+    #[clap(short = 'D', long = "dataset-vector-file", num_args = 1..)]
+    dataset_vector_file: Vec<String>,
     /// File to save the source data to with 'build-source-data' command, later for loading the source data as well
     /// [Defaults to 'data/<source-name>-source-data.bin']
-    #[clap(short = 'S', long = "source-data-file")]
-    source_data_file: Option<String>,
+    /// The match commands accept several files (-S a.bin -S b.bin), one for each dataset vectors file
+    #[clap(short = 'S', long = "source-data-file", num_args = 1..)]
+    source_data_file: Vec<String>,
     /// Input. File or directory to read input from. Format of input depends on the command.
-    #[clap(short = 'i', long = "input")]
-    input: Option<String>,
+    /// match-json-zip accepts several inputs (-i a.zip -i b.zip, or -i *.zip), matched in one run
+    #[clap(short = 'i', long = "input", num_args = 1..)]
+    input: Vec<String>,
     /// Output. File to write output to. Format of output depends on the command. Defaults to stdout.
     #[clap(short = 'o', long = "output")]
     output: Option<String>,
-    /// Output format. Format of the output. Available formats: 'text', 'csv', 'xlsx'
-    /// [Defaults to 'text']
+    /// Output format. Format of the output. Available formats: 'text', 'json', 'csv', 'xlsx', 'crosswalk'
+    /// [Defaults to 'text' on stdout. For an output file of match-json-zip it is taken from the
+    /// extension (.csv, .tsv, .json, .xlsx, .txt)]
     #[clap(short = 'F', long = "output-format")]
     output_format: Option<String>,
     /// Print verbose output
@@ -60,7 +67,13 @@ pub struct Config {
     pub vocab_file: String,
     pub dataset_vector_file: String,
     pub source_data_file: String,
+    // All dataset vectors and source data files of the match commands in the order given,
+    // dataset_vector_file and source_data_file are the first of them
+    pub dataset_vector_files: Vec<String>,
+    pub source_data_files: Vec<String>,
     pub input: String,
+    // All inputs of match-json-zip in the order given, input is the first of them
+    pub inputs: Vec<String>,
     pub output: Output,
     pub output_format: OutputFormat,
     pub verbose: bool,
@@ -71,6 +84,11 @@ pub struct Config {
 }
 
 pub const DEFAULT_YEAR_TOLERANCE_PENALTY: f32 = 0.25;
+// Parts that need a weight in a weights file, a weights profile or the weights of the config file
+pub const WEIGHT_PARTS: [&str; 5] = ["author", "title", "location", "year", "all"];
+
+// JSON schema version "auto", the version is read from each JSON file's "schema_version" field instead
+pub const JSON_SCHEMA_VERSION_AUTO: i32 = 0;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum JaroTruncate {
@@ -101,6 +119,275 @@ impl From<JaroTruncate> for Option<String> {
     }
 }
 
+// How strings are split into tokens, see tokenizer::tokenize_part
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TokenizerMode {
+    Ngram,     // 2- and 3-grams over the whole string (default)
+    WordNgram, // 2- and 3-grams within each word, whole words for location
+}
+
+impl Display for TokenizerMode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            TokenizerMode::Ngram => write!(f, "ngram"),
+            TokenizerMode::WordNgram => write!(f, "word-ngram"),
+        }
+    }
+}
+
+impl TokenizerMode {
+    fn parse(value: &str) -> TokenizerMode {
+        match value {
+            "ngram" => TokenizerMode::Ngram,
+            "word-ngram" => TokenizerMode::WordNgram,
+            _ => {
+                eprintln!("Unknown tokenizer: {} (available: ngram, word-ngram)", value);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+// Order of the names of each author before tokenizing, see tokenizer::normalize_authors
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AuthorNormalize {
+    None,         // Author as given (default)
+    SurnameFirst, // "Surname, Given names"
+    GivenFirst,   // "Given names Surname"
+}
+
+impl Display for AuthorNormalize {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            AuthorNormalize::None => write!(f, "none"),
+            AuthorNormalize::SurnameFirst => write!(f, "surname-first"),
+            AuthorNormalize::GivenFirst => write!(f, "given-first"),
+        }
+    }
+}
+
+impl AuthorNormalize {
+    fn parse(value: &str) -> AuthorNormalize {
+        match value {
+            "none" => AuthorNormalize::None,
+            "surname-first" => AuthorNormalize::SurnameFirst,
+            "given-first" => AuthorNormalize::GivenFirst,
+            _ => {
+                eprintln!("Unknown author normalization: {} (available: none, surname-first, given-first)", value);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+// What to do with input files that give the same card name (files with the same name in different
+// directories of the input, since version 2 and later use the file name without directory as card)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DedupInput {
+    Warn,     // Keep all files and record a warning (default)
+    Error,    // Stop with an error
+    First,    // Keep the first file (in path order), skip the others with a warning
+    KeepPath, // Use the full path in the input as card name for these files
+}
+
+impl Display for DedupInput {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            DedupInput::Warn => write!(f, "warn"),
+            DedupInput::Error => write!(f, "error"),
+            DedupInput::First => write!(f, "first"),
+            DedupInput::KeepPath => write!(f, "keep-path"),
+        }
+    }
+}
+
+impl DedupInput {
+    fn parse(value: &str) -> DedupInput {
+        match value {
+            "warn" => DedupInput::Warn,
+            "error" => DedupInput::Error,
+            "first" => DedupInput::First,
+            "keep-path" => DedupInput::KeepPath,
+            _ => {
+                eprintln!("Unknown dedup-input: {} (available: warn, error, first, keep-path)", value);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+// How the z-scores of the candidates are calculated, see matcher::calculate_z_scores
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ZscoreMethod {
+    Mean,   // (x - mean) / standard deviation (default)
+    Robust, // (x - median) / median absolute deviation
+}
+
+impl Display for ZscoreMethod {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ZscoreMethod::Mean => write!(f, "mean"),
+            ZscoreMethod::Robust => write!(f, "robust"),
+        }
+    }
+}
+
+impl ZscoreMethod {
+    fn parse(value: &str) -> ZscoreMethod {
+        match value {
+            "mean" => ZscoreMethod::Mean,
+            "robust" => ZscoreMethod::Robust,
+            _ => {
+                eprintln!("Unknown zscore method: {} (available: mean, robust)", value);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+// Transform of the similarity after all adjustments, before thresholds and z-scores, see matcher::apply_score_transform
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ScoreTransform {
+    None, // The similarity as it is (default)
+    Sqrt, // Square root, spreads the low similarities
+    Rank, // Normalized rank in the candidate pool, 1.0 for the best
+}
+
+impl Display for ScoreTransform {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ScoreTransform::None => write!(f, "none"),
+            ScoreTransform::Sqrt => write!(f, "sqrt"),
+            ScoreTransform::Rank => write!(f, "rank"),
+        }
+    }
+}
+
+impl ScoreTransform {
+    fn parse(value: &str) -> ScoreTransform {
+        match value {
+            "none" => ScoreTransform::None,
+            "sqrt" => ScoreTransform::Sqrt,
+            "rank" => ScoreTransform::Rank,
+            _ => {
+                eprintln!("Unknown score transform: {} (available: none, sqrt, rank)", value);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+// Fields compared by the overlap adjustment, see matcher::overlap_text
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum OverlapField {
+    Title,    // Title only (default)
+    Author,   // Author only
+    Combined, // Author, title and location in the combined order
+}
+
+impl Display for OverlapField {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            OverlapField::Title => write!(f, "title"),
+            OverlapField::Author => write!(f, "author"),
+            OverlapField::Combined => write!(f, "combined"),
+        }
+    }
+}
+
+impl OverlapField {
+    fn parse(value: &str) -> OverlapField {
+        match value {
+            "title" => OverlapField::Title,
+            "author" => OverlapField::Author,
+            "combined" => OverlapField::Combined,
+            _ => {
+                eprintln!("Unknown overlap field: {} (available: title, author, combined)", value);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+// Tokens for the year part, see tokenizer::tokenize_year
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum YearGranularity {
+    Year,   // Only the full year (default)
+    Decade, // The full year and its decade ("1948" and "194x")
+}
+
+impl Display for YearGranularity {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            YearGranularity::Year => write!(f, "year"),
+            YearGranularity::Decade => write!(f, "decade"),
+        }
+    }
+}
+
+impl YearGranularity {
+    fn parse(value: &str) -> YearGranularity {
+        match value {
+            "year" => YearGranularity::Year,
+            "decade" => YearGranularity::Decade,
+            _ => {
+                eprintln!("Unknown year granularity: {} (available: year, decade)", value);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+// Which reports are written by match-json-zip, see report::output_report
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ReportKind {
+    Both, // -report.json and -report.md (default)
+    Json,
+    Markdown,
+    None,
+}
+
+impl Display for ReportKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ReportKind::Both => write!(f, "both"),
+            ReportKind::Json => write!(f, "json"),
+            ReportKind::Markdown => write!(f, "markdown"),
+            ReportKind::None => write!(f, "none"),
+        }
+    }
+}
+
+impl ReportKind {
+    fn parse(value: &str) -> ReportKind {
+        match value {
+            "both" => ReportKind::Both,
+            "json" => ReportKind::Json,
+            "markdown" => ReportKind::Markdown,
+            "none" => ReportKind::None,
+            _ => {
+                eprintln!("Unknown report: {} (available: json, markdown, both, none)", value);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    pub fn json(&self) -> bool {
+        matches!(self, ReportKind::Both | ReportKind::Json)
+    }
+
+    pub fn markdown(&self) -> bool {
+        matches!(self, ReportKind::Both | ReportKind::Markdown)
+    }
+}
+
+// Where an option was set, see ConfigOptions::option_sources
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum OptionSource {
+    CommandLine, // -O on the command line
+    ConfigFile,  // -C config file
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ConfigOptions {
     pub force_year: bool,
@@ -108,42 +395,213 @@ pub struct ConfigOptions {
     pub year_tolerance: Option<i32>,
     // When using year_tolerance, the year difference causes a penalty of year_difference * M (M defaults to 0.25)
     pub year_tolerance_penalty: f32,
+    // Share of the similarity taken off source records with an author when the card has none (0.0 is no penalty)
+    pub empty_author_penalty: f32,
     // Use "year_of_publication_compact_string" field to parse year ranges. This field is only available in JSON schema version 2.
     pub parse_year_ranges: bool,
     // When parsing multiple years from "year_of_publication_compact_string", use the first (earliest) year only
     pub use_first_parsed_year: bool,
+    // Elastic field holding the last year of records covering a span of years. Only used when building source data.
+    pub last_year_field: Option<String>,
+    // Elastic field holding the publication type of the records. Only used when building source data.
+    pub publication_type_field: Option<String>,
+    // Zero the similarity of candidates whose publication type differs from the card's publication type
+    pub require_type_match: bool,
     pub include_source_data: bool,
+    // Text output with the candidates of each record as a table with aligned columns
+    pub text_table: bool,
     pub similarity_threshold: Option<f32>,
+    // Minimum cosine similarity of the title and author parts alone, candidates below are dropped
+    pub title_threshold: Option<f32>,
+    pub author_threshold: Option<f32>,
+    // Transform of the similarity before the thresholds, the transformed similarity is also what is written to the output
+    pub score_transform: ScoreTransform,
     pub z_threshold: Option<f32>,
+    // Spread of the similarities in the candidate pool (standard deviation, or MAD with zscore-method=robust)
+    // below which z_threshold is not applied to a record
+    pub adaptive_z: Option<f32>,
+    // Smallest candidate pool with z-scores, smaller pools get z-scores of 0 and no z_threshold
+    pub min_pool_for_z: Option<i32>,
+    // Mean/standard deviation or median/MAD z-scores
+    pub zscore_method: ZscoreMethod,
     pub min_single_similarity: Option<f32>,
     pub min_multiple_similarity: Option<f32>,
+    // Multiple matches where the similarity gap between the top two is below this are Ambiguous
+    pub ambiguous_spread: Option<f32>,
+    // Single matches where the title of the candidate equals the title of the card (after normalization) are Exact
+    pub detect_exact: bool,
+    // Exit with FAIL_EXIT_CODE after matching if the no match percentage is above this
+    pub fail_if_nomatch_above: Option<f32>,
+    // No summary line on stderr after matching
+    pub quiet: bool,
     pub weights_file: Option<String>,
+    // Weights from the "weights" object of the config file, replaced by -O weights-file on the command line
+    pub config_weights: Option<FxHashMap<String, f32>>,
+    // JSON file with an array of named weights, each record is matched with all of them and the best is kept
+    pub weights_profiles: Option<String>,
+    // Per-part weights given inline with -O weight-<part>=N, applied on top of the weights file or default weights
+    pub inline_weights: FxHashMap<String, f32>,
+    // Match only on the combined "all" part (all other part weights set to 0.0)
+    pub use_combined_only: bool,
+    // Match records with fewer than this many of author, title, location and year on the combined "all" part alone
+    pub all_fallback: Option<i32>,
+    // Scale each part by its length relative to the other parts before averaging (pivoted length normalization)
+    pub length_normalize: bool,
     pub extended_output: bool,
+    // List the tokens contributing most to the similarity of each candidate in extended output
+    pub explain: bool,
+    // Add the combined ("all") text of the input record, as it is tokenized, to extended output
+    pub output_combined_text: bool,
+    // Add a worksheet with the statistics of the report before the data in the xlsx output
+    pub xlsx_summary_sheet: bool,
+    // Write the JSON output as an object with the run metadata and the rows as "records"
+    pub json_envelope: bool,
+    // Add the cosine similarity of each part (title, author, location, year) to extended output
+    pub output_part_similarities: bool,
+    // Add a 0-100 confidence of each candidate to extended output, a logistic of its similarity, margin and z-score
+    pub confidence_score: bool,
+    // Similarity that gives confidence 50 (with no margin or z-score weight)
+    pub confidence_midpoint: f32,
+    // How fast the confidence goes from 0 to 100 around the midpoint
+    pub confidence_steepness: f32,
+    // Weight of the margin to the next candidate, added to the similarity before the logistic
+    pub confidence_margin_weight: f32,
+    // Weight of the z-score, added after the steepness is applied
+    pub confidence_z_weight: f32,
+    // Merge the results of all editions of a card into one output record
+    pub merge_editions: bool,
+    // Keep at most this many candidate rows per card in the output, over all its editions
+    pub max_rows_per_card: Option<i32>,
+    // Write the output in batches of this many records while matching (csv and json files only)
+    pub output_batch_size: Option<i32>,
+    // Add the edition to the card (and card_ID) in the output, "003_00153.json#1", so that each edition has its own card
+    pub edition_in_card_id: bool,
+    // Previous JSON output of match-json-zip, whose records are kept and not matched again
+    pub resume_from: Option<String>,
+    // Round similarities and other scores in the output to this many decimals (full precision if not set)
+    pub output_precision: Option<i32>,
+    // Which of the JSON and markdown reports to write
+    pub report: ReportKind,
+    // Stop fetching from Elastic after this many records (for quick test builds)
+    pub fetch_limit: Option<i32>,
+    // Query-DSL fragment combined with the source match when fetching from Elastic
+    pub es_filter: Option<serde_json::Value>,
+    // Elastic field with the record id (default "id" in _source, "_id" for the meta id of the hit)
+    pub id_field: Option<String>,
+    // Append new records to the existing dataset vectors instead of rebuilding (build-dataset-vectors)
+    pub append: bool,
+    // Elastic field with the date a record was added, used by append to only fetch newer records
+    pub date_field: Option<String>,
+    // Allow vocab, vectors and source data built from different sources
+    pub skip_source_check: bool,
+    // Read the dataset vectors in chunks instead of loading all documents into memory at once
+    pub lazy_vectors: bool,
+    // Always calculate the weighted dataset vectors instead of loading them from the cache next to the vectors file
+    pub no_vector_cache: bool,
+    // Order of the fields in the combined "all" part, must be the same when building and matching
+    pub combined_order: Vec<String>,
+    // Tokenizer used for all parts, must be the same when building and matching
+    pub tokenizer: TokenizerMode,
+    // Tokens for the year part, must be the same when building and matching
+    pub year_granularity: YearGranularity,
+    // Take the 4-digit year out of years like "c. 1948", "1948?" and "[1948]", must be the same when building and matching
+    pub clean_year: bool,
+    // Start and end symbols around the text before making n-grams, -O sentinels=off turns them off.
+    // Must be the same when building and matching
+    pub sentinels: bool,
+    // Runs of digits as tokens of their own in addition to the n-grams, must be the same when building and matching
+    pub numeric_tokens: bool,
+    // Calculate the idf of each part from the number of documents with a value in that part, must be the same when building and matching
+    pub part_idf: bool,
+    // Name order of the authors in the author part, must be the same when building and matching
+    pub author_normalize: AuthorNormalize,
+    // Address for the serve command, host:port or unix:/path/to/socket
+    pub listen: Option<String>,
+    // Trim whitespace and trailing slashes from source ids in the output (and matched_ID derived from them)
+    pub normalize_output_ids: bool,
+    // Also lowercase the output ids, -O normalize-output-ids=lowercase
+    pub lowercase_output_ids: bool,
+    // Separator before the last part of the source id that is written as matched_ID, "/" by default
+    pub matched_id_sep: String,
+    // Number of ranked candidates per record to write to the -candidates.ndjson sidecar file
+    pub candidates_dump: Option<i32>,
+    // Log the candidates removed by each filter for one in N records (all records with -v)
+    pub trace_filtering: Option<i32>,
+    // Number of source records matched against the dataset by the self-check command
+    pub self_check_sample: Option<i32>,
+    // NDJSON file for the weighted average vector of each input record, with the tokens from the vocab
+    pub dump_query_vectors: Option<String>,
     pub add_author_to_title: bool,
+    // Records with an empty title are not matched (MatchStat::SkippedEmpty)
+    pub skip_empty_title: bool,
     pub add_serial_to_title: bool,
     pub add_edition_to_title: bool,
     // Overlap adjustment, the value is the minimum number of characters that must overlap
     pub overlap_adjustment: Option<i32>,
+    // Titles shorter than this (in characters) are not overlap adjusted, 0 adjusts all titles
+    pub overlap_min_title_len: i32,
+    // Cut both titles to this many characters before calculating the overlap, bounds the cost for very long titles
+    pub overlap_max_len: Option<i32>,
+    // Fields compared by the overlap adjustment
+    pub overlap_field: OverlapField,
     // Jaro-Winkler adjustment, multiplier to similarity for Jaro-Winkler similarity between titles
     pub jaro_winkler_adjustment: bool,
     // Jaro-Winkler author adjustment, multiplier to similarity for Jaro-Winkler similarity between authors
     pub jaro_winkler_author_adjustment: bool,
     // Jaro-Winkler truncate length to dataset source for title,author or all (both)
     pub jaro_winkler_truncate: JaroTruncate,
-    // JSON schema version, version 2 is explicit, all others are version 1
+    // JSON schema version, versions 2 and 3 are explicit, JSON_SCHEMA_VERSION_AUTO (auto) detects it per file, all others are version 1
     pub json_schema_version: i32,
+    // Input files with the same card name
+    pub dedup_input: DedupInput,
     // Output source name (overriding the source parameter which is used for loading from the index). Only used when building vocab, vectors and source data.
     pub output_source_name: String,
     // Base directory for vocab/dataset-vectors/source-data, defaults to "data"
     pub dataset_dir: String,
     // List of files containing IDs (one per line) to exclude from matching
     pub exclude_files: Vec<String>,
+    // Exclude source records where any of these predicates on a source data field is true, e.g. year<1800
+    pub exclude_where: Vec<source_data::FieldPredicate>,
     // List of IDs to exclude from matching, populated from exclude_files
     pub excluded_ids: Vec<String>,
+    // External IDs to exclude, populated from the first column of tab separated lines in exclude_files,
+    // translated to internal IDs by matching exclude_id_field in the source data
+    pub excluded_external_ids: Vec<String>,
+    // Source data field that the external IDs are matched against (see source_data::SourceRecord::field_value)
+    pub exclude_id_field: String,
     // Same as exclude_files, but for input data only
     pub input_exclude_files: Vec<String>,
     // Same as excluded_ids, but for input data only
     pub input_excluded_ids: Vec<String>,
+    // JSON file with place names, canonical name => list of variants, used to normalize the location part before tokenization
+    pub location_gazetteer: Option<String>,
+    // Normalized variant => normalized canonical name, populated from location_gazetteer
+    pub location_gazetteer_map: FxHashMap<String, String>,
+    // CSV file with card_prefix,source_id,multiplier rows, used to re-rank candidates after the adjustments
+    pub rerank_table: Option<String>,
+    // Source id => (card prefix, multiplier), populated from rerank_table
+    pub rerank_entries: FxHashMap<String, Vec<(String, f32)>>,
+    // Tab separated file with card and correct source id, used to evaluate the matches in the report
+    pub gold_file: Option<String>,
+    // Card (without .json) => correct source id ("" if no match is expected), populated from gold_file
+    pub gold_ids: FxHashMap<String, String>,
+    // Tab separated file with card and expected source id, the rank of that id is added to extended output
+    pub known_ids_file: Option<String>,
+    // Card (without .json) => expected source id, populated from known_ids_file
+    pub known_ids: FxHashMap<String, String>,
+    // Text file with the prompt used to create the input data, used in the report instead of a .prompt file in the input
+    pub prompt_file: Option<String>,
+    // Text file with leading articles (one per line) to remove from the start of titles before tokenization
+    pub strip_leading_articles: Option<String>,
+    // Normalized articles, populated from strip_leading_articles
+    pub leading_articles: FxHashSet<String>,
+    // JSON file with acronyms and their expansions, expanded in titles before tokenization
+    pub acronym_file: Option<String>,
+    // Acronym without punctuation => expansion, populated from acronym_file
+    pub acronyms: FxHashMap<String, String>,
+    // Option name as given with -O (e.g. "similarity-threshold") => where it was set,
+    // options left at their defaults are not present. The command line overrides the config file.
+    pub option_sources: FxHashMap<String, OptionSource>,
 }
 
 impl ConfigOptions {
@@ -163,6 +621,74 @@ impl ConfigOptions {
         s.split('=').collect::<Vec<&str>>()[0]
     }
 
+    // Part name from an inline weight option, "weight-title=1.8" gives "title". Only the parts of WEIGHT_PARTS
+    // have weights, any other name (such as a misspelled part) is an error.
+    fn weight_part_name(s: &str) -> String {
+        let part = ConfigOptions::option_name(s).trim_start_matches("weight-");
+        if !WEIGHT_PARTS.contains(&part) {
+            eprintln!("Unknown part {} in -O {}, valid parts are: {}", part, ConfigOptions::option_name(s), WEIGHT_PARTS.join(", "));
+            std::process::exit(1);
+        }
+        part.to_string()
+    }
+
+    // Special for json-schema-version, which is either a number or "auto"
+    fn json_schema_version_option(s: &str) -> i32 {
+        if ConfigOptions::string_option(s) == "auto" {
+            JSON_SCHEMA_VERSION_AUTO
+        } else {
+            ConfigOptions::i32_option(s)
+        }
+    }
+
+    fn exclude_where_option(value: &str) -> source_data::FieldPredicate {
+        source_data::FieldPredicate::parse(value).unwrap_or_else(|e| {
+            eprintln!("Invalid exclude-where: {}", e);
+            std::process::exit(1);
+        })
+    }
+
+    // A JSON object with an Elastic query, e.g. {"range":{"first_year":{"gte":1900}}}
+    fn es_filter_option(value: &str) -> serde_json::Value {
+        match serde_json::from_str::<serde_json::Value>(value) {
+            Ok(query) if query.is_object() => query,
+            Ok(_) => {
+                eprintln!("Invalid es-filter: {} (must be a JSON object with an Elasticsearch query)", value);
+                std::process::exit(1);
+            },
+            Err(e) => {
+                eprintln!("Invalid es-filter: {} ({})", value, e);
+                std::process::exit(1);
+            },
+        }
+    }
+
+    // Comma separated list with each of author, title, location and year exactly once
+    fn combined_order_option(value: &str) -> Vec<String> {
+        let order = value.split(',').map(|field| field.trim().to_string()).collect::<Vec<String>>();
+        let mut sorted_order = order.clone();
+        sorted_order.sort();
+        let mut sorted_default = elastic::DEFAULT_COMBINED_ORDER.to_vec();
+        sorted_default.sort();
+        if sorted_order != sorted_default {
+            eprintln!("Invalid combined-order: {} (use each of author, title, location and year once, e.g. title,author,location,year)", value);
+            std::process::exit(1);
+        }
+        order
+    }
+
+    // "on" (default) or "off"
+    fn sentinels_option(value: &str) -> bool {
+        match value {
+            "on" => true,
+            "off" => false,
+            _ => {
+                eprintln!("Unknown value for sentinels: {} (available: on, off)", value);
+                std::process::exit(1);
+            }
+        }
+    }
+
     // Special for JaroTruncate
     fn jaro_truncate_option(s: &str) -> JaroTruncate {
         match s.split('=').collect::<Vec<&str>>()[1] {
@@ -174,12 +700,14 @@ impl ConfigOptions {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OutputFormat {
     Text,
     Json,
     CSV,
     XLSX,
+    // Tab separated card_ID and source id of the single matches only
+    Crosswalk,
 }
 
 impl From<String> for OutputFormat {
@@ -189,6 +717,7 @@ impl From<String> for OutputFormat {
             "json" => OutputFormat::Json,
             "csv" => OutputFormat::CSV,
             "xlsx" => OutputFormat::XLSX,
+            "crosswalk" => OutputFormat::Crosswalk,
             _ => OutputFormat::Text,
         }
     }
@@ -201,6 +730,7 @@ impl Display for OutputFormat {
             OutputFormat::Json => write!(f, "json"),
             OutputFormat::CSV => write!(f, "csv"),
             OutputFormat::XLSX => write!(f, "xlsx"),
+            OutputFormat::Crosswalk => write!(f, "crosswalk"),
         }
     }
 }
@@ -211,6 +741,14 @@ impl Config {
         let options = parse_options(&args);
         parse_command(&args, options)
     }
+
+    // The config of a command line given without the program name, for tests
+    #[cfg(test)]
+    pub fn from_args(args: &[&str]) -> Config {
+        let args = Args::parse_from(std::iter::once("matching-tool").chain(args.iter().copied()));
+        let options = parse_options(&args);
+        parse_command(&args, options)
+    }
 }
 
 fn parse_options(args: &Args) -> ConfigOptions {
@@ -218,29 +756,113 @@ fn parse_options(args: &Args) -> ConfigOptions {
         force_year: false,
         year_tolerance: None,
         year_tolerance_penalty: DEFAULT_YEAR_TOLERANCE_PENALTY,
+        empty_author_penalty: 0.0,
         parse_year_ranges: false,
         use_first_parsed_year: false,
+        last_year_field: None,
+        publication_type_field: None,
+        require_type_match: false,
         include_source_data: false,
+        text_table: false,
         similarity_threshold: None,
+        title_threshold: None,
+        author_threshold: None,
+        score_transform: ScoreTransform::None,
         z_threshold: None,
+        adaptive_z: None,
+        min_pool_for_z: None,
+        zscore_method: ZscoreMethod::Mean,
         min_single_similarity: None,
         min_multiple_similarity: None,
+        ambiguous_spread: None,
+        detect_exact: false,
+        fail_if_nomatch_above: None,
+        quiet: false,
         weights_file: None,
+        config_weights: None,
+        weights_profiles: None,
+        inline_weights: FxHashMap::default(),
+        use_combined_only: false,
+        all_fallback: None,
+        length_normalize: false,
         extended_output: false,
+        explain: false,
+        output_combined_text: false,
+        xlsx_summary_sheet: false,
+        json_envelope: false,
+        output_part_similarities: false,
+        confidence_score: false,
+        confidence_midpoint: 0.5,
+        confidence_steepness: 10.0,
+        confidence_margin_weight: 0.0,
+        confidence_z_weight: 0.0,
+        merge_editions: false,
+        max_rows_per_card: None,
+        output_batch_size: None,
+        edition_in_card_id: false,
+        resume_from: None,
+        output_precision: None,
+        report: ReportKind::Both,
+        candidates_dump: None,
+        trace_filtering: None,
+        dump_query_vectors: None,
+        self_check_sample: None,
+        normalize_output_ids: false,
+        lowercase_output_ids: false,
+        matched_id_sep: "/".to_string(),
+        listen: None,
+        tokenizer: TokenizerMode::Ngram,
+        year_granularity: YearGranularity::Year,
+        clean_year: false,
+        sentinels: true,
+        numeric_tokens: false,
+        part_idf: false,
+        author_normalize: AuthorNormalize::None,
+        combined_order: elastic::DEFAULT_COMBINED_ORDER.iter().map(|field| field.to_string()).collect(),
+        skip_source_check: false,
+        lazy_vectors: false,
+        no_vector_cache: false,
+        fetch_limit: None,
+        es_filter: None,
+        id_field: None,
+        append: false,
+        date_field: None,
         add_author_to_title: false,
+        skip_empty_title: false,
         add_serial_to_title: false,
         add_edition_to_title: false,
         overlap_adjustment: None,
+        overlap_min_title_len: 0,
+        overlap_max_len: None,
+        overlap_field: OverlapField::Title,
         jaro_winkler_adjustment: false,
         jaro_winkler_author_adjustment: false,
         jaro_winkler_truncate: JaroTruncate::None,
         json_schema_version: 1,
+        dedup_input: DedupInput::Warn,
         output_source_name: args.source.clone().unwrap_or_default(),
         dataset_dir: "data".to_string(),
         exclude_files: vec![],
+        exclude_where: vec![],
         excluded_ids: vec![],
+        excluded_external_ids: vec![],
+        exclude_id_field: "matched_id".to_string(),
         input_exclude_files: vec![],
         input_excluded_ids: vec![],
+        location_gazetteer: None,
+        location_gazetteer_map: FxHashMap::default(),
+        rerank_table: None,
+        rerank_entries: FxHashMap::default(),
+        gold_file: None,
+        gold_ids: FxHashMap::default(),
+        known_ids_file: None,
+        known_ids: FxHashMap::default(),
+        prompt_file: None,
+        strip_leading_articles: None,
+        leading_articles: FxHashSet::default(),
+        acronym_file: None,
+        acronyms: FxHashMap::default(),
+        option_sources: FxHashMap::default(),
     };
 
     if let Some(config_file) = &args.config_file {
@@ -249,6 +871,7 @@ fn parse_options(args: &Args) -> ConfigOptions {
     }
 
     for option in args.options.clone() {
+        options.option_sources.insert(ConfigOptions::option_name(&option).to_string(), OptionSource::CommandLine);
         match ConfigOptions::option_name(&option) {
             "force-year" => options.force_year = true,
             "year-tolerance" => {
@@ -259,17 +882,51 @@ fn parse_options(args: &Args) -> ConfigOptions {
                 let value = ConfigOptions::f32_option(&option);
                 options.year_tolerance_penalty = value;
             },
+            "empty-author-penalty" => {
+                let value = ConfigOptions::f32_option(&option);
+                if !(0.0..=1.0).contains(&value) {
+                    eprintln!("Invalid empty-author-penalty: {} (must be between 0 and 1)", value);
+                    std::process::exit(1);
+                }
+                options.empty_author_penalty = value;
+            },
             "parse-year-ranges" => options.parse_year_ranges = true,
             "use-first-parsed-year" => options.use_first_parsed_year = true,
+            "publication-type-field" => {
+                let value = ConfigOptions::string_option(&option);
+                options.publication_type_field = Some(value);
+            },
+            "require-type-match" => options.require_type_match = true,
+            "last-year-field" => {
+                let value = ConfigOptions::string_option(&option);
+                options.last_year_field = Some(value);
+            },
             "include-source-data" => options.include_source_data = true,
+            "text-table" => options.text_table = true,
             "similarity-threshold" => {
                 let value = ConfigOptions::f32_option(&option);
                 options.similarity_threshold = Some(value);
             },
+            "title-threshold" => {
+                let value = ConfigOptions::f32_option(&option);
+                options.title_threshold = Some(value);
+            },
+            "author-threshold" => {
+                let value = ConfigOptions::f32_option(&option);
+                options.author_threshold = Some(value);
+            },
             "z-threshold" => {
                 let value = ConfigOptions::f32_option(&option);
                 options.z_threshold = Some(value);
             },
+            "adaptive-z" => {
+                let value = ConfigOptions::f32_option(&option);
+                options.adaptive_z = Some(value);
+            },
+            "min-pool-for-z" => {
+                let value = ConfigOptions::i32_option(&option);
+                options.min_pool_for_z = Some(value);
+            },
             "min-single-similarity" => {
                 let value = ConfigOptions::f32_option(&option);
                 options.min_single_similarity = Some(value);
@@ -278,26 +935,188 @@ fn parse_options(args: &Args) -> ConfigOptions {
                 let value = ConfigOptions::f32_option(&option);
                 options.min_multiple_similarity = Some(value);
             },
+            "quiet" => options.quiet = true,
+            "fail-if-nomatch-above" => {
+                let value = ConfigOptions::f32_option(&option);
+                options.fail_if_nomatch_above = Some(value);
+            },
+            "detect-exact" => options.detect_exact = true,
+            "ambiguous-spread" => {
+                let value = ConfigOptions::f32_option(&option);
+                options.ambiguous_spread = Some(value);
+            },
             "weights-file" => {
                 let value = ConfigOptions::string_option(&option);
                 options.weights_file = Some(value);
+                options.config_weights = None;
+            },
+            "weights-profiles" => {
+                let value = ConfigOptions::string_option(&option);
+                options.weights_profiles = Some(value);
+            },
+            name if name.starts_with("weight-") => { // Repeatable option, one per part
+                let part = ConfigOptions::weight_part_name(&option);
+                let value = ConfigOptions::f32_option(&option);
+                options.inline_weights.insert(part, value);
             },
             "extended-output" => options.extended_output = true,
+            "explain" => options.explain = true,
+            "output-combined-text" => options.output_combined_text = true,
+            "xlsx-summary-sheet" => options.xlsx_summary_sheet = true,
+            "json-envelope" => options.json_envelope = true,
+            "output-part-similarities" => options.output_part_similarities = true,
+            "confidence-score" => options.confidence_score = true,
+            "confidence-midpoint" => {
+                options.confidence_midpoint = ConfigOptions::f32_option(&option);
+            },
+            "confidence-steepness" => {
+                let value = ConfigOptions::f32_option(&option);
+                if value <= 0.0 {
+                    eprintln!("Invalid confidence-steepness: {} (must be above 0)", value);
+                    std::process::exit(1);
+                }
+                options.confidence_steepness = value;
+            },
+            "confidence-margin-weight" => {
+                options.confidence_margin_weight = ConfigOptions::f32_option(&option);
+            },
+            "confidence-z-weight" => {
+                options.confidence_z_weight = ConfigOptions::f32_option(&option);
+            },
+            "merge-editions" => options.merge_editions = true,
+            "report" => {
+                options.report = ReportKind::parse(&ConfigOptions::string_option(&option));
+            },
+            "max-rows-per-card" => {
+                let value = ConfigOptions::i32_option(&option);
+                options.max_rows_per_card = Some(value);
+            },
+            "output-batch-size" => {
+                let value = ConfigOptions::i32_option(&option);
+                options.output_batch_size = Some(value);
+            },
+            "edition-in-card-id" => options.edition_in_card_id = true,
+            "resume-from" => {
+                let value = ConfigOptions::string_option(&option);
+                options.resume_from = Some(value);
+            },
+            "output-precision" => {
+                let value = ConfigOptions::i32_option(&option);
+                options.output_precision = Some(value);
+            },
+            "matched-id-sep" => {
+                options.matched_id_sep = ConfigOptions::string_option(&option);
+            },
+            "normalize-output-ids" => {
+                options.normalize_output_ids = true;
+                match option.split_once('=').map(|(_, value)| value) {
+                    None => {},
+                    Some("lowercase") => options.lowercase_output_ids = true,
+                    Some(value) => {
+                        eprintln!("Unknown value for normalize-output-ids: {} (available: lowercase)", value);
+                        std::process::exit(1);
+                    }
+                }
+            },
+            "skip-source-check" => options.skip_source_check = true,
+            "lazy-vectors" => options.lazy_vectors = true,
+            "no-vector-cache" => options.no_vector_cache = true,
+            "fetch-limit" => {
+                let value = ConfigOptions::i32_option(&option);
+                options.fetch_limit = Some(value);
+            },
+            "es-filter" => {
+                let value = ConfigOptions::string_option(&option);
+                options.es_filter = Some(ConfigOptions::es_filter_option(&value));
+            },
+            "id-field" => {
+                let value = ConfigOptions::string_option(&option);
+                options.id_field = Some(value);
+            },
+            "append" => options.append = true,
+            "date-field" => {
+                let value = ConfigOptions::string_option(&option);
+                options.date_field = Some(value);
+            },
+            "combined-order" => {
+                options.combined_order = ConfigOptions::combined_order_option(&ConfigOptions::string_option(&option));
+            },
+            "score-transform" => {
+                options.score_transform = ScoreTransform::parse(&ConfigOptions::string_option(&option));
+            },
+            "zscore-method" => {
+                options.zscore_method = ZscoreMethod::parse(&ConfigOptions::string_option(&option));
+            },
+            "tokenizer" => {
+                options.tokenizer = TokenizerMode::parse(&ConfigOptions::string_option(&option));
+            },
+            "year-granularity" => {
+                options.year_granularity = YearGranularity::parse(&ConfigOptions::string_option(&option));
+            },
+            "numeric-tokens" => options.numeric_tokens = true,
+            "clean-year" => options.clean_year = true,
+            "part-idf" => options.part_idf = true,
+            "author-normalize" => {
+                options.author_normalize = AuthorNormalize::parse(&ConfigOptions::string_option(&option));
+            },
+            "sentinels" => {
+                options.sentinels = ConfigOptions::sentinels_option(&ConfigOptions::string_option(&option));
+            },
+            "listen" => {
+                let value = ConfigOptions::string_option(&option);
+                options.listen = Some(value);
+            },
+            "candidates-dump" => {
+                let value = ConfigOptions::i32_option(&option);
+                options.candidates_dump = Some(value);
+            },
+            "trace-filtering" => {
+                let value = ConfigOptions::i32_option(&option);
+                options.trace_filtering = Some(value);
+            },
+            "self-check-sample" => {
+                let value = ConfigOptions::i32_option(&option);
+                options.self_check_sample = Some(value);
+            },
+            "dump-query-vectors" => {
+                let value = ConfigOptions::string_option(&option);
+                options.dump_query_vectors = Some(value);
+            },
+            "use-combined-only" => options.use_combined_only = true,
+            "all-fallback" => {
+                let value = ConfigOptions::i32_option(&option);
+                options.all_fallback = Some(value);
+            },
+            "length-normalize" => options.length_normalize = true,
             "add-author-to-title" => options.add_author_to_title = true,
+            "skip-empty-title" => options.skip_empty_title = true,
             "add-serial-to-title" => options.add_serial_to_title = true,
             "add-edition-to-title" => options.add_edition_to_title = true,
             "overlap-adjustment" => {
                 let value = ConfigOptions::i32_option(&option);
                 options.overlap_adjustment = Some(value);
             },
+            "overlap-field" => {
+                options.overlap_field = OverlapField::parse(&ConfigOptions::string_option(&option));
+            },
+            "overlap-max-len" => {
+                let value = ConfigOptions::i32_option(&option);
+                options.overlap_max_len = Some(value);
+            },
+            "overlap-min-title-len" => {
+                let value = ConfigOptions::i32_option(&option);
+                options.overlap_min_title_len = value;
+            },
             "jaro-winkler-adjustment" => options.jaro_winkler_adjustment = true,
             "jaro-winkler-author-adjustment" => options.jaro_winkler_author_adjustment = true,
             "jaro-winkler-truncate" => {
                 options.jaro_winkler_truncate = ConfigOptions::jaro_truncate_option(&option);
             },
             "json-schema-version" => {
-                let value = ConfigOptions::i32_option(&option);
-                options.json_schema_version = value;
+                options.json_schema_version = ConfigOptions::json_schema_version_option(&option);
+            },
+            "dedup-input" => {
+                options.dedup_input = DedupInput::parse(&ConfigOptions::string_option(&option));
             },
             "output-source-name" => {
                 let value = ConfigOptions::string_option(&option);
@@ -311,18 +1130,64 @@ fn parse_options(args: &Args) -> ConfigOptions {
                 let value = ConfigOptions::string_option(&option);
                 options.exclude_files.push(value);
             },
+            "exclude-where" => { // Repeatable option
+                let value = ConfigOptions::string_option(&option);
+                options.exclude_where.push(ConfigOptions::exclude_where_option(&value));
+            },
+            "exclude-id-field" => {
+                let value = ConfigOptions::string_option(&option);
+                options.exclude_id_field = value;
+            },
             "input-exclude-file" => { // Repeatable option, similar to exclude-file but for input data only
                 let value = ConfigOptions::string_option(&option);
                 options.input_exclude_files.push(value);
             },
+            "location-gazetteer" => {
+                let value = ConfigOptions::string_option(&option);
+                options.location_gazetteer = Some(value);
+            },
+            "rerank-table" => {
+                let value = ConfigOptions::string_option(&option);
+                options.rerank_table = Some(value);
+            },
+            "gold-file" => {
+                let value = ConfigOptions::string_option(&option);
+                options.gold_file = Some(value);
+            },
+            "known-ids-file" => {
+                let value = ConfigOptions::string_option(&option);
+                options.known_ids_file = Some(value);
+            },
+            "prompt-file" => {
+                let value = ConfigOptions::string_option(&option);
+                options.prompt_file = Some(value);
+            },
+            "strip-leading-articles" => {
+                let value = ConfigOptions::string_option(&option);
+                options.strip_leading_articles = Some(value);
+            },
+            "acronym-file" => {
+                let value = ConfigOptions::string_option(&option);
+                options.acronym_file = Some(value);
+            },
             _ => {
                 eprintln!("Unknown option: {}", option);
                 std::process::exit(1);
             }
         }
     }
+    if options.matched_id_sep.is_empty() {
+        eprintln!("Option matched-id-sep can not be empty");
+        std::process::exit(1);
+    }
     populate_excluded_ids(&mut options);
     populate_excluded_input_ids(&mut options);
+    populate_location_gazetteer(&mut options);
+    populate_leading_articles(&mut options);
+    populate_acronyms(&mut options);
+    populate_rerank_entries(&mut options);
+    populate_gold_ids(&mut options);
+    populate_known_ids(&mut options);
     options
 }
 
@@ -332,8 +1197,13 @@ fn parse_command(args: &Args, options: ConfigOptions) -> Config {
         "build-vocab" => parse_command_build_vocab(args, options),
         "build-dataset-vectors" => parse_command_build_dataset_vectors(args, options),
         "match-json-zip" => parse_command_match_json_zip(args, options),
+        "match-single-json" => parse_command_match_single_json(args, options),
+        "serve" => parse_command_serve(args, options),
         "build-source-data" => parse_command_build_source_data(args, options),
+        "build-all" => parse_command_build_all(args, options),
         "dump-source-data" => parse_command_dump_source_data(args, options),
+        "inspect-vocab" => parse_command_inspect_vocab(args, options),
+        "self-check" => parse_command_self_check(args, options),
         _ => {
             eprintln!("Unknown command: {}", command);
             std::process::exit(1);
@@ -355,7 +1225,10 @@ fn parse_command_build_vocab(args: &Args, options: ConfigOptions) -> Config {
         vocab_file,
         dataset_vector_file: "".to_string(),
         source_data_file: "".to_string(),
+        dataset_vector_files: vec![],
+        source_data_files: vec![],
         input: "".to_string(),
+        inputs: vec![],
         output: Output::Stdout,
         output_format: OutputFormat::Text,
         verbose,
@@ -382,7 +1255,10 @@ fn parse_command_build_dataset_vectors(args: &Args, options: ConfigOptions) -> C
         vocab_file,
         dataset_vector_file,
         source_data_file,
+        dataset_vector_files: vec![],
+        source_data_files: vec![],
         input: "".to_string(),
+        inputs: vec![],
         output: Output::Stdout,
         output_format: OutputFormat::Text,
         verbose,
@@ -402,28 +1278,35 @@ fn parse_command_match_json_zip(args: &Args, options: ConfigOptions) -> Config {
         eprintln!("Source name is required for match-single-zip command");
         std::process::exit(1);
     }
-    if args.input.is_none() {
+    if args.input.is_empty() {
         eprintln!("Input file is required for match-single-zip command");
         std::process::exit(1);
     }
     let source = args.source.clone().unwrap();
-    let input = args.input.clone().unwrap();
+    let inputs = args.input.clone();
+    let input = inputs[0].clone();
     let vocab_file = vocab_file_name(args, &options);
-    let dataset_vector_file = dataset_vector_file_name(args, &options);
-    let source_data_file = source_data_file_name(args, &options);
+    let dataset_vector_files = dataset_vector_file_names(args, &options);
+    let source_data_files = source_data_file_names(args, &options);
     let output = match &args.output {
         Some(filename) => Output::File(filename.clone()),
         None => Output::Stdout,
     };
-    let output_format = args.output_format.clone().unwrap_or("xlsx".to_string()).into();
+    let output_format = match &args.output_format {
+        Some(output_format) => output_format.clone().into(),
+        None => default_output_format(&output),
+    };
     let verbose = args.verbose;
     let mut config = Config {
         cmd: Cmd::MatchJsonZip,
         source,
         vocab_file,
-        dataset_vector_file,
-        source_data_file,
+        dataset_vector_file: dataset_vector_files[0].clone(),
+        source_data_file: source_data_files[0].clone(),
+        dataset_vector_files,
+        source_data_files,
         input,
+        inputs,
         output,
         output_format,
         verbose,
@@ -437,6 +1320,94 @@ fn parse_command_match_json_zip(args: &Args, options: ConfigOptions) -> Config {
     config
 }
 
+// Output format of match-json-zip when -F is not given: text on stdout,
+// and for a file the format of its extension. An unknown extension is an error.
+fn default_output_format(output: &Output) -> OutputFormat {
+    let Output::File(filename) = output else {
+        return OutputFormat::Text;
+    };
+    output_format_from_extension(filename).unwrap_or_else(|| {
+        eprintln!("Can not tell the output format from the extension of {} (known: .csv, .tsv, .json, .xlsx, .txt), use -F to give the format", filename);
+        std::process::exit(1);
+    })
+}
+
+// The csv output is tab separated, so .tsv is csv as well
+fn output_format_from_extension(filename: &str) -> Option<OutputFormat> {
+    let extension = std::path::Path::new(filename).extension()?.to_str()?;
+    match extension.to_lowercase().as_str() {
+        "csv" | "tsv" => Some(OutputFormat::CSV),
+        "json" => Some(OutputFormat::Json),
+        "xlsx" => Some(OutputFormat::XLSX),
+        "txt" => Some(OutputFormat::Text),
+        _ => None,
+    }
+}
+
+// match-single-json requires source, input is a single JSON file or stdin if not given (or "-")
+// output is always JSON on stdout
+fn parse_command_match_single_json(args: &Args, options: ConfigOptions) -> Config {
+    if args.source.is_none() {
+        eprintln!("Source name is required for match-single-json command");
+        std::process::exit(1);
+    }
+    let source = args.source.clone().unwrap();
+    if args.input.len() > 1 {
+        eprintln!("Only one input file is supported for match-single-json command");
+        std::process::exit(1);
+    }
+    let input = args.input.first().cloned().unwrap_or_default();
+    let vocab_file = vocab_file_name(args, &options);
+    let dataset_vector_files = dataset_vector_file_names(args, &options);
+    let source_data_files = source_data_file_names(args, &options);
+    let verbose = args.verbose;
+    let mut config = Config {
+        cmd: Cmd::MatchSingleJson,
+        source,
+        vocab_file,
+        dataset_vector_file: dataset_vector_files[0].clone(),
+        source_data_file: source_data_files[0].clone(),
+        dataset_vector_files,
+        source_data_files,
+        input,
+        inputs: vec![],
+        output: Output::Stdout,
+        output_format: OutputFormat::Json,
+        verbose,
+        options,
+        config_file: args.config_file.clone(),
+        default_args: FxHashMap::default(),
+    };
+    add_default_source_data_file(&mut config);
+    add_default_vocab_file(&mut config);
+    add_default_dataset_vector_file(&mut config);
+    config
+}
+
+// self-check requires source and matches records from the source data, no input
+fn parse_command_self_check(args: &Args, options: ConfigOptions) -> Config {
+    if args.source.is_none() {
+        eprintln!("Source name is required for self-check command");
+        std::process::exit(1);
+    }
+    let mut config = parse_command_match_single_json(args, options);
+    config.cmd = Cmd::SelfCheck;
+    config.input = "".to_string();
+    config.output_format = OutputFormat::Text;
+    config
+}
+
+// serve requires source, no input, replies are always JSON over the socket
+fn parse_command_serve(args: &Args, options: ConfigOptions) -> Config {
+    if args.source.is_none() {
+        eprintln!("Source name is required for serve command");
+        std::process::exit(1);
+    }
+    let mut config = parse_command_match_single_json(args, options);
+    config.cmd = Cmd::Serve;
+    config
+}
+
 fn parse_command_build_source_data(args: &Args, options: ConfigOptions) -> Config {
     if args.source.is_none() {
         eprintln!("Source name is required for build-source-data command");
@@ -451,7 +1422,10 @@ fn parse_command_build_source_data(args: &Args, options: ConfigOptions) -> Confi
         vocab_file: "".to_string(),
         dataset_vector_file: "".to_string(),
         source_data_file,
+        dataset_vector_files: vec![],
+        source_data_files: vec![],
         input: "".to_string(),
+        inputs: vec![],
         output: Output::Stdout,
         output_format: OutputFormat::Text,
         verbose,
@@ -462,6 +1436,41 @@ fn parse_command_build_source_data(args: &Args, options: ConfigOptions) -> Confi
     config
 }
 
+// build-all requires source, and writes all three files (vocab, dataset vectors, source data)
+// to their default names in dataset-dir unless given
+fn parse_command_build_all(args: &Args, options: ConfigOptions) -> Config {
+    if args.source.is_none() {
+        eprintln!("Source name is required for build-all command");
+        std::process::exit(1);
+    }
+    if options.append {
+        eprintln!("Option append is not supported with build-all, use build-dataset-vectors");
+        std::process::exit(1);
+    }
+    let source = args.source.clone().unwrap();
+    let vocab_file = vocab_file_name(args, &options);
+    let dataset_vector_file = dataset_vector_file_name(args, &options);
+    let source_data_file = source_data_file_name(args, &options);
+    let verbose = args.verbose;
+    Config {
+        cmd: Cmd::BuildAll,
+        source,
+        vocab_file,
+        dataset_vector_file,
+        source_data_file,
+        dataset_vector_files: vec![],
+        source_data_files: vec![],
+        input: "".to_string(),
+        inputs: vec![],
+        output: Output::Stdout,
+        output_format: OutputFormat::Text,
+        verbose,
+        options,
+        config_file: args.config_file.clone(),
+        default_args: FxHashMap::default(),
+    }
+}
+
 fn parse_command_dump_source_data(args: &Args, options: ConfigOptions) -> Config {
     if args.source.is_none() {
         eprintln!("Source name is required for dump-source-data command");
@@ -480,7 +1489,10 @@ fn parse_command_dump_source_data(args: &Args, options: ConfigOptions) -> Config
         vocab_file: "".to_string(),
         dataset_vector_file: "".to_string(),
         source_data_file,
+        dataset_vector_files: vec![],
+        source_data_files: vec![],
         input: "".to_string(),
+        inputs: vec![],
         output,
         output_format: OutputFormat::Json,
         verbose,
@@ -491,9 +1503,45 @@ fn parse_command_dump_source_data(args: &Args, options: ConfigOptions) -> Config
     config
 }
 
+// inspect-vocab reads the vocab file given with -V (or the default vocab file of the source)
+// and writes its statistics as text or JSON, to stdout unless given a file
+fn parse_command_inspect_vocab(args: &Args, options: ConfigOptions) -> Config {
+    if args.source.is_none() && args.vocab_file.is_none() {
+        eprintln!("Source name or vocab file is required for inspect-vocab command");
+        std::process::exit(1);
+    }
+    let output_format: OutputFormat = args.output_format.clone().unwrap_or("text".to_string()).into();
+    if !matches!(output_format, OutputFormat::Text | OutputFormat::Json) {
+        eprintln!("Output format {} is not supported for inspect-vocab command, use text or json", output_format);
+        std::process::exit(1);
+    }
+    let vocab_file = vocab_file_name(args, &options);
+    let output = match &args.output {
+        Some(filename) => Output::File(filename.clone()),
+        None => Output::Stdout,
+    };
+    Config {
+        cmd: Cmd::InspectVocab,
+        source: args.source.clone().unwrap_or_default(),
+        vocab_file,
+        dataset_vector_file: "".to_string(),
+        source_data_file: "".to_string(),
+        dataset_vector_files: vec![],
+        source_data_files: vec![],
+        input: "".to_string(),
+        inputs: vec![],
+        output,
+        output_format,
+        verbose: args.verbose,
+        options,
+        config_file: args.config_file.clone(),
+        default_args: FxHashMap::default(),
+    }
+}
+
 // If config.source_data_file is equal to the default value, add "source-data-file" to default_args
 fn add_default_source_data_file(config: &mut Config) {
-    if config.source_data_file == format!("{}/{}-source-data.bin", config.options.dataset_dir, config.options.output_source_name) {
+    if config.source_data_files.len() == 1 && config.source_data_file == format!("{}/{}-source-data.bin", config.options.dataset_dir, config.options.output_source_name) {
         config.default_args.insert("source-data-file".to_string(), true);
     }
 }
@@ -505,7 +1553,7 @@ fn add_default_vocab_file(config: &mut Config) {
 }
 
 fn add_default_dataset_vector_file(config: &mut Config) {
-    if config.dataset_vector_file == format!("{}/{}-dataset-vectors.bin", config.options.dataset_dir, config.options.output_source_name) {
+    if config.dataset_vector_files.len() == 1 && config.dataset_vector_file == format!("{}/{}-dataset-vectors.bin", config.options.dataset_dir, config.options.output_source_name) {
         config.default_args.insert("dataset-vector-file".to_string(), true);
     }
 }
@@ -534,13 +1582,26 @@ fn read_exclude_file(filename: &str) -> Vec<String> {
 
 // Read all exclude files and populate options.excluded_ids with each line from those files
 // Allow "#" for comments and ignore empty lines
+// Lines with two tab separated columns have an external ID in the first column (the second is ignored),
+// these are collected in options.excluded_external_ids and resolved against the source data when matching.
 fn populate_excluded_ids(options: &mut ConfigOptions) {
     let mut excluded_ids = Vec::new();
+    let mut excluded_external_ids = Vec::new();
     for filename in &options.exclude_files {
-        let mut ids = read_exclude_file(filename);
-        excluded_ids.append(&mut ids);
+        for id in read_exclude_file(filename) {
+            if let Some((external_id, _)) = id.split_once('\t') {
+                excluded_external_ids.push(external_id.trim().to_string());
+            } else {
+                excluded_ids.push(id);
+            }
+        }
+    }
+    if !excluded_external_ids.is_empty() && !source_data::SOURCE_RECORD_FIELDS.contains(&options.exclude_id_field.as_str()) {
+        eprintln!("Invalid exclude-id-field: {} (available: {})", options.exclude_id_field, source_data::SOURCE_RECORD_FIELDS.join(", "));
+        std::process::exit(1);
     }
     options.excluded_ids = excluded_ids;
+    options.excluded_external_ids = excluded_external_ids;
 }
 
 // Same as populate_excluded_ids, but for input_exclude_files and input_excluded_ids
@@ -553,6 +1614,116 @@ fn populate_excluded_input_ids(options: &mut ConfigOptions) {
     options.input_excluded_ids = excluded_ids;
 }
 
+// Read the location gazetteer file (if any) into options.location_gazetteer_map
+fn populate_location_gazetteer(options: &mut ConfigOptions) {
+    if let Some(filename) = &options.location_gazetteer {
+        let file = File::open(filename).unwrap_or_else(|e| {
+            eprintln!("Failed to open location gazetteer file {}: {}", filename, e);
+            std::process::exit(1);
+        });
+        let gazetteer: FxHashMap<String, Vec<String>> = serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|e| {
+            eprintln!("Failed to parse location gazetteer file {}: {}", filename, e);
+            std::process::exit(1);
+        });
+        options.location_gazetteer_map = tokenizer::gazetteer_variants(&gazetteer);
+    }
+}
+
+// Read the re-rank table (if any) into options.rerank_entries.
+// Each line is card_prefix,source_id,multiplier. "#" is used for comments, and an optional
+// header line starting with "card_prefix" and empty lines are ignored.
+fn populate_rerank_entries(options: &mut ConfigOptions) {
+    if let Some(filename) = &options.rerank_table {
+        let content = std::fs::read_to_string(filename).unwrap_or_else(|e| {
+            eprintln!("Failed to read rerank table {}: {}", filename, e);
+            std::process::exit(1);
+        });
+        for (line_number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("card_prefix") {
+                continue;
+            }
+            let columns = line.split(',').map(str::trim).collect::<Vec<&str>>();
+            let multiplier = match columns.as_slice() {
+                [_, _, multiplier] => multiplier.parse::<f32>().ok(),
+                _ => None,
+            };
+            let Some(multiplier) = multiplier else {
+                eprintln!("Invalid line {} in rerank table {}, expected card_prefix,source_id,multiplier: {}", line_number + 1, filename, line);
+                std::process::exit(1);
+            };
+            options.rerank_entries.entry(columns[1].to_string()).or_default().push((columns[0].to_string(), multiplier));
+        }
+    }
+}
+
+// Read the gold file (if any) into options.gold_ids
+fn populate_gold_ids(options: &mut ConfigOptions) {
+    if let Some(filename) = &options.gold_file {
+        options.gold_ids = read_card_ids(filename, "gold file");
+    }
+}
+
+// Read the known ids file (if any) into options.known_ids, same format as the gold file
+fn populate_known_ids(options: &mut ConfigOptions) {
+    if let Some(filename) = &options.known_ids_file {
+        options.known_ids = read_card_ids(filename, "known ids file");
+    }
+}
+
+// Each line is card<TAB>source_id, where "-" or an empty source id means that no match is expected.
+// "#" is used for comments and empty lines are ignored.
+fn read_card_ids(filename: &str, description: &str) -> FxHashMap<String, String> {
+    let content = std::fs::read_to_string(filename).unwrap_or_else(|e| {
+        eprintln!("Failed to read {} {}: {}", description, filename, e);
+        std::process::exit(1);
+    });
+    let mut card_ids = FxHashMap::default();
+    for line in content.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (card, source_id) = line.split_once('\t').unwrap_or((line, ""));
+        let source_id = source_id.trim();
+        let source_id = if source_id == "-" { "" } else { source_id };
+        card_ids.insert(gold_card_name(card), source_id.to_string());
+    }
+    card_ids
+}
+
+// Card name as used in the gold file, without any directory and .json extension
+pub fn gold_card_name(card: &str) -> String {
+    let card = card.trim();
+    let card = card.split('/').next_back().unwrap_or(card);
+    card.trim_end_matches(".json").to_string()
+}
+
+// Read the leading articles file (if any) into options.leading_articles
+fn populate_leading_articles(options: &mut ConfigOptions) {
+    if let Some(filename) = &options.strip_leading_articles {
+        let content = std::fs::read_to_string(filename).unwrap_or_else(|e| {
+            eprintln!("Failed to read leading articles file {}: {}", filename, e);
+            std::process::exit(1);
+        });
+        options.leading_articles = tokenizer::leading_articles(&content);
+    }
+}
+
+// Read the acronym file (if any) into options.acronyms
+fn populate_acronyms(options: &mut ConfigOptions) {
+    if let Some(filename) = &options.acronym_file {
+        let file = File::open(filename).unwrap_or_else(|e| {
+            eprintln!("Failed to open acronym file {}: {}", filename, e);
+            std::process::exit(1);
+        });
+        let acronyms: FxHashMap<String, String> = serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|e| {
+            eprintln!("Failed to parse acronym file {}: {}", filename, e);
+            std::process::exit(1);
+        });
+        options.acronyms = tokenizer::acronym_lookup(&acronyms);
+    }
+}
+
     // let vocab_file = args.vocab_file.clone().unwrap_or(format!("data/{}-vocab.bin", source));
     // let dataset_vector_file = args.dataset_vector_file.clone().unwrap_or(format!("data/{}-dataset-vectors.bin", source));
     // let source_data_file = args.source_data_file.clone().unwrap_or(format!("data/{}-source-data.bin", source));
@@ -561,12 +1732,40 @@ fn vocab_file_name(args: &Args, options: &ConfigOptions) -> String {
     args.vocab_file.clone().unwrap_or(format!("{}/{}-vocab.bin", options.dataset_dir, options.output_source_name))
 }
 
+// The build commands write a single dataset vectors and source data file
 fn dataset_vector_file_name(args: &Args, options: &ConfigOptions) -> String {
-    args.dataset_vector_file.clone().unwrap_or(format!("{}/{}-dataset-vectors.bin", options.dataset_dir, options.output_source_name))
+    single_file(&args.dataset_vector_file, "dataset vector").unwrap_or(format!("{}/{}-dataset-vectors.bin", options.dataset_dir, options.output_source_name))
 }
 
 fn source_data_file_name(args: &Args, options: &ConfigOptions) -> String {
-    args.source_data_file.clone().unwrap_or(format!("{}/{}-source-data.bin", options.dataset_dir, options.output_source_name))
+    single_file(&args.source_data_file, "source data").unwrap_or(format!("{}/{}-source-data.bin", options.dataset_dir, options.output_source_name))
+}
+
+fn single_file(files: &[String], description: &str) -> Option<String> {
+    if files.len() > 1 {
+        eprintln!("Only one {} file is supported for this command, several are only supported when matching", description);
+        std::process::exit(1);
+    }
+    files.first().cloned()
+}
+
+// The match commands read one or more dataset vectors files, each with its source data file
+fn dataset_vector_file_names(args: &Args, options: &ConfigOptions) -> Vec<String> {
+    if args.dataset_vector_file.is_empty() {
+        return vec![dataset_vector_file_name(args, options)];
+    }
+    args.dataset_vector_file.clone()
+}
+
+fn source_data_file_names(args: &Args, options: &ConfigOptions) -> Vec<String> {
+    if args.source_data_file.is_empty() {
+        return vec![source_data_file_name(args, options)];
+    }
+    if args.source_data_file.len() != args.dataset_vector_file.len().max(1) {
+        eprintln!("Give one source data file (-S) for each dataset vectors file (-D), got {} and {}", args.source_data_file.len(), args.dataset_vector_file.len());
+        std::process::exit(1);
+    }
+    args.source_data_file.clone()
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -596,24 +1795,33 @@ fn load_options_from_file(filename: &str, options: &mut ConfigOptions) {
         if let Some(file_opts) = matching_config.options {
             fill_options(options, file_opts);
         }
-        // If there is a weights field, write it to a tempfile and set options.weights_file to that filename
+        // If there is a weights field, it is used instead of a weights file
         if let Some(weights) = matching_config.weights {
-            let temp_dir = std::env::temp_dir();
-            let random_number = rand::random::<u32>();
-            let weights_file_path = temp_dir.join(format!("matching_weights_temp-{}.json", random_number));
-            let weights_file = File::create(&weights_file_path).unwrap_or_else(|e| {
-                eprintln!("Failed to create temporary weights file: {}", e);
-                std::process::exit(1);
-            });
-            serde_json::to_writer_pretty(weights_file, &weights).unwrap_or_else(|e| {
-                eprintln!("Failed to write weights to temporary file: {}", e);
-                std::process::exit(1);
-            });
-            options.weights_file = Some(weights_file_path.to_str().unwrap().to_string());
+            options.config_weights = Some(config_file_weights(filename, weights));
+            options.option_sources.insert("weights-file".to_string(), OptionSource::ConfigFile);
         }
     }
 }
 
+// The "weights" object of the config file, a weight for each of WEIGHT_PARTS
+fn config_file_weights(filename: &str, weights: serde_json::Value) -> FxHashMap<String, f32> {
+    let weights: FxHashMap<String, f32> = serde_json::from_value(weights).unwrap_or_else(|e| {
+        eprintln!("Invalid weights in config file {}, expected an object with a number per part: {}", filename, e);
+        std::process::exit(1);
+    });
+    if let Some(part) = WEIGHT_PARTS.iter().find(|part| !weights.contains_key(**part)) {
+        eprintln!("Weights in config file {} have no weight for {}", filename, part);
+        std::process::exit(1);
+    }
+    weights
+}
+
+// true, false or "lowercase"
+fn fill_normalize_output_ids(options: &mut ConfigOptions, option_value: &serde_json::Value) {
+    options.lowercase_output_ids = option_value.as_str() == Some("lowercase");
+    options.normalize_output_ids = options.lowercase_output_ids || option_value.as_bool().unwrap_or(false);
+}
+
 fn fill_bool(option: &mut bool, option_value: &serde_json::Value) {
     *option = option_value.as_bool().unwrap_or(false)
 }
@@ -646,6 +1854,10 @@ fn fill_string(option: &mut String, option_value: &serde_json::Value) {
     *option = option_value.as_str().unwrap_or("").to_string()
 }
 
+fn fill_optional_string(option: &mut Option<String>, option_value: &serde_json::Value) {
+    *option = option_value.as_str().map(|value| value.to_string())
+}
+
 fn fill_jaro_truncate(option: &mut JaroTruncate, option_value: &serde_json::Value) {
     if let Some(value) = option_value.as_str() {
         match value {
@@ -660,29 +1872,202 @@ fn fill_jaro_truncate(option: &mut JaroTruncate, option_value: &serde_json::Valu
     }
 }
 
+fn fill_combined_order(option: &mut Vec<String>, option_value: &serde_json::Value) {
+    if let Some(value) = option_value.as_str() {
+        *option = ConfigOptions::combined_order_option(value);
+    }
+}
+
+// A single predicate or a list of predicates
+// Either the query as a JSON object, or the same as a string as on the command line
+fn fill_es_filter(option: &mut Option<serde_json::Value>, option_value: &serde_json::Value) {
+    match option_value {
+        serde_json::Value::String(value) => *option = Some(ConfigOptions::es_filter_option(value)),
+        serde_json::Value::Object(_) => *option = Some(option_value.clone()),
+        _ => {},
+    }
+}
+
+fn fill_exclude_where(option: &mut Vec<source_data::FieldPredicate>, option_value: &serde_json::Value) {
+    match option_value {
+        serde_json::Value::String(value) => *option = vec![ConfigOptions::exclude_where_option(value)],
+        serde_json::Value::Array(values) => {
+            *option = values.iter().filter_map(|value| value.as_str()).map(ConfigOptions::exclude_where_option).collect();
+        },
+        _ => {},
+    }
+}
+
+fn fill_overlap_field(option: &mut OverlapField, option_value: &serde_json::Value) {
+    if let Some(value) = option_value.as_str() {
+        *option = OverlapField::parse(value);
+    }
+}
+
+fn fill_score_transform(option: &mut ScoreTransform, option_value: &serde_json::Value) {
+    if let Some(value) = option_value.as_str() {
+        *option = ScoreTransform::parse(value);
+    }
+}
+
+fn fill_zscore_method(option: &mut ZscoreMethod, option_value: &serde_json::Value) {
+    if let Some(value) = option_value.as_str() {
+        *option = ZscoreMethod::parse(value);
+    }
+}
+
+fn fill_tokenizer(option: &mut TokenizerMode, option_value: &serde_json::Value) {
+    if let Some(value) = option_value.as_str() {
+        *option = TokenizerMode::parse(value);
+    }
+}
+
+fn fill_dedup_input(option: &mut DedupInput, option_value: &serde_json::Value) {
+    if let Some(value) = option_value.as_str() {
+        *option = DedupInput::parse(value);
+    }
+}
+
+fn fill_author_normalize(option: &mut AuthorNormalize, option_value: &serde_json::Value) {
+    if let Some(value) = option_value.as_str() {
+        *option = AuthorNormalize::parse(value);
+    }
+}
+
+// "on"/"off" or true/false
+fn fill_sentinels(option: &mut bool, option_value: &serde_json::Value) {
+    match option_value {
+        serde_json::Value::String(value) => *option = ConfigOptions::sentinels_option(value),
+        serde_json::Value::Bool(value) => *option = *value,
+        _ => {},
+    }
+}
+
+fn fill_year_granularity(option: &mut YearGranularity, option_value: &serde_json::Value) {
+    if let Some(value) = option_value.as_str() {
+        *option = YearGranularity::parse(value);
+    }
+}
+
+fn fill_report(option: &mut ReportKind, option_value: &serde_json::Value) {
+    if let Some(value) = option_value.as_str() {
+        *option = ReportKind::parse(value);
+    }
+}
+
+fn fill_json_schema_version(option: &mut i32, option_value: &serde_json::Value) {
+    if option_value.as_str() == Some("auto") {
+        *option = JSON_SCHEMA_VERSION_AUTO
+    } else {
+        fill_i32(option, option_value)
+    }
+}
+
+// Name of a JSON schema version as given with -O json-schema-version
+pub fn json_schema_version_name(version: i32) -> String {
+    if version == JSON_SCHEMA_VERSION_AUTO {
+        "auto".to_string()
+    } else {
+        version.to_string()
+    }
+}
+
 fn fill_option(option_name: &str, option_value: &serde_json::Value, options: &mut ConfigOptions) {
     match option_name {
         "force_year" => fill_bool(&mut options.force_year, option_value),
         "year_tolerance" => fill_optional_i32(&mut options.year_tolerance, option_value),
         "year_tolerance_penalty" => fill_f32(&mut options.year_tolerance_penalty, option_value),
+        "empty_author_penalty" => fill_f32(&mut options.empty_author_penalty, option_value),
         "parse_year_ranges" => fill_bool(&mut options.parse_year_ranges, option_value),
         "use_first_parsed_year" => fill_bool(&mut options.use_first_parsed_year, option_value),
+        "last_year_field" => fill_optional_string(&mut options.last_year_field, option_value),
+        "publication_type_field" => fill_optional_string(&mut options.publication_type_field, option_value),
+        "require_type_match" => fill_bool(&mut options.require_type_match, option_value),
         "include_source_data" => fill_bool(&mut options.include_source_data, option_value),
+        "text_table" => fill_bool(&mut options.text_table, option_value),
         "similarity_threshold" => fill_optional_f32(&mut options.similarity_threshold, option_value),
+        "title_threshold" => fill_optional_f32(&mut options.title_threshold, option_value),
+        "author_threshold" => fill_optional_f32(&mut options.author_threshold, option_value),
         "z_threshold" => fill_optional_f32(&mut options.z_threshold, option_value),
+        "adaptive_z" => fill_optional_f32(&mut options.adaptive_z, option_value),
+        "min_pool_for_z" => fill_optional_i32(&mut options.min_pool_for_z, option_value),
+        "score_transform" => fill_score_transform(&mut options.score_transform, option_value),
+        "zscore_method" => fill_zscore_method(&mut options.zscore_method, option_value),
         "min_single_similarity" => fill_optional_f32(&mut options.min_single_similarity, option_value),
         "min_multiple_similarity" => fill_optional_f32(&mut options.min_multiple_similarity, option_value),
+        "ambiguous_spread" => fill_optional_f32(&mut options.ambiguous_spread, option_value),
+        "detect_exact" => fill_bool(&mut options.detect_exact, option_value),
+        "fail_if_nomatch_above" => fill_optional_f32(&mut options.fail_if_nomatch_above, option_value),
+        "quiet" => fill_bool(&mut options.quiet, option_value),
+        "weights_profiles" => fill_optional_string(&mut options.weights_profiles, option_value),
         "extended_output" => fill_bool(&mut options.extended_output, option_value),
+        "explain" => fill_bool(&mut options.explain, option_value),
+        "output_combined_text" => fill_bool(&mut options.output_combined_text, option_value),
+        "xlsx_summary_sheet" => fill_bool(&mut options.xlsx_summary_sheet, option_value),
+        "json_envelope" => fill_bool(&mut options.json_envelope, option_value),
+        "output_part_similarities" => fill_bool(&mut options.output_part_similarities, option_value),
+        "confidence_score" => fill_bool(&mut options.confidence_score, option_value),
+        "confidence_midpoint" => fill_f32(&mut options.confidence_midpoint, option_value),
+        "confidence_steepness" => fill_f32(&mut options.confidence_steepness, option_value),
+        "confidence_margin_weight" => fill_f32(&mut options.confidence_margin_weight, option_value),
+        "confidence_z_weight" => fill_f32(&mut options.confidence_z_weight, option_value),
+        "merge_editions" => fill_bool(&mut options.merge_editions, option_value),
+        "max_rows_per_card" => fill_optional_i32(&mut options.max_rows_per_card, option_value),
+        "output_batch_size" => fill_optional_i32(&mut options.output_batch_size, option_value),
+        "edition_in_card_id" => fill_bool(&mut options.edition_in_card_id, option_value),
+        "resume_from" => fill_optional_string(&mut options.resume_from, option_value),
+        "output_precision" => fill_optional_i32(&mut options.output_precision, option_value),
+        "report" => fill_report(&mut options.report, option_value),
+        "normalize_output_ids" => fill_normalize_output_ids(options, option_value),
+        "matched_id_sep" => fill_string(&mut options.matched_id_sep, option_value),
+        "fetch_limit" => fill_optional_i32(&mut options.fetch_limit, option_value),
+        "es_filter" => fill_es_filter(&mut options.es_filter, option_value),
+        "id_field" => fill_optional_string(&mut options.id_field, option_value),
+        "append" => fill_bool(&mut options.append, option_value),
+        "date_field" => fill_optional_string(&mut options.date_field, option_value),
+        "skip_source_check" => fill_bool(&mut options.skip_source_check, option_value),
+        "lazy_vectors" => fill_bool(&mut options.lazy_vectors, option_value),
+        "no_vector_cache" => fill_bool(&mut options.no_vector_cache, option_value),
+        "combined_order" => fill_combined_order(&mut options.combined_order, option_value),
+        "tokenizer" => fill_tokenizer(&mut options.tokenizer, option_value),
+        "year_granularity" => fill_year_granularity(&mut options.year_granularity, option_value),
+        "sentinels" => fill_sentinels(&mut options.sentinels, option_value),
+        "numeric_tokens" => fill_bool(&mut options.numeric_tokens, option_value),
+        "clean_year" => fill_bool(&mut options.clean_year, option_value),
+        "part_idf" => fill_bool(&mut options.part_idf, option_value),
+        "author_normalize" => fill_author_normalize(&mut options.author_normalize, option_value),
+        "listen" => fill_optional_string(&mut options.listen, option_value),
+        "candidates_dump" => fill_optional_i32(&mut options.candidates_dump, option_value),
+        "trace_filtering" => fill_optional_i32(&mut options.trace_filtering, option_value),
+        "self_check_sample" => fill_optional_i32(&mut options.self_check_sample, option_value),
+        "dump_query_vectors" => fill_optional_string(&mut options.dump_query_vectors, option_value),
+        "use_combined_only" => fill_bool(&mut options.use_combined_only, option_value),
+        "all_fallback" => fill_optional_i32(&mut options.all_fallback, option_value),
+        "length_normalize" => fill_bool(&mut options.length_normalize, option_value),
         "add_author_to_title" => fill_bool(&mut options.add_author_to_title, option_value),
+        "skip_empty_title" => fill_bool(&mut options.skip_empty_title, option_value),
         "add_serial_to_title" => fill_bool(&mut options.add_serial_to_title, option_value),
         "add_edition_to_title" => fill_bool(&mut options.add_edition_to_title, option_value),
         "overlap_adjustment" => fill_optional_i32(&mut options.overlap_adjustment, option_value),
+        "overlap_min_title_len" => fill_i32(&mut options.overlap_min_title_len, option_value),
+        "overlap_max_len" => fill_optional_i32(&mut options.overlap_max_len, option_value),
+        "overlap_field" => fill_overlap_field(&mut options.overlap_field, option_value),
         "jaro_winkler_adjustment" => fill_bool(&mut options.jaro_winkler_adjustment, option_value),
         "jaro_winkler_author_adjustment" => fill_bool(&mut options.jaro_winkler_author_adjustment, option_value),
         "jaro_winkler_truncate" => fill_jaro_truncate(&mut options.jaro_winkler_truncate, option_value),
-        "json_schema_version" => fill_i32(&mut options.json_schema_version, option_value),
+        "json_schema_version" => fill_json_schema_version(&mut options.json_schema_version, option_value),
+        "dedup_input" => fill_dedup_input(&mut options.dedup_input, option_value),
         "output_source_name" => fill_string(&mut options.output_source_name, option_value),
         "dataset_dir" => fill_string(&mut options.dataset_dir, option_value),
+        "exclude_id_field" => fill_string(&mut options.exclude_id_field, option_value),
+        "location_gazetteer" => fill_optional_string(&mut options.location_gazetteer, option_value),
+        "exclude_where" => fill_exclude_where(&mut options.exclude_where, option_value),
+        "rerank_table" => fill_optional_string(&mut options.rerank_table, option_value),
+        "gold_file" => fill_optional_string(&mut options.gold_file, option_value),
+        "known_ids_file" => fill_optional_string(&mut options.known_ids_file, option_value),
+        "prompt_file" => fill_optional_string(&mut options.prompt_file, option_value),
+        "strip_leading_articles" => fill_optional_string(&mut options.strip_leading_articles, option_value),
+        "acronym_file" => fill_optional_string(&mut options.acronym_file, option_value),
         _ => {},
     }
 }
@@ -691,6 +2076,24 @@ fn fill_options(options: &mut ConfigOptions, file_opts: serde_json::Value) {
     if let serde_json::Value::Object(map) = file_opts {
         for (key, value) in map {
             fill_option(&key, &value, options);
+            // Recorded under the -O name of the option
+            options.option_sources.insert(key.replace('_', "-"), OptionSource::ConfigFile);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{output_format_from_extension, OutputFormat};
+
+    #[test]
+    fn output_format_from_file_extension() {
+        assert_eq!(output_format_from_extension("out/results.csv"), Some(OutputFormat::CSV));
+        assert_eq!(output_format_from_extension("results.TSV"), Some(OutputFormat::CSV));
+        assert_eq!(output_format_from_extension("results.json"), Some(OutputFormat::Json));
+        assert_eq!(output_format_from_extension("results.xlsx"), Some(OutputFormat::XLSX));
+        assert_eq!(output_format_from_extension("results.txt"), Some(OutputFormat::Text));
+        assert_eq!(output_format_from_extension("results.ods"), None);
+        assert_eq!(output_format_from_extension("results"), None);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::args::Config;
+use crate::elastic::{self, Pagination};
+use crate::vocab::VocabBuilder;
+use crate::vectorize;
+use crate::source_data::SourceData;
+use rustc_hash::FxHashMap;
+use std::time::Instant;
+
+// Rate and elapsed time for the progress messages of the builds
+pub struct BuildProgress {
+    start: Instant,
+}
+
+impl BuildProgress {
+    pub fn start() -> BuildProgress {
+        BuildProgress { start: Instant::now() }
+    }
+
+    // E.g. "2500 records/s, elapsed 1h02m03s"
+    pub fn summary(&self, counter: u32) -> String {
+        let elapsed = self.start.elapsed();
+        let rate = counter as f64 / elapsed.as_secs_f64().max(0.001);
+        format!("{:.0} records/s, elapsed {}", rate, format_elapsed(elapsed.as_secs()))
+    }
+}
+
+// E.g. "1h02m03s", also used for the summary line after matching
+pub fn format_elapsed(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, (seconds % 3600) / 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h{:02}m{:02}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m{:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+// Build vocab, dataset vectors and source data for one source (build-all).
+// The vectors need the idf values from the complete vocab, so the source is scanned twice:
+// the first scan builds both the vocab and the source data, the second the dataset vectors.
+pub fn build_all(config: &Config) {
+    let source = &config.source;
+    let mut vocab_builder = VocabBuilder::new();
+    let mut source_records = FxHashMap::default();
+    let mut counter = 0;
+    let progress = BuildProgress::start();
+    println!("Building vocab and source data from {}", config.options.output_source_name);
+    let mut records = elastic::fetch_source(config, source, Pagination::Initial, 0);
+    loop {
+        if let Ok((_, Pagination::Done, _)) = records {
+            break;
+        }
+        if let Ok((new_records, new_pagination, total_count)) = records {
+            counter += new_records.len() as u32;
+            if counter % 10000 == 0 {
+                println!("Processing {} records from {} ({})", counter, config.options.output_source_name, progress.summary(counter));
+            }
+            for record in new_records {
+                vocab_builder.add_record(config, &record);
+                source_records.insert(record.id.clone(), record.into());
+            }
+            records = elastic::fetch_source(config, source, new_pagination, total_count);
+        }
+    }
+    println!("Processed {} records in {} ({})", counter, config.options.output_source_name, progress.summary(counter));
+
+    let vocab = vocab_builder.finish(config);
+    vocab.print_vocab_stats();
+    vocab.save(&config.vocab_file);
+    println!("Saved vocab: {}", config.vocab_file);
+
+    let source_data = SourceData {
+        source: config.options.output_source_name.clone(),
+        records: source_records,
+    };
+    source_data.save(&config.source_data_file);
+    println!("Saved source data: {}", config.source_data_file);
+
+    println!("Building dataset vectors from {}", config.options.output_source_name);
+    vectorize::build_dataset_vectors_with_vocab(config, &vocab);
+    println!("Saved dataset vectors: {}", config.dataset_vector_file);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_elapsed;
+
+    #[test]
+    fn elapsed_time_format() {
+        assert_eq!(format_elapsed(45), "45s");
+        assert_eq!(format_elapsed(123), "2m03s");
+        assert_eq!(format_elapsed(3723), "1h02m03s");
+    }
+}
@@ -3,15 +3,28 @@ use crate::vocab;
 use crate::vectorize;
 use crate::matcher;
 use crate::source_data;
+use crate::server;
+use crate::build;
 use std::fmt::{self, Display, Formatter};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
 
 #[derive(Debug)]
 pub enum Cmd {
     BuildVocab,
     BuildDatasetVectors,
     MatchJsonZip,
+    MatchSingleJson,
+    Serve,
     BuildSourceData,
+    BuildAll,
     DumpSourceData,
+    InspectVocab,
+    SelfCheck,
 }
 
 impl Cmd {
@@ -20,8 +33,13 @@ impl Cmd {
             Cmd::BuildVocab => vocab::build_vocab(config),
             Cmd::BuildDatasetVectors => vectorize::build_dataset_vectors(config),
             Cmd::MatchJsonZip => matcher::match_json_zip(config),
+            Cmd::MatchSingleJson => matcher::match_single_json(config),
+            Cmd::Serve => server::serve(config),
             Cmd::BuildSourceData => source_data::build_source_data(config),
+            Cmd::BuildAll => build::build_all(config),
             Cmd::DumpSourceData => source_data::dump_source_data(config),
+            Cmd::InspectVocab => vocab::inspect_vocab(config),
+            Cmd::SelfCheck => matcher::self_check(config),
         }
     }
 }
@@ -32,8 +50,109 @@ impl Display for Cmd {
             Cmd::BuildVocab => write!(f, "build-vocab"),
             Cmd::BuildDatasetVectors => write!(f, "build-dataset-vectors"),
             Cmd::MatchJsonZip => write!(f, "match-json-zip"),
+            Cmd::MatchSingleJson => write!(f, "match-single-json"),
+            Cmd::Serve => write!(f, "serve"),
             Cmd::BuildSourceData => write!(f, "build-source-data"),
+            Cmd::BuildAll => write!(f, "build-all"),
             Cmd::DumpSourceData => write!(f, "dump-source-data"),
+            Cmd::InspectVocab => write!(f, "inspect-vocab"),
+            Cmd::SelfCheck => write!(f, "self-check"),
+        }
+    }
+}
+
+// Open one of the data files built by the build-* commands, exiting with a message
+// naming the command that builds the file if it cannot be opened.
+// Gzip-compressed files are detected by their magic bytes and decompressed transparently,
+// regardless of the file name.
+pub fn open_data_file(path: &str, description: &str, build_cmd: Cmd) -> Box<dyn Read> {
+    let mut reader = match std::fs::File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(e) => {
+            eprintln!("Failed to open {} file {}: {}", description, path, e);
+            eprintln!("Check the path, or build the file with: -c {} -s <source>", build_cmd);
+            std::process::exit(1);
+        }
+    };
+    let is_gzip = match reader.fill_buf() {
+        Ok(buf) => buf.starts_with(&GZIP_MAGIC),
+        Err(_) => false,
+    };
+    if is_gzip {
+        Box::new(BufReader::new(GzDecoder::new(reader)))
+    } else {
+        Box::new(reader)
+    }
+}
+
+// Writer for the data files, gzip-compressed when the path ends in .gz
+pub enum DataFileWriter {
+    Plain(BufWriter<std::fs::File>),
+    Gzip(GzEncoder<BufWriter<std::fs::File>>),
+}
+
+impl DataFileWriter {
+    // Flush the writer, and for gzip write the trailer, which is not done reliably on drop
+    pub fn finish(self) -> std::io::Result<()> {
+        match self {
+            DataFileWriter::Plain(mut writer) => writer.flush(),
+            DataFileWriter::Gzip(encoder) => encoder.finish()?.flush(),
         }
     }
 }
+
+impl Write for DataFileWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            DataFileWriter::Plain(writer) => writer.write(buf),
+            DataFileWriter::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            DataFileWriter::Plain(writer) => writer.flush(),
+            DataFileWriter::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+pub fn create_data_file(path: &str) -> DataFileWriter {
+    let writer = BufWriter::new(std::fs::File::create(path).unwrap());
+    if path.ends_with(".gz") {
+        DataFileWriter::Gzip(GzEncoder::new(writer, Compression::default()))
+    } else {
+        DataFileWriter::Plain(writer)
+    }
+}
+
+// Exit with a message when a data file exists but cannot be read
+pub fn invalid_data_file(path: &str, description: &str, build_cmd: Cmd) -> ! {
+    eprintln!("Failed to read {} file {}, it is not a valid {} file", description, path, description);
+    eprintln!("Rebuild the file with: -c {} -s <source>", build_cmd);
+    std::process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{create_data_file, open_data_file, Cmd};
+    use std::io::{Read, Write};
+
+    #[test]
+    fn gzip_data_file_is_read_regardless_of_name() {
+        let dir = std::env::temp_dir();
+        let gz_path = dir.join(format!("datafile-test-{}.bin.gz", std::process::id()));
+        let gz_path = gz_path.to_str().unwrap();
+        let mut writer = create_data_file(gz_path);
+        writer.write_all(b"vectors").unwrap();
+        writer.finish().unwrap();
+
+        // Same content under a name without .gz must still be decompressed
+        let plain_name = gz_path.trim_end_matches(".gz");
+        std::fs::rename(gz_path, plain_name).unwrap();
+        let mut content = String::new();
+        open_data_file(plain_name, "test", Cmd::BuildVocab).read_to_string(&mut content).unwrap();
+        std::fs::remove_file(plain_name).unwrap();
+        assert_eq!(content, "vectors");
+    }
+}
@@ -3,11 +3,18 @@
 use reqwest::blocking::Client;
 use serde_json::json;
 use crate::args::Config;
+use crate::tokenizer;
 
 const ELASTIC_URL: &str = "http://localhost:9200";
 const INDEX_NAME: &str = "records";
 const MAX_RECORDS: u32 = 10000000;
 
+// Extra filter on the initial query, only records where field > value are fetched
+pub struct RangeFilter {
+    pub field: String,
+    pub greater_than: String,
+}
+
 pub enum Pagination {
     Scroll(String),
     Initial,
@@ -23,20 +30,37 @@ pub struct Record {
     pub author: String,
     pub location: String, // From publisher property
     pub year: String, // From first_year property
+    pub last_year: String, // From the field given by last_year_field, empty if not configured
+    pub date: String, // From the field given by date_field, empty if not configured
+    pub publication_type: String, // From the field given by publication_type_field, empty if not configured
 }
 
+// Default order of the fields in the combined ("all") part
+pub const DEFAULT_COMBINED_ORDER: [&str; 4] = ["author", "title", "location", "year"];
+
 impl Record {
-    // Combine main fields in the order author, title, location, year
-    pub fn combined(&self) -> String {
-        format!("{} {} {} {}", self.author, self.title, self.location, self.year)
+    // Combine main fields in the given order (see -O combined-order), by default author, title, location, year
+    pub fn combined(&self, order: &[String]) -> String {
+        order.iter().map(|field| match field.as_str() {
+            "author" => self.author.as_str(),
+            "title" => self.title.as_str(),
+            "location" => self.location.as_str(),
+            "year" => self.year.as_str(),
+            _ => "",
+        }).collect::<Vec<&str>>().join(" ")
     }
 }
 
 // Fetch all documents from the index where source:<source_name>
 // Use the scroll API to fetch all documents in pages
 pub fn fetch_source(config: &Config, source_name: &str, pagination: Pagination, total_count: u32) -> Result<(Vec<Record>, Pagination, u32), reqwest::Error> {
+    fetch_source_filtered(config, source_name, pagination, total_count, None)
+}
+
+// Same as fetch_source, with an optional range filter applied to the initial query
+pub fn fetch_source_filtered(config: &Config, source_name: &str, pagination: Pagination, total_count: u32, filter: Option<&RangeFilter>) -> Result<(Vec<Record>, Pagination, u32), reqwest::Error> {
     match pagination {
-        Pagination::Initial => fetch_initial(config, source_name),
+        Pagination::Initial => fetch_initial(config, source_name, filter),
         Pagination::Scroll(scroll_id) => fetch_scroll(config, &scroll_id, total_count),
         Pagination::Done => Ok((vec![], Pagination::Done, total_count)),
     }
@@ -53,6 +77,35 @@ fn get_as_string(value: &serde_json::Value) -> String {
     }
 }
 
+// Years (and dates and ids) are stored either as strings or numbers in the index
+fn get_scalar_as_string(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(year_str) => year_str.clone(),
+        serde_json::Value::Number(year_num) => year_num.to_string(),
+        _ => "".to_string(),
+    }
+}
+
+// Maximum number of records to fetch, MAX_RECORDS unless lowered with -O fetch-limit
+fn fetch_limit(config: &Config) -> u32 {
+    match config.options.fetch_limit {
+        Some(limit) if limit >= 0 => (limit as u32).min(MAX_RECORDS),
+        _ => MAX_RECORDS,
+    }
+}
+
+// The record id is read from _source["id"] unless id_field is set,
+// where the special value "_id" is the meta id of the hit instead of a field in _source.
+// None if the configured field is missing or empty, since records without id would all share one key.
+fn get_record_id(id_field: Option<&str>, hit: &serde_json::Value) -> Option<String> {
+    let id = match id_field {
+        Some("_id") => get_scalar_as_string(&hit["_id"]),
+        Some(field) => get_scalar_as_string(&hit["_source"][field]),
+        None => hit["_source"]["id"].as_str().unwrap().to_string(),
+    };
+    (!id.is_empty()).then_some(id)
+}
+
 // Break out everything after the response since it is the same for both fetch_scroll and fetch_initial
 fn handle_response(config: &Config, response: reqwest::blocking::Response, total_count: u32) -> Result<(Vec<Record>, Pagination, u32), reqwest::Error> {
     let response_json: serde_json::Value = response.json()?;
@@ -64,16 +117,34 @@ fn handle_response(config: &Config, response: reqwest::blocking::Response, total
         return Ok((vec![], Pagination::Done, total_count));
     }
 
-    if total_count >= MAX_RECORDS {
+    let limit = fetch_limit(config);
+    if total_count >= limit {
         return Ok((vec![], Pagination::Done, total_count));
     }
+    // Only keep the hits up to the limit, the next call will then be Done
+    let hits = &hits[..hits.len().min((limit - total_count) as usize)];
 
     let records = hits.iter().map(|hit| {
         let source = hit["_source"].clone();
-        let year = match &source["first_year"] {
-            serde_json::Value::String(year_str) => year_str.clone(),
-            serde_json::Value::Number(year_num) => year_num.to_string(),
-            _ => "".to_string(),
+        let mut year = get_scalar_as_string(&source["first_year"]);
+        // If option "clean_year" is set, "c. 1948" is stored as "1948", in the tokens as well as in the source data
+        if config.options.clean_year {
+            year = tokenizer::clean_year(&year);
+        }
+        // If option "last_year_field" is set, the record covers the span first_year..=last_year
+        let last_year = match &config.options.last_year_field {
+            Some(field) => get_scalar_as_string(&source[field.as_str()]),
+            None => "".to_string(),
+        };
+        // If option "date_field" is set, keep the date (string or number, same as years) for append mode
+        let date = match &config.options.date_field {
+            Some(field) => get_scalar_as_string(&source[field.as_str()]),
+            None => "".to_string(),
+        };
+        // If option "publication_type_field" is set, keep the publication type for require-type-match
+        let publication_type = match &config.options.publication_type_field {
+            Some(field) => get_as_string(&source[field.as_str()]),
+            None => "".to_string(),
         };
         let mut title = get_as_string(&source["title"]);
         // If option "add_serial_to_title" is set, append "serial_info" field to the title joined with a space
@@ -90,13 +161,22 @@ fn handle_response(config: &Config, response: reqwest::blocking::Response, total
                 title = format!("{} {}", title, edition);
             }
         }
+        let Some(id) = get_record_id(config.options.id_field.as_deref(), hit) else {
+            let id_field = config.options.id_field.as_deref().unwrap_or("id");
+            eprintln!("Record {} in {} has no value in the id field {}", get_scalar_as_string(&hit["_id"]), config.source, id_field);
+            eprintln!("Check -O id-field, every record needs an id");
+            std::process::exit(1);
+        };
         Record {
-            id: source["id"].as_str().unwrap().to_string(),
+            id,
             source: config.options.output_source_name.clone(),
             title: title,
             author: get_as_string(&source["author"]),
             location: get_as_string(&source["publisher"]),
             year: year,
+            last_year,
+            date,
+            publication_type,
         }
     }).collect();
 
@@ -118,19 +198,51 @@ fn fetch_scroll(config: &Config, scroll_id: &str, total_count: u32) -> Result<(V
     handle_response(config, response, total_count)
  }
 
-fn fetch_initial(config: &Config, source_name: &str) -> Result<(Vec<Record>, Pagination, u32), reqwest::Error> {
+// The source match, with the es-filter query (if set) as a second must clause
+// and the range filter (if any) as a filter
+fn source_query(source_name: &str, es_filter: Option<&serde_json::Value>, filter: Option<&RangeFilter>) -> serde_json::Value {
+    let source_match = json!({
+        "match": {
+            "source": source_name
+        }
+    });
+    if es_filter.is_none() && filter.is_none() {
+        return source_match;
+    }
+    let mut must = vec![source_match];
+    if let Some(es_filter) = es_filter {
+        must.push(es_filter.clone());
+    }
+    let mut query = json!({
+        "bool": {
+            "must": must
+        }
+    });
+    if let Some(filter) = filter {
+        query["bool"]["filter"] = json!({
+            "range": {
+                filter.field.as_str(): {
+                    "gt": filter.greater_than
+                }
+            }
+        });
+    }
+    query
+}
+
+fn fetch_initial(config: &Config, source_name: &str, filter: Option<&RangeFilter>) -> Result<(Vec<Record>, Pagination, u32), reqwest::Error> {
     let url = format!("{}/{}/_search?scroll=1m", ELASTIC_URL, INDEX_NAME);
     let client = Client::new();
 
     // Size to fetch in each scroll is the minimum of MAX_RECORDS and 10000
     let size = MAX_RECORDS.min(10000);
+    if config.verbose && config.options.fetch_limit.is_some() {
+        println!("Fetching at most {} records from {}", fetch_limit(config), source_name);
+    }
 
+    let query = source_query(source_name, config.options.es_filter.as_ref(), filter);
     let body = json!({
-        "query": {
-            "match": {
-                "source": source_name
-            }
-        },
+        "query": query,
         "size": size
     });
 
@@ -139,4 +251,72 @@ fn fetch_initial(config: &Config, source_name: &str) -> Result<(Vec<Record>, Pag
         .send()?;
 
     handle_response(config, response, 0)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_record_id, source_query, RangeFilter};
+    use serde_json::json;
+
+    #[test]
+    fn record_id_from_configured_field() {
+        let hit = json!({
+            "_id": "meta-123",
+            "_source": {
+                "id": "source-456",
+                "record_id": 789
+            }
+        });
+        assert_eq!(get_record_id(None, &hit).as_deref(), Some("source-456"));
+        assert_eq!(get_record_id(Some("_id"), &hit).as_deref(), Some("meta-123"));
+        assert_eq!(get_record_id(Some("record_id"), &hit).as_deref(), Some("789"));
+    }
+
+    #[test]
+    fn record_id_missing_from_configured_field() {
+        let hit = json!({
+            "_id": "meta-123",
+            "_source": {
+                "id": "source-456",
+                "record_id": ""
+            }
+        });
+        assert_eq!(get_record_id(Some("libris_id"), &hit), None);
+        assert_eq!(get_record_id(Some("record_id"), &hit), None);
+    }
+
+    #[test]
+    fn source_query_with_es_filter() {
+        assert_eq!(source_query("libris", None, None), json!({"match": {"source": "libris"}}));
+        let es_filter = json!({"range": {"first_year": {"gte": 1900}}});
+        let range_filter = RangeFilter { field: "updated".to_string(), greater_than: "2024-01-01".to_string() };
+        assert_eq!(source_query("libris", Some(&es_filter), Some(&range_filter)), json!({
+            "bool": {
+                "must": [
+                    {"match": {"source": "libris"}},
+                    {"range": {"first_year": {"gte": 1900}}}
+                ],
+                "filter": {"range": {"updated": {"gt": "2024-01-01"}}}
+            }
+        }));
+    }
+
+    #[test]
+    fn combined_in_configured_order() {
+        let record = super::Record {
+            id: "1".to_string(),
+            source: "test".to_string(),
+            title: "Titel".to_string(),
+            author: "Författare".to_string(),
+            location: "Stockholm".to_string(),
+            year: "1950".to_string(),
+            last_year: "".to_string(),
+            date: "".to_string(),
+            publication_type: "".to_string(),
+        };
+        let default_order = super::DEFAULT_COMBINED_ORDER.iter().map(|field| field.to_string()).collect::<Vec<String>>();
+        assert_eq!(record.combined(&default_order), "Författare Titel Stockholm 1950");
+        let title_first = vec!["title".to_string(), "author".to_string(), "location".to_string(), "year".to_string()];
+        assert_eq!(record.combined(&title_first), "Titel Författare Stockholm 1950");
+    }
+}
@@ -0,0 +1,38 @@
+// Stable (FNV-1a, 64 bit) hash used for values that are stored in files or compared between runs.
+// std's DefaultHasher is not guaranteed to be stable between Rust versions, so it can't be used for that.
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+pub fn stable_hash(data: &[u8]) -> u64 {
+    let mut hasher = StableHasher::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+// Same hash for data given in several parts
+pub struct StableHasher {
+    hash: u64,
+}
+
+impl StableHasher {
+    pub fn new() -> Self {
+        StableHasher { hash: FNV_OFFSET_BASIS }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.hash ^= *byte as u64;
+            self.hash = self.hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+// Hash as a 16 character hex string
+pub fn stable_hash_hex(data: &[u8]) -> String {
+    format!("{:016x}", stable_hash(data))
+}
@@ -10,6 +10,11 @@ mod report;
 mod output;
 mod zipfile;
 mod overlap;
+mod hash;
+mod server;
+mod build;
+mod run_id;
+mod vector_cache;
 
 fn main() {
     let config = args::Config::new();
@@ -1,19 +1,29 @@
-use crate::args::{Config, JaroTruncate};
+use crate::args::{AuthorNormalize, Config, JaroTruncate, OverlapField, ScoreTransform, ZscoreMethod, WEIGHT_PARTS, gold_card_name};
 use crate::vocab::Vocab;
+use crate::tokenizer;
 use crate::vectorize::{self, Vectors, Document};
 use crate::elastic::Record as ElasticRecord;
 use crate::source_data::{self, SourceRecord};
 use crate::report;
 use crate::output;
 use crate::zipfile;
+use crate::run_id::RunId;
 use crate::overlap::maximal_overlaps;
+use crate::vector_cache;
+use crate::build;
+use crate::hash::stable_hash;
 use serde::{Serialize, Deserialize};
 // use std::collections::{HashMap, BTreeMap};
 use std::collections::BTreeMap;
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 use rayon::prelude::*;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::io::{IsTerminal, Read, Write};
+use std::time::Instant;
 
 pub const TOP_N: usize = 10;
+// Number of candidates kept for calculating z-scores
+pub const Z_SCORE_POOL: usize = TOP_N*20;
 
 #[derive(Debug, Clone)]
 pub struct JsonRecord {
@@ -24,6 +34,30 @@ pub struct JsonRecord {
     pub year: String,
     pub publication_type: String, // Not used for matching
     pub allowed_years: Vec<u32>, // Not used for vector matching, but may be used for filtering later
+    pub schema_version: i32, // JSON schema version the record was read with
+}
+
+impl JsonRecord {
+    // Allowed years in compact form with consecutive years as ranges, e.g. "1949;1951-1954"
+    pub fn allowed_years_string(&self) -> String {
+        let mut years = self.allowed_years.clone();
+        years.sort();
+        years.dedup();
+        let mut ranges: Vec<(u32, u32)> = vec![];
+        for year in years {
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == year => *end = year,
+                _ => ranges.push((year, year)),
+            }
+        }
+        ranges.iter().map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        }).collect::<Vec<String>>().join(";")
+    }
 }
 
 impl From<&JsonRecord> for ElasticRecord {
@@ -35,6 +69,9 @@ impl From<&JsonRecord> for ElasticRecord {
             author: json_record.author.clone(),
             location: json_record.location.clone(),
             year: json_record.year.clone(),
+            last_year: "".to_string(),
+            date: "".to_string(),
+            publication_type: json_record.publication_type.clone(),
         }
     }
 }
@@ -42,12 +79,15 @@ impl From<&JsonRecord> for ElasticRecord {
 #[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
 pub enum MatchStat {
     SingleMatch,
+    ExactMatch, // Single where the title of the candidate is the title of the card, with detect_exact
     MultipleMatches,
+    Ambiguous, // Multiple where the top two are closer than ambiguous_spread
     UnqualifiedMultipleMatches,
     NoMatch,
     Unqualified, // Single not reaching min_single_similarity
     NoEdition, // No edition in the JSON record
     Excluded, // Excluded by id
+    SkippedEmpty, // Empty title with skip_empty_title, not matched
     InvalidJSON,
     NA,
 }
@@ -56,12 +96,15 @@ impl MatchStat {
     pub fn to_str(&self) -> &str {
         match self {
             MatchStat::SingleMatch => "Single",
+            MatchStat::ExactMatch => "Exact",
             MatchStat::MultipleMatches => "Multiple",
+            MatchStat::Ambiguous => "Ambiguous",
             MatchStat::UnqualifiedMultipleMatches => "Unqualified multiple",
             MatchStat::NoMatch => "No match",
             MatchStat::Unqualified => "Unqualified",
             MatchStat::NoEdition => "No edition",
             MatchStat::Excluded => "Excluded",
+            MatchStat::SkippedEmpty => "Skipped empty title",
             MatchStat::InvalidJSON => "Invalid JSON",
             MatchStat::NA => "",
         }
@@ -71,6 +114,38 @@ impl MatchStat {
     }
 }
 
+// The filter that removed a candidate in process_record, logged with -O trace-filtering
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterStage {
+    Excluded,                    // -O exclude-file
+    TypeMismatch,                // -O require-type-match
+    ExcludeWhere,                // -O exclude-where
+    SimilarityThreshold,         // -O similarity-threshold, before any adjustments
+    PartThreshold,               // -O title-threshold or author-threshold
+    Pool,                        // Not among the Z_SCORE_POOL most similar candidates
+    ZThreshold,                  // -O z-threshold
+    TopN,                        // Not among the TOP_N highest z-scores
+    ZeroSimilarity,              // Similarity 0.0 for any other reason (no common tokens, force-year, ...)
+    AdjustedSimilarityThreshold, // -O similarity-threshold after overlap or Jaro-Winkler adjustments
+}
+
+impl std::fmt::Display for FilterStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FilterStage::Excluded => write!(f, "exclude-file"),
+            FilterStage::TypeMismatch => write!(f, "require-type-match"),
+            FilterStage::ExcludeWhere => write!(f, "exclude-where"),
+            FilterStage::SimilarityThreshold => write!(f, "similarity-threshold"),
+            FilterStage::PartThreshold => write!(f, "title-threshold/author-threshold"),
+            FilterStage::Pool => write!(f, "z-score pool of {}", Z_SCORE_POOL),
+            FilterStage::ZThreshold => write!(f, "z-threshold"),
+            FilterStage::TopN => write!(f, "top {}", TOP_N),
+            FilterStage::ZeroSimilarity => write!(f, "similarity 0.0"),
+            FilterStage::AdjustedSimilarityThreshold => write!(f, "similarity-threshold after adjustments"),
+        }
+    }
+}
+
 // Struct to hold each candidate during match processing. Unused values (based on options) are set to 0.0
 #[derive(Debug, Clone, Default)]
 pub struct MatchCandidate {
@@ -83,6 +158,12 @@ pub struct MatchCandidate {
     pub adjusted_overlap_score: f32,
     pub jaro_winkler_score: f32,
     pub jaro_winkler_author_score: f32,
+    pub explanation: String, // Top contributing tokens to the similarity, only with -O explain
+    pub ngram_jaccard: f32, // Jaccard coefficient of the token sets, without weights or idf
+    pub document_index: usize, // Index in the dataset vectors
+    pub weights_profile: String, // Name of the profile the candidate was found with, only with -O weights-profiles
+    pub part_similarities: Vec<f32>, // Cosine similarity of each of SIMILARITY_PARTS, only with -O output-part-similarities
+    pub dropped_by: Option<FilterStage>, // Why process_one_item set the similarity to 0.0, for -O trace-filtering
 }
 
 impl MatchCandidate {
@@ -102,6 +183,7 @@ pub struct OutputRecord {
     pub record: JsonRecord,
     pub top: Vec<MatchCandidate>,
     pub stats: MatchStat,
+    pub expected_rank: Option<i32>, // Rank of the known id among all candidates (-1 if absent), only with -O known-ids-file
 }
 
 impl OutputRecord {
@@ -130,6 +212,7 @@ impl OutputRecord {
             record: new_record,
             top: top_source_records,
             stats,
+            expected_rank: None,
         }
     }
 }
@@ -140,17 +223,23 @@ pub struct MatchStatistics {
     pub number_of_records: usize,
     pub cards: FxHashMap<String, bool>,
     pub prompt_used: String,
+    // Problems found while loading the input files (see zipfile::InputWarning)
+    pub warnings: Vec<zipfile::InputWarning>,
+    // Identifier of the run, also in every output row (see run_id.rs)
+    pub run_id: String,
+    // Agreement with the gold file, only with -O gold-file
+    pub gold: Option<report::GoldEvaluation>,
+    // Input records skipped since they are in the previous output, only with -O resume-from
+    pub resumed_records: usize,
 }
 
 impl MatchStatistics {
     pub fn update(&mut self, stat: &MatchStat, card: &str) {
-        // If stat is NoEdition, we don't update any statistics other than the cards
-        if let MatchStat::NoEdition = stat {
-            self.cards.insert(card.to_string(), true);
-            return;
-        }
-        // If stat is InvalidJSON, we don't update any statistics other than the cards
-        if let MatchStat::InvalidJSON = stat {
+        // If stat is NoEdition or InvalidJSON, the card is counted in match_types,
+        // but it is not a match entity so number_of_records is not updated
+        if let MatchStat::NoEdition | MatchStat::InvalidJSON = stat {
+            let entry = self.match_types.entry(stat.clone()).or_insert(0);
+            *entry += 1;
             self.cards.insert(card.to_string(), true);
             return;
         }
@@ -179,104 +268,806 @@ impl MatchStatistics {
         let matches = self.match_stat(stat) as f32;
         (matches / total) * 100.0
     }
+
+    // Input files whose card name is also used by another file (see -O dedup-input)
+    pub fn duplicate_cards(&self) -> usize {
+        self.warnings.iter().filter(|warning| matches!(warning.kind, zipfile::InputWarningKind::DuplicateCard)).count()
+    }
+
+    // Percentage of cards, for stats counted per card (NoEdition and InvalidJSON)
+    pub fn card_stat_percent(&self, stat: &MatchStat) -> f32 {
+        let total = self.number_of_cards() as f32;
+        let cards = self.match_stat(stat) as f32;
+        (cards / total) * 100.0
+    }
 }
 
+#[derive(Serialize, Deserialize)]
 struct DatasetWeightedVector {
     id: String,
     vector: Vec<(u32, f32)>,
     dot: f32,
+    // Vectors of single parts, only the parts in retained_parts
+    part_vectors: FxHashMap<String, PartVector>,
+}
+
+// The tf-idf vector of one part and the square root of its dot product with itself
+#[derive(Serialize, Deserialize)]
+struct PartVector {
+    vector: Vec<(u32, f32)>,
+    dot: f32,
+}
+
+// The dataset vector an input record is compared to: the weighted average vector,
+// or the all part alone for records with few parts (-O all-fallback)
+#[derive(Clone, Copy)]
+struct ComparedVector<'a> {
+    vector: &'a [(u32, f32)],
+    dot: f32,
+}
+
+impl DatasetWeightedVector {
+    fn compared_vector(&self, all_fallback: bool) -> ComparedVector<'_> {
+        match self.part_vectors.get("all") {
+            Some(all) if all_fallback => ComparedVector { vector: &all.vector, dot: all.dot },
+            _ => ComparedVector { vector: &self.vector, dot: self.dot },
+        }
+    }
+}
+
+// Part vectors of the input record (see retained_parts), and whether it is compared on the all part alone
+struct InputParts {
+    vectors: FxHashMap<String, PartVector>,
+    all_fallback: bool,
+}
+
+// True if fewer than all-fallback (K) of author, title, location and year have tokens in the input record
+fn uses_all_fallback(config: &Config, document: &Document) -> bool {
+    let Some(min_parts) = config.options.all_fallback else {
+        return false;
+    };
+    let parts = SIMILARITY_PARTS.iter()
+        .filter(|part| document.vectors.get(**part).is_some_and(|vector| !vector.is_empty()))
+        .count();
+    (parts as i32) < min_parts
+}
+
+// Parts compared one by one with -O output-part-similarities, in the order of the output columns
+pub const SIMILARITY_PARTS: [&str; 4] = ["title", "author", "location", "year"];
+
+// Parts whose own vectors are kept next to the weighted average vector, since they are
+// compared one by one. Empty unless an option needs them, the vectors then take more memory.
+fn retained_parts(config: &Config) -> Vec<&'static str> {
+    let mut parts = if config.options.output_part_similarities {
+        SIMILARITY_PARTS.to_vec()
+    } else {
+        part_thresholds(config).into_iter().map(|(part, _)| part).collect()
+    };
+    if config.options.all_fallback.is_some() {
+        parts.push("all");
+    }
+    parts
+}
+
+// Per-part similarity thresholds (-O title-threshold, -O author-threshold)
+fn part_thresholds(config: &Config) -> Vec<(&'static str, f32)> {
+    [("title", config.options.title_threshold), ("author", config.options.author_threshold)].into_iter()
+        .filter_map(|(part, threshold)| threshold.map(|threshold| (part, threshold)))
+        .collect()
+}
+
+fn part_vectors(document: &Document, parts: &[&str]) -> FxHashMap<String, PartVector> {
+    parts.iter().map(|part| {
+        let vector = document.vectors.get(*part).cloned().unwrap_or_default();
+        let dot = dot_product(&vector, &vector).sqrt();
+        (part.to_string(), PartVector { vector, dot })
+    }).collect()
+}
+
+// Cosine similarity of the same part of two records, 0.0 if either is empty
+fn part_similarity(input: &PartVector, source: Option<&PartVector>) -> f32 {
+    match source {
+        Some(source) if input.dot > 0.0 && source.dot > 0.0 => cosine_similarity(&input.vector, input.dot, &source.vector, source.dot),
+        _ => 0.0,
+    }
+}
+
+// True if a part with a threshold is below it. Parts that are empty in the input record are not checked,
+// a record without author is not dropped by author-threshold.
+fn below_part_thresholds(config: &Config, input_parts: &FxHashMap<String, PartVector>, document: &DatasetWeightedVector) -> bool {
+    part_thresholds(config).into_iter().any(|(part, threshold)| {
+        match input_parts.get(part) {
+            Some(input) if !input.vector.is_empty() => part_similarity(input, document.part_vectors.get(part)) < threshold,
+            _ => false,
+        }
+    })
+}
+
+// The weighted vectors are calculated once for each set of weights (one per weights profile)
+fn precalc_weighted_average_vectors_for_source(config: &Config, dataset_vectors: &Vectors, weights: &[&FxHashMap<String, f32>]) -> Vec<Vec<DatasetWeightedVector>> {
+    if config.verbose {
+        eprintln!("Calculating weighted average vectors for {}", config.source);
+    }
+    weights.iter().map(|weights| precalc_weighted_average_vectors(config, &dataset_vectors.documents, weights)).collect()
 }
 
-fn precalc_weighted_average_vectors_for_source(config: &Config, dataset_vectors: &Vectors, weights: &FxHashMap<String, f32>) -> Vec<DatasetWeightedVector> {
+// Same as precalc_weighted_average_vectors_for_source, but reading the vectors file chunk by chunk
+// so the raw documents are never all in memory at once (-O lazy-vectors). Returns the source of the vectors file.
+fn precalc_weighted_average_vectors_chunked(config: &Config, dataset_vector_file: &str, weights: &[&FxHashMap<String, f32>]) -> (String, Vec<Vec<DatasetWeightedVector>>) {
     if config.verbose {
-        println!("Calculating weighted average vectors for {}", config.source);
+        eprintln!("Calculating weighted average vectors for {} in chunks of {}", config.source, vectorize::LOAD_CHUNK_SIZE);
+    }
+    let mut dataset_weighted_vectors = weights.iter().map(|_| Vec::new()).collect::<Vec<Vec<DatasetWeightedVector>>>();
+    let source = Vectors::load_chunked(dataset_vector_file, |documents| {
+        for (profile_vectors, weights) in dataset_weighted_vectors.iter_mut().zip(weights) {
+            profile_vectors.extend(precalc_weighted_average_vectors(config, &documents, weights));
+        }
+    });
+    for profile_vectors in dataset_weighted_vectors.iter_mut() {
+        profile_vectors.shrink_to_fit();
     }
-    // dataset_vectors.documents.iter()
-    dataset_vectors.documents.par_iter()
+    (source, dataset_weighted_vectors)
+}
+
+fn precalc_weighted_average_vectors(config: &Config, documents: &[Document], weights: &FxHashMap<String, f32>) -> Vec<DatasetWeightedVector> {
+    let parts = retained_parts(config);
+    documents.par_iter()
         .map(|document| {
-            let combined_vector = weighted_averaged_vector(&document, &weights);
+            let combined_vector = weighted_averaged_vector(document, weights, config.options.length_normalize, config.verbose);
             let dot = dot_product(&combined_vector, &combined_vector);
             DatasetWeightedVector {
                 id: document.id.clone(),
                 vector: combined_vector,
                 dot: dot.sqrt(),
+                part_vectors: part_vectors(document, &parts),
             }
         })
         .collect()
 }
 
-// Reads a zip file with json-files into Vec<JsonRecord>
-// via a Vec<JsonRecordLoader>
+// Input records in batches (one per JSON file or line) with the warnings from reading them
+type InputBatches<'a> = Box<dyn Iterator<Item = (Vec<(String, JsonRecord)>, Vec<zipfile::InputWarning>)> + 'a>;
+
+// Matches the JSON files of zip files (or directories) and the lines of JSON Lines files (.jsonl),
+// read one file or line at a time while matching (see zipfile::JsonFileInput).
 pub fn match_json_zip(config: &Config) {
-    let (prompt, records) = read_json_zip_file(config, &config.input);
-    let vocab = Vocab::load(&config.vocab_file);
-    let dataset_vectors = Vectors::load(&config.dataset_vector_file);
-    let source_data = source_data::SourceData::load(&config.source_data_file);
-    let source_data_records = source_data.records;
+    let start = Instant::now();
     let mut statistics = MatchStatistics::default();
+    let mut run_id = RunId::new(config);
+    let mut prompts = vec![];
+    let mut inputs: Vec<InputBatches> = vec![];
+    let mut total = Some(0);
+    for (input, namespace) in config.inputs.iter().zip(input_namespaces(&config.inputs)) {
+        if zipfile::is_json_lines(input) {
+            run_id.add_file(input);
+            total = None;
+            let batches = zipfile::read_json_lines(config, input, config.options.json_schema_version)
+                .map(move |(records, warnings)| (namespace_cards(namespace.as_deref(), records), warnings));
+            inputs.push(Box::new(batches));
+        } else {
+            let json_files = open_json_zip_file(config, input, &mut statistics.warnings);
+            if zipfile::is_directory(input) {
+                run_id.add_directory(input);
+            } else {
+                run_id.add_file(input);
+            }
+            total = total.map(|total| total + json_files.number_of_files());
+            prompts.push((input.clone(), json_files.prompt().to_string()));
+            let batches = json_files.records(config, config.options.json_schema_version)
+                .map(move |(records, warnings)| (namespace_cards(namespace.as_deref(), records), warnings));
+            inputs.push(Box::new(batches));
+        }
+    }
+    let input = inputs.into_iter().flatten();
+    let prompt = select_prompt(&prompts);
+    statistics.run_id = run_id.finish(&config.inputs);
+    if config.verbose {
+        eprintln!("Run id: {}", statistics.run_id);
+    }
+    let previous_records = output::json::previous_records(config);
+    let context = MatchContext::load(config);
     let mut output_records = Vec::new();
+    let batch_size = output_batch_size(config);
+    let mut output_writer = batch_size.and_then(|_| output::OutputWriter::open(config, Some(&statistics.run_id)));
 
-    let weights = vector_weights(config);
-    // let weights = unit_weights();
-    let dataset_weighted_vectors = precalc_weighted_average_vectors_for_source(config, &dataset_vectors, &weights);
-    
-    statistics.set_prompt(&prompt);
-    for (card, mut record) in records {
-        if config.options.add_author_to_title {
-            // If config.add_author_to_title is true, we add the author to the title
-            // This is used for matching with the source data
-            record.title = combine_title_and_author(&record.title, &record.author);
+    let mut candidates_dump = output::candidates::open_candidates_file(config);
+    let mut query_vectors_dump = output::query_vectors::open_query_vectors_file(config);
+    statistics.set_prompt(&read_prompt_file(config).unwrap_or(prompt));
+    let progress = progress_bar(config, total);
+    for (records, warnings) in input {
+        update_progress_bar(&progress, &statistics);
+        statistics.warnings.extend(warnings);
+        for (card, record) in records {
+            if let Some(previous_records) = &previous_records {
+                if previous_records.contains(&output::json::record_key(config, &card, record.edition)) {
+                    statistics.resumed_records += 1;
+                    continue;
+                }
+            }
+            let mut record_dump = RecordDump::default();
+            let record_result = match_record(config, &card, record, &context, &mut record_dump);
+            if let Some(dump) = &mut candidates_dump {
+                if !matches!(record_result.stats, MatchStat::Excluded | MatchStat::NoEdition | MatchStat::InvalidJSON | MatchStat::SkippedEmpty) {
+                    output::candidates::write_candidates(config, dump, &card, record_result.record.edition, &record_dump.candidates);
+                }
+            }
+            if let Some(dump) = &mut query_vectors_dump {
+                output::query_vectors::write_query_vectors(dump, &context.vocab, &card, record_result.record.edition, &record_dump.query_vectors);
+            }
+            statistics.update(&record_result.stats, &card);
+            output_records.push(record_result);
+            if let (Some(writer), Some(batch_size)) = (&mut output_writer, batch_size) {
+                if output_records.len() >= batch_size {
+                    writer.write_batch(config, &output_records);
+                    output_records.clear();
+                }
+            }
+        }
+    }
+    if let Some(progress) = &progress {
+        progress.finish();
+    }
+    check_resumed_records(config, previous_records.as_ref(), &statistics);
+    if let Some(mut dump) = candidates_dump {
+        dump.flush().unwrap();
+    }
+    if let Some(mut dump) = query_vectors_dump {
+        dump.flush().unwrap();
+    }
+    if let Some(mut writer) = output_writer {
+        writer.write_batch(config, &output_records);
+        writer.finish();
+    } else {
+        if config.options.merge_editions {
+            output_records = merge_editions(config, output_records);
         }
+        statistics.gold = report::evaluate_gold(config, &output_records);
+        output_records = cap_rows_per_card(config, output_records);
+        // Write output
+        output::output_records(config, &output_records, Some(&statistics));
+    }
+    // Write report.
+    report::output_report(config, &statistics);
+    if !config.options.quiet {
+        eprintln!("{}", summary_line(&statistics, start.elapsed().as_secs()));
+    }
+    check_nomatch_rate(config, &statistics);
+}
+
+// With -O resume-from, warn when none of the input records were in a non-empty previous output.
+// Cards are compared as they are named in the output, so the previous output was probably made from
+// other input, or with another json-schema-version (version 1 names cards by their path in the zip file,
+// later versions by the file name), and all records have been matched again.
+fn check_resumed_records(config: &Config, previous_records: Option<&FxHashSet<(String, usize)>>, statistics: &MatchStatistics) {
+    let Some(previous_records) = previous_records else {
+        return;
+    };
+    if !previous_records.is_empty() && statistics.resumed_records == 0 {
+        eprintln!("Warning: none of the {} records in the previous output {} are in the input, all records were matched",
+            previous_records.len(), config.options.resume_from.as_deref().unwrap_or_default());
+        eprintln!("Check that the previous output is from the same input and json-schema-version");
+    } else if config.verbose {
+        eprintln!("Skipped {} records already in the previous output", statistics.resumed_records);
+    }
+}
+
+// One line on how the run went, written to stderr after matching unless -O quiet, e.g.
+// "Matched 1200 records (1150 cards): single 61.5%, multiple 20.3%, no match 18.2%, elapsed 2m05s"
+// Exact matches (-O detect-exact) are shown after single when there are any.
+fn summary_line(statistics: &MatchStatistics, elapsed_seconds: u64) -> String {
+    let percent = |stat: &MatchStat| if statistics.number_of_records == 0 { 0.0 } else { statistics.match_stat_percent(stat) };
+    let exact = if statistics.match_stat(&MatchStat::ExactMatch) > 0 { format!(", exact {:.1}%", percent(&MatchStat::ExactMatch)) } else { "".to_string() };
+    format!("Matched {} records ({} cards): single {:.1}%{}, multiple {:.1}%, no match {:.1}%, elapsed {}",
+        statistics.number_of_records,
+        statistics.number_of_cards(),
+        percent(&MatchStat::SingleMatch),
+        exact,
+        percent(&MatchStat::MultipleMatches),
+        percent(&MatchStat::NoMatch),
+        build::format_elapsed(elapsed_seconds))
+}
+
+// Records per batch with -O output-batch-size. None (all records written at once) when an option
+// needs all records before writing: merge-editions, max-rows-per-card and gold-file.
+fn output_batch_size(config: &Config) -> Option<usize> {
+    let batch_size = config.options.output_batch_size?;
+    if config.options.merge_editions || config.options.max_rows_per_card.is_some() || config.options.gold_file.is_some() {
         if config.verbose {
-            print!("Processing record: {} {} => ", card, record.edition);
+            eprintln!("Output is written at once, merge-editions, max-rows-per-card and gold-file need all records");
         }
-        // Check if id is in input_excluded_ids of format
-        // jsonfilename:edition (as one string)
-        if input_is_excluded(config, &card, record.edition) {
-            if config.verbose {
-                println!("Excluded by id");
-            }
-            statistics.update(&MatchStat::Excluded, &card);
-            output_records.push(OutputRecord::new(config, &card, &record, &vec![], MatchStat::Excluded, &source_data_records));
-            continue;
+        return None;
+    }
+    Some(batch_size.max(1) as usize)
+}
+
+// Exit code when the no match percentage is above -O fail-if-nomatch-above (1 is used for errors)
+const FAIL_EXIT_CODE: i32 = 2;
+
+// Exit with FAIL_EXIT_CODE if the no match percentage is above fail-if-nomatch-above,
+// after the output and report are written
+fn check_nomatch_rate(config: &Config, statistics: &MatchStatistics) {
+    let Some(threshold) = config.options.fail_if_nomatch_above else {
+        return;
+    };
+    if statistics.number_of_records == 0 {
+        return;
+    }
+    let nomatch_percent = statistics.match_stat_percent(&MatchStat::NoMatch);
+    if nomatch_percent > threshold {
+        eprintln!("No match percentage {:.2} is above {} (fail-if-nomatch-above)", nomatch_percent, threshold);
+        std::process::exit(FAIL_EXIT_CODE);
+    }
+}
+
+// With several inputs, the card names are prefixed with the name of their input file
+// (without extension), or the full path when the names are not unique, e.g. "week12/001_1.json".
+// A single input keeps its card names as they are.
+fn input_namespaces(inputs: &[String]) -> Vec<Option<String>> {
+    if inputs.len() < 2 {
+        return vec![None; inputs.len()];
+    }
+    let strip_extension = |input: &str| {
+        let input = input.trim_end_matches('/');
+        input.strip_suffix(".zip").or_else(|| input.strip_suffix(".jsonl")).unwrap_or(input).to_string()
+    };
+    let names = inputs.iter().map(|input| {
+        let path = strip_extension(input);
+        path.rsplit('/').next().unwrap_or(&path).to_string()
+    }).collect::<Vec<String>>();
+    let unique = names.iter().collect::<FxHashSet<&String>>().len() == names.len();
+    if unique {
+        names.into_iter().map(Some).collect()
+    } else {
+        inputs.iter().map(|input| Some(strip_extension(input))).collect()
+    }
+}
+
+fn namespace_cards(namespace: Option<&str>, records: Vec<(String, JsonRecord)>) -> Vec<(String, JsonRecord)> {
+    match namespace {
+        Some(namespace) => records.into_iter().map(|(card, record)| (format!("{}/{}", namespace, card), record)).collect(),
+        None => records,
+    }
+}
+
+// The prompt of the first input (in the order given) that has one.
+// Inputs with a different prompt are reported, since the report only shows one prompt.
+fn select_prompt(prompts: &[(String, String)]) -> String {
+    let Some((selected_input, selected)) = prompts.iter().find(|(_, prompt)| !prompt.trim().is_empty()) else {
+        return String::new();
+    };
+    for (input, prompt) in prompts {
+        if !prompt.trim().is_empty() && prompt != selected {
+            eprintln!("Prompt in {} differs from the prompt in {}, using the prompt from {}", input, selected_input, selected_input);
         }
-        if record.edition == 9999999 {
-            if config.verbose {
-                println!("No edition");
-            }
-            statistics.update(&MatchStat::NoEdition, &card);
-            output_records.push(OutputRecord::new(config, &card, &record, &vec![], MatchStat::NoEdition, &source_data_records));
-            continue;
+    }
+    selected.clone()
+}
+
+// The prompt from -O prompt-file, which takes precedence over a .prompt file in the input
+fn read_prompt_file(config: &Config) -> Option<String> {
+    let filename = config.options.prompt_file.as_ref()?;
+    match std::fs::read_to_string(filename) {
+        Ok(prompt) => Some(prompt),
+        Err(e) => {
+            eprintln!("Failed to read prompt file {}: {}", filename, e);
+            std::process::exit(1);
         }
-        if record.edition == 9999998 {
-            if config.verbose {
-                println!("Invalid JSON");
+    }
+}
+
+// Data kept from matching a record for the sidecar files
+#[derive(Default)]
+pub struct RecordDump {
+    // The ranked candidates (before thresholds and TOP_N), if candidates-dump is set
+    pub candidates: Vec<MatchCandidate>,
+    // Weights profile name and the weighted average vector of the record, if dump-query-vectors is set
+    pub query_vectors: Vec<(String, Vec<(u32, f32)>)>,
+    // Rank of the known id of the card, if known-ids-file is set and has the card
+    pub expected_rank: Option<i32>,
+}
+
+// Match a single input record (one edition of a card), handling excluded records and the
+// no-edition/invalid-JSON sentinels. Data for the sidecar files is collected in `dump`.
+fn match_record(config: &Config, card: &str, mut record: JsonRecord, context: &MatchContext, dump: &mut RecordDump) -> OutputRecord {
+    let source_data_records = &context.source_data_records;
+    if config.options.add_author_to_title {
+        // If config.add_author_to_title is true, we add the author to the title
+        // This is used for matching with the source data
+        record.title = combine_title_and_author(&record.title, &record.author, config.options.author_normalize);
+    }
+    if config.verbose {
+        eprint!("Processing record: {} {} => ", card, record.edition);
+    }
+    // Check if id is in input_excluded_ids of format
+    // jsonfilename:edition (as one string)
+    if input_is_excluded(config, card, record.edition) {
+        if config.verbose {
+            eprintln!("Excluded by id");
+        }
+        return OutputRecord::new(config, card, &record, &vec![], MatchStat::Excluded, source_data_records);
+    }
+    if record.edition == 9999999 {
+        if config.verbose {
+            eprintln!("No edition");
+        }
+        return OutputRecord::new(config, card, &record, &vec![], MatchStat::NoEdition, source_data_records);
+    }
+    if record.edition == 9999998 {
+        if config.verbose {
+            eprintln!("Invalid JSON");
+        }
+        return OutputRecord::new(config, card, &record, &vec![], MatchStat::InvalidJSON, source_data_records);
+    }
+    // The title is checked after add-author-to-title, so with that option only records without title and author are skipped
+    if config.options.skip_empty_title && record.title.trim().is_empty() {
+        if config.verbose {
+            eprintln!("Skipped empty title");
+        }
+        return OutputRecord::new(config, card, &record, &[], MatchStat::SkippedEmpty, source_data_records);
+    }
+    let top = process_record_with_profiles(config, card, &record, context, dump);
+    let top_title = top.first().and_then(|candidate| source_data_records.get(&candidate.id)).map(|source_record| source_record.title.as_str());
+    let stats = exact_match_stat(config, &record.title, top_title, get_stats(config, &top));
+    if let Some(rank) = dump.expected_rank {
+        if config.verbose {
+            eprint!("expected rank {}, ", rank);
+        }
+    }
+    if config.verbose {
+        if let MatchStat::NoMatch = stats {
+            eprintln!("{}", stats.to_str());
+        } else {
+            let topmost_similarity = match top.first() {
+                Some(candidate) => candidate.similarity,
+                None => 0.0,
+            };
+            eprintln!("{} ({})", stats.to_str(), topmost_similarity);
+        }
+    }
+    let mut output_record = OutputRecord::new(config, card, &record, &top, stats, source_data_records);
+    output_record.expected_rank = dump.expected_rank;
+    output_record
+}
+
+// Weights and the dataset vectors weighted with them
+struct WeightsProfile {
+    name: String,
+    weights: FxHashMap<String, f32>,
+    dataset_weighted_vectors: Vec<DatasetWeightedVector>,
+}
+
+// Everything loaded and precalculated before records can be matched
+pub struct MatchContext {
+    vocab: Vocab,
+    // One profile with the weights from the options, or one per profile in -O weights-profiles
+    profiles: Vec<WeightsProfile>,
+    source_data_records: FxHashMap<String, SourceRecord>,
+    // Source record ids excluded from matching (-O exclude-file)
+    excluded_ids: FxHashSet<String>,
+}
+
+impl MatchContext {
+    pub fn load(config: &Config) -> MatchContext {
+        let vocab = Vocab::load(&config.vocab_file);
+        vocab.check_metadata(config);
+        let profile_weights = weights_profiles(config);
+        let weights = profile_weights.iter().map(|(_, weights)| weights).collect::<Vec<&FxHashMap<String, f32>>>();
+        // With several vectors files (-D a.bin -D b.bin), their documents are matched as one dataset
+        let mut dataset_weighted_vectors = weights.iter().map(|_| Vec::new()).collect::<Vec<Vec<DatasetWeightedVector>>>();
+        let mut dataset_vectors_sources = Vec::new();
+        for dataset_vector_file in &config.dataset_vector_files {
+            let (source, file_weighted_vectors) = load_weighted_vectors(config, dataset_vector_file, &weights);
+            check_vocab_indexes(&vocab, dataset_vector_file, &file_weighted_vectors[0]);
+            for (profile_vectors, file_profile_vectors) in dataset_weighted_vectors.iter_mut().zip(file_weighted_vectors) {
+                profile_vectors.extend(file_profile_vectors);
             }
-            statistics.update(&MatchStat::InvalidJSON, &card);
-            output_records.push(OutputRecord::new(config, &card, &record, &vec![], MatchStat::InvalidJSON, &source_data_records));
+            dataset_vectors_sources.push((dataset_vector_file.as_str(), source));
+        }
+        let profiles = profile_weights.into_iter().zip(dataset_weighted_vectors)
+            .map(|((name, weights), dataset_weighted_vectors)| WeightsProfile { name, weights, dataset_weighted_vectors })
+            .collect();
+        let source_data = config.source_data_files.iter()
+            .map(|source_data_file| (source_data_file.as_str(), source_data::SourceData::load(source_data_file)))
+            .collect::<Vec<(&str, source_data::SourceData)>>();
+        let source_data_sources = source_data.iter().map(|(file, source_data)| (*file, source_data.source.clone())).collect::<Vec<(&str, String)>>();
+        check_sources(config, &vocab, &dataset_vectors_sources, &source_data_sources);
+        let source_data_records = source_data.into_iter()
+            .flat_map(|(_, source_data)| source_data.records)
+            .collect::<FxHashMap<String, SourceRecord>>();
+        let excluded_ids = resolve_excluded_ids(config, &source_data_records);
+        MatchContext {
+            vocab,
+            profiles,
+            source_data_records,
+            excluded_ids,
+        }
+    }
+
+    // Match all editions of one JSON file (given as its content) and return the output records
+    pub fn match_json(&self, config: &Config, card: &str, content: &str) -> Vec<OutputRecord> {
+        // Input warnings are only collected for the report of match-json-zip
+        let records = zipfile::convert_json_file(config, card, content, config.options.json_schema_version, &mut Vec::new());
+        let mut output_records = Vec::new();
+        for (card, record) in records {
+            output_records.push(match_record(config, &card, record, self, &mut RecordDump::default()));
+        }
+        if config.options.merge_editions {
+            output_records = merge_editions(config, output_records);
+        }
+        output_records
+    }
+}
+
+// The source and the weighted vectors (one list per weights profile) of one vectors file,
+// from its cache if the cache is for the same weights
+fn load_weighted_vectors(config: &Config, dataset_vector_file: &str, weights: &[&FxHashMap<String, f32>]) -> (String, Vec<Vec<DatasetWeightedVector>>) {
+    let cache_key = if config.options.no_vector_cache { None } else { vector_cache::cache_key(config, dataset_vector_file, weights, &retained_parts(config)) };
+    if let Some(cached) = cache_key.as_ref().and_then(|key| vector_cache::load(config, dataset_vector_file, key)) {
+        return cached;
+    }
+    let (source, dataset_weighted_vectors) = if config.options.lazy_vectors {
+        precalc_weighted_average_vectors_chunked(config, dataset_vector_file, weights)
+    } else {
+        let dataset_vectors = Vectors::load(dataset_vector_file);
+        let dataset_weighted_vectors = precalc_weighted_average_vectors_for_source(config, &dataset_vectors, weights);
+        (dataset_vectors.source, dataset_weighted_vectors)
+    };
+    if let Some(key) = &cache_key {
+        vector_cache::save(config, dataset_vector_file, key, &source, &dataset_weighted_vectors);
+    }
+    (source, dataset_weighted_vectors)
+}
+
+// Vectors built with another vocab of the same source pass the source check, but their word
+// indexes do not belong to this vocab. Indexes beyond the end of the vocab at least show that.
+fn check_vocab_indexes(vocab: &Vocab, dataset_vector_file: &str, dataset_weighted_vectors: &[DatasetWeightedVector]) {
+    let outside = dataset_weighted_vectors.iter()
+        .any(|document| document.vector.iter().any(|(index, _)| *index as usize >= vocab.words.len()));
+    if outside {
+        eprintln!("Dataset vectors file {} has words that are not in the vocab, it is built with another vocab", dataset_vector_file);
+        eprintln!("Rebuild the vectors files with the same vocab file");
+        std::process::exit(1);
+    }
+}
+
+// The excluded source record ids: the ids from plain exclude files as they are, and the external ids
+// from two-column exclude files translated to internal ids by matching them against exclude-id-field
+fn resolve_excluded_ids(config: &Config, source_data_records: &FxHashMap<String, SourceRecord>) -> FxHashSet<String> {
+    let mut excluded_ids = config.options.excluded_ids.iter().cloned().collect::<FxHashSet<String>>();
+    if config.options.excluded_external_ids.is_empty() {
+        return excluded_ids;
+    }
+    let external_ids = config.options.excluded_external_ids.iter().map(|id| id.as_str()).collect::<FxHashSet<&str>>();
+    let mut found = FxHashSet::default();
+    for source_record in source_data_records.values() {
+        let value = source_record.field_value(&config.options.exclude_id_field, &config.options.matched_id_sep);
+        if external_ids.contains(value) {
+            excluded_ids.insert(source_record.id.clone());
+            found.insert(value);
+        }
+    }
+    let not_found = external_ids.len() - found.len();
+    if not_found > 0 {
+        eprintln!("Warning: {} of {} external ids in exclude files not found in source data ({})", not_found, external_ids.len(), config.options.exclude_id_field);
+    }
+    if config.verbose {
+        eprintln!("Excluding {} source records from {} external ids", excluded_ids.len() - config.options.excluded_ids.len(), external_ids.len());
+    }
+    excluded_ids
+}
+
+// The vocab, vectors and source data files must all be built from the same source,
+// otherwise the vector indexes and ids do not belong together and the results are meaningless.
+// Each of several vectors and source data files is checked.
+fn check_sources(config: &Config, vocab: &Vocab, dataset_vectors_sources: &[(&str, String)], source_data_sources: &[(&str, String)]) {
+    if dataset_vectors_sources.iter().chain(source_data_sources).all(|(_, source)| *source == vocab.source) {
+        return;
+    }
+    let mut message = format!("The data files are built from different sources:\n  vocab {}: {}", config.vocab_file, vocab.source);
+    for (file, source) in dataset_vectors_sources {
+        message.push_str(&format!("\n  dataset vectors {}: {}", file, source));
+    }
+    for (file, source) in source_data_sources {
+        message.push_str(&format!("\n  source data {}: {}", file, source));
+    }
+    if config.options.skip_source_check {
+        eprintln!("Warning: {}", message);
+        return;
+    }
+    eprintln!("{}", message);
+    eprintln!("Rebuild the files from the same source, or use -O skip-source-check to match anyway");
+    std::process::exit(1);
+}
+
+// Match the editions of a single JSON record read from the input file (or stdin if no input is given)
+// and write the result as JSON to stdout. Vocab, vectors and source data are loaded on every call.
+pub fn match_single_json(config: &Config) {
+    let (card, content) = read_single_json_input(config);
+    let context = MatchContext::load(config);
+    let output_records = context.match_json(config, &card, &content);
+    output::output_records(config, &output_records, None);
+}
+
+// Number of records matched by self-check unless -O self-check-sample is given
+const SELF_CHECK_SAMPLE: usize = 100;
+
+// Match a sample of the source records against the dataset, as if each was an input record,
+// and count how many have their own id as the top candidate (-c self-check).
+// The sample is evenly spread over the dataset vectors, so the same files always give the same sample.
+// A low rate means that vocab, vectors and weights do not fit together.
+pub fn self_check(config: &Config) {
+    let context = MatchContext::load(config);
+    let sample_size = config.options.self_check_sample.map(|size| size.max(1) as usize).unwrap_or(SELF_CHECK_SAMPLE);
+    let ids = context.profiles[0].dataset_weighted_vectors.iter()
+        .map(|document| document.id.clone())
+        .filter(|id| !context.excluded_ids.contains(id))
+        .collect::<Vec<String>>();
+    let step = (ids.len() / sample_size).max(1);
+    let mut checked = 0;
+    let mut own_top = 0;
+    for id in ids.iter().step_by(step).take(sample_size) {
+        let Some(source_record) = context.source_data_records.get(id) else {
             continue;
+        };
+        let record = JsonRecord {
+            edition: 0,
+            title: source_record.title.clone(),
+            author: source_record.author.clone(),
+            location: source_record.location.clone(),
+            year: source_record.year.clone(),
+            publication_type: source_record.publication_type.clone(),
+            allowed_years: vec![],
+            schema_version: 1,
+        };
+        let top = process_record_with_profiles(config, id, &record, &context, &mut RecordDump::default());
+        checked += 1;
+        match top.first() {
+            Some(candidate) if candidate.id == *id => own_top += 1,
+            Some(candidate) if config.verbose => println!("{}: top candidate is {} ({})", id, candidate.id, candidate.similarity),
+            None if config.verbose => println!("{}: no match", id),
+            _ => {},
         }
-        let top = process_record(&config, &record, &vocab, &dataset_weighted_vectors, &weights, &source_data_records);
-        let stats = get_stats(&config, &top);
-        if config.verbose {
-            if let MatchStat::NoMatch = stats {
-                println!("{}", stats.to_str());
-            } else {
-                let topmost_similarity = match top.first() {
-                    Some(candidate) => candidate.similarity,
-                    None => 0.0,
-                };
-                println!("{} ({})", stats.to_str(), topmost_similarity);
+    }
+    if checked == 0 {
+        eprintln!("No source records to check, the source data has none of the dataset vector ids");
+        std::process::exit(1);
+    }
+    println!("Self check: {} of {} records ({:.1}%) have their own id as the top candidate", own_top, checked, own_top as f64 / checked as f64 * 100.0);
+}
+
+// The JSON content and its card name (the file name, or "stdin.json")
+fn read_single_json_input(config: &Config) -> (String, String) {
+    if config.input.is_empty() || config.input == "-" {
+        let mut content = String::new();
+        if let Err(e) = std::io::stdin().read_to_string(&mut content) {
+            eprintln!("Failed to read JSON record from stdin: {}", e);
+            std::process::exit(1);
+        }
+        return ("stdin.json".to_string(), content);
+    }
+    match std::fs::read_to_string(&config.input) {
+        Ok(content) => {
+            let card = std::path::Path::new(&config.input).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or(config.input.clone());
+            (card, content)
+        },
+        Err(e) => {
+            eprintln!("Failed to read JSON record from {}: {}", config.input, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Progress bar over the input records, shown on stderr.
+// Not used with verbose output (which prints one line per record instead), when writing to stdout
+// or when stderr is not a terminal, so that piped output is not corrupted.
+// Progress bar on stderr, with an ETA only when the number of records is known
+fn progress_bar(config: &Config, total: Option<usize>) -> Option<ProgressBar> {
+    if config.verbose || !std::io::stderr().is_terminal() {
+        return None;
+    }
+    if let output::Output::Stdout = config.output {
+        return None;
+    }
+    let (progress, template) = match total {
+        Some(total) => (ProgressBar::new(total as u64), "{bar:40} {pos}/{len} [{elapsed_precise} / ETA {eta}] {msg}"),
+        None => (ProgressBar::no_length(), "{spinner} {pos} [{elapsed_precise}] {msg}"),
+    };
+    let style = ProgressStyle::with_template(template)
+        .unwrap_or_else(|_| ProgressStyle::default_bar());
+    progress.set_style(style);
+    Some(progress)
+}
+
+// Advance the progress bar one input file (or line) and show the running single match rate
+fn update_progress_bar(progress: &Option<ProgressBar>, statistics: &MatchStatistics) {
+    if let Some(progress) = progress {
+        if statistics.number_of_records > 0 {
+            progress.set_message(format!("Single: {:.1}%", statistics.match_stat_percent(&MatchStat::SingleMatch)));
+        }
+        progress.inc(1);
+    }
+}
+
+// Merge the output records of all editions of a card into a single output record.
+// The top candidates are combined, keeping the best similarity for each source id, and the
+// match stat is recalculated from the combined candidates. Cards without any candidates keep
+// their stat if all editions agree, otherwise they are NoMatch.
+// Statistics are not affected, they are still counted per edition.
+fn merge_editions(config: &Config, output_records: Vec<OutputRecord>) -> Vec<OutputRecord> {
+    let mut merged_records: Vec<OutputRecord> = vec![];
+    let mut card_index: FxHashMap<String, usize> = FxHashMap::default();
+    for output_record in output_records {
+        if let Some(index) = card_index.get(&output_record.card) {
+            let merged = &mut merged_records[*index];
+            for candidate in output_record.top {
+                match merged.top.iter_mut().find(|existing| existing.id == candidate.id) {
+                    Some(existing) => {
+                        if candidate.similarity > existing.similarity {
+                            *existing = candidate;
+                        }
+                    },
+                    None => merged.top.push(candidate),
+                }
+            }
+            if merged.stats != output_record.stats {
+                merged.stats = MatchStat::NoMatch;
             }
+            merged.expected_rank = best_expected_rank(merged.expected_rank, output_record.expected_rank);
+        } else {
+            card_index.insert(output_record.card.clone(), merged_records.len());
+            merged_records.push(output_record);
         }
-        statistics.update(&stats, &card);
-        let record_result = OutputRecord::new(config, &card, &record, &top, stats, &source_data_records);
-        output_records.push(record_result);
     }
-    // Write output
-    output::output_records(&config, &output_records);
-    // Write report.
-    report::output_report(config, &statistics);
+    for merged in merged_records.iter_mut() {
+        merged.top.sort_by(|a, b| b.similarity.partial_cmp(&a.similarity).unwrap());
+        merged.top.truncate(TOP_N);
+        if !merged.top.is_empty() {
+            let top_title = merged.top[0].source_record.as_ref().map(|source_record| source_record.title.as_str());
+            merged.stats = exact_match_stat(config, &merged.record.title, top_title, get_stats(config, &merged.top));
+        }
+    }
+    merged_records
+}
+
+// The best (lowest) rank of two editions, where -1 (absent) is worse than any rank
+fn best_expected_rank(rank1: Option<i32>, rank2: Option<i32>) -> Option<i32> {
+    match (rank1, rank2) {
+        (Some(rank1), Some(rank2)) if rank1 < 0 || rank2 < 0 => Some(rank1.max(rank2)),
+        (Some(rank1), Some(rank2)) => Some(rank1.min(rank2)),
+        (rank1, rank2) => rank1.or(rank2),
+    }
+}
+
+// Keep the max-rows-per-card candidates with the highest similarity over all editions of each card.
+// Records without candidates keep their row, editions left without candidates by the cap are removed.
+fn cap_rows_per_card(config: &Config, output_records: Vec<OutputRecord>) -> Vec<OutputRecord> {
+    let Some(max_rows) = config.options.max_rows_per_card else {
+        return output_records;
+    };
+    // (record index, candidate index, similarity) of the candidates of each card
+    let mut card_candidates: FxHashMap<&str, Vec<(usize, usize, f32)>> = FxHashMap::default();
+    for (record_index, output_record) in output_records.iter().enumerate() {
+        for (candidate_index, candidate) in output_record.top.iter().enumerate() {
+            card_candidates.entry(&output_record.card).or_default().push((record_index, candidate_index, candidate.similarity));
+        }
+    }
+    let mut kept = FxHashSet::default();
+    for candidates in card_candidates.values_mut() {
+        candidates.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap());
+        kept.extend(candidates.iter().take(max_rows.max(0) as usize).map(|(record_index, candidate_index, _)| (*record_index, *candidate_index)));
+    }
+    output_records.into_iter().enumerate().filter_map(|(record_index, mut output_record)| {
+        if output_record.top.is_empty() {
+            return Some(output_record);
+        }
+        let mut candidate_index = 0;
+        output_record.top.retain(|_| {
+            let keep = kept.contains(&(record_index, candidate_index));
+            candidate_index += 1;
+            keep
+        });
+        if output_record.top.is_empty() { None } else { Some(output_record) }
+    }).collect()
 }
 
 fn input_is_excluded(config: &Config, card: &str, edition: usize) -> bool {
@@ -304,12 +1095,12 @@ fn get_stats(config: &Config, top: &[MatchCandidate]) -> MatchStat {
 
             if let Some(min_multiple_similarity) = config.options.min_multiple_similarity {
                 if top.iter().all(|candidate| candidate.similarity >= min_multiple_similarity) {
-                    MatchStat::MultipleMatches
+                    multiple_match_stat(config, top)
                 } else {
                     MatchStat::UnqualifiedMultipleMatches
                 }
             } else {
-                MatchStat::MultipleMatches
+                multiple_match_stat(config, top)
             }
         }
     } else {
@@ -317,44 +1108,176 @@ fn get_stats(config: &Config, top: &[MatchCandidate]) -> MatchStat {
     }
 }
 
-fn process_record(config: &Config, record: &JsonRecord, vocab: &Vocab, dataset_vectors: &[DatasetWeightedVector], weights: &FxHashMap<String, f32>, source_data_records: &FxHashMap<String, SourceRecord>) -> Vec<MatchCandidate> {
+// With -O detect-exact, a single match is Exact when the title of the candidate is the title of the card,
+// compared after the normalization of the tokenizer (case and punctuation) with the whitespace collapsed.
+// Any other stats are kept as they are.
+fn exact_match_stat(config: &Config, title: &str, top_title: Option<&str>, stats: MatchStat) -> MatchStat {
+    if !config.options.detect_exact || stats != MatchStat::SingleMatch {
+        return stats;
+    }
+    match top_title {
+        Some(top_title) if same_title(title, top_title) => MatchStat::ExactMatch,
+        _ => stats,
+    }
+}
+
+fn same_title(title: &str, other: &str) -> bool {
+    let normalized = |title: &str| tokenizer::normalize(title).split_whitespace().collect::<Vec<&str>>().join(" ");
+    let title = normalized(title);
+    !title.is_empty() && title == normalized(other)
+}
+
+// MultipleMatches, or Ambiguous if ambiguous-spread is set and the similarities
+// of the two best candidates are closer than the spread
+fn multiple_match_stat(config: &Config, top: &[MatchCandidate]) -> MatchStat {
+    let Some(spread) = config.options.ambiguous_spread else {
+        return MatchStat::MultipleMatches;
+    };
+    let mut similarities = top.iter().map(|candidate| candidate.similarity).collect::<Vec<f32>>();
+    similarities.sort_by(|a, b| b.partial_cmp(a).unwrap());
+    if similarities[0] - similarities[1] < spread {
+        MatchStat::Ambiguous
+    } else {
+        MatchStat::MultipleMatches
+    }
+}
+
+// Match the record with each weights profile and keep the candidates of the profile with the best
+// top candidate (highest similarity, then highest z-score). The name of that profile is set on its candidates.
+// With a single profile (no -O weights-profiles) this is the same as process_record.
+fn process_record_with_profiles(config: &Config, card: &str, record: &JsonRecord, context: &MatchContext, dump: &mut RecordDump) -> Vec<MatchCandidate> {
+    if let [profile] = context.profiles.as_slice() {
+        return process_record(config, card, record, context, profile, dump);
+    }
+    let mut best: Option<(Vec<MatchCandidate>, RecordDump)> = None;
+    for profile in &context.profiles {
+        let mut profile_dump = RecordDump::default();
+        let mut top = process_record(config, card, record, context, profile, &mut profile_dump);
+        // The query vectors of all profiles are kept, the candidates and expected rank only for the best profile
+        dump.query_vectors.append(&mut profile_dump.query_vectors);
+        for candidate in top.iter_mut().chain(profile_dump.candidates.iter_mut()) {
+            candidate.weights_profile = profile.name.clone();
+        }
+        let is_better = match (&best, top.first()) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some((best_top, _)), Some(candidate)) => match best_top.first() {
+                Some(best_candidate) => (candidate.similarity, candidate.zscore) > (best_candidate.similarity, best_candidate.zscore),
+                None => true,
+            },
+        };
+        if is_better {
+            best = Some((top, profile_dump));
+        }
+    }
+    let (top, best_dump) = best.unwrap_or_default();
+    dump.candidates = best_dump.candidates;
+    dump.expected_rank = best_dump.expected_rank;
+    top
+}
+
+// The ranked candidates (before thresholds and TOP_N) are copied to `dump` if candidates-dump is set,
+// the weighted average vector of the record if dump-query-vectors is set
+// and the rank of the known id of the card if known-ids-file is set
+fn process_record(config: &Config, card: &str, record: &JsonRecord, context: &MatchContext, profile: &WeightsProfile, dump: &mut RecordDump) -> Vec<MatchCandidate> {
+    let vocab = &context.vocab;
+    let dataset_vectors = &profile.dataset_weighted_vectors;
+    let source_data_records = &context.source_data_records;
     // Tokenize each of author, title, location, year and combined (all)
     // Calculate the tf-idf for each word in each part
     // There should be a tf-idf vector for each part
-    let input_document = vectorize::process_record(&record.into(), vocab);
-    let input_combined_vector = weighted_averaged_vector(&input_document, &weights);
+    let input_document = vectorize::process_record(config, &record.into(), vocab);
+    let all_fallback = uses_all_fallback(config, &input_document);
+    let input_combined_vector = if all_fallback {
+        if config.verbose {
+            eprintln!("Record {} has few parts, matching on the all part (all-fallback)", card);
+        }
+        input_document.vectors.get("all").cloned().unwrap_or_default()
+    } else {
+        weighted_averaged_vector(&input_document, &profile.weights, config.options.length_normalize, config.verbose)
+    };
     let self_dot = dot_product(&input_combined_vector, &input_combined_vector).sqrt();
+    if config.options.dump_query_vectors.is_some() {
+        dump.query_vectors.push((profile.name.clone(), input_combined_vector.clone()));
+    }
+    let input_parts = InputParts {
+        vectors: part_vectors(&input_document, &retained_parts(config)),
+        all_fallback,
+    };
     // Now we loop over all the dataset vectors and calculate the cosine similarity for their weighted average vector
     // We will keep the TOP_N most similar vectors
     // let mut top_n: Vec<(String, f32)> = dataset_vectors.iter()
     let mut top_n: Vec<MatchCandidate> = dataset_vectors.par_iter()
-        .map(|document| {
-            process_one_item(config, &input_combined_vector, self_dot, record, document, source_data_records)
+        .enumerate()
+        .map(|(document_index, document)| {
+            let mut candidate = process_one_item(config, &input_combined_vector, self_dot, &input_parts, record, document, context);
+            candidate.document_index = document_index;
+            candidate
         })
         .collect();
     top_n.sort_by(|a, b| b.similarity.partial_cmp(&a.similarity).unwrap());
-    // Keep only the top N*10 (used for Z-scores)
-    top_n.truncate(TOP_N*20);
+    if let Some(known_id) = config.options.known_ids.get(&gold_card_name(card)) {
+        dump.expected_rank = Some(expected_rank(config, &top_n, known_id));
+    }
+    // Card name if the filters are traced for this record (-O trace-filtering)
+    let trace = traced_card(config, card);
+    // Keep only the top candidates used for Z-scores
+    truncate_traced(trace, &mut top_n, Z_SCORE_POOL, FilterStage::Pool);
     // Apply overlap score to each top_n item (only if option is set)
     apply_overlap_score(config, &mut top_n, &record, source_data_records);
     // Apply Jaro-Winkler to each top_n item (only if option is set)
     apply_jaro_winkler(config, &mut top_n, &record, source_data_records);
+    // Apply the re-rank table multipliers (only if option is set)
+    apply_rerank_table(config, &mut top_n, card);
+    // Transform the similarities (only if option is set)
+    apply_score_transform(config.options.score_transform, &mut top_n);
+    apply_transformed_similarity_threshold(config, &mut top_n);
+    // With adaptive-z, a flat pool (short cards) is not held to the z-threshold
+    let flat_pool = config.options.adaptive_z.is_some_and(|floor| pool_spread(&top_n) < floor);
+    // With min-pool-for-z, a pool too small for meaningful z-scores is ranked on the similarity alone
+    let small_pool = below_min_pool(top_n.len(), config.options.min_pool_for_z);
     // Calculate z-scores for the top N*10
-    let mut z_scores = calculate_z_scores(top_n);
+    let mut z_scores = pool_z_scores(top_n, config.options.zscore_method, config.options.min_pool_for_z);
     // Sort by z-score and keep the top N
     z_scores.sort_by(|a, b| b.zscore.partial_cmp(&a.zscore).unwrap());
+    if let Some(dump_size) = config.options.candidates_dump {
+        dump.candidates = z_scores.iter().take(dump_size.max(0) as usize).cloned().collect();
+    }
     // If z-threshold is set, filter out all below the threshold
     if let Some(z_threshold) = config.options.z_threshold {
-        z_scores.retain(|candidate| candidate.zscore > z_threshold);
+        if small_pool {
+            if config.verbose {
+                eprintln!("Candidate pool of {} is too small for z-scores, z-threshold not applied", card);
+            }
+        } else if flat_pool {
+            if config.verbose {
+                eprintln!("Candidate pool of {} is flat, z-threshold not applied", card);
+            }
+        } else {
+            retain_traced(trace, &mut z_scores, FilterStage::ZThreshold, |candidate| candidate.zscore > z_threshold);
+        }
+    }
+    truncate_traced(trace, &mut z_scores, TOP_N, FilterStage::TopN);
+    for candidate in z_scores.iter_mut() {
+        let document = &dataset_vectors[candidate.document_index];
+        let compared = document.compared_vector(all_fallback);
+        candidate.ngram_jaccard = ngram_jaccard(&input_combined_vector, compared.vector);
+        if config.options.output_part_similarities {
+            candidate.part_similarities = SIMILARITY_PARTS.iter()
+                .map(|part| part_similarity(&input_parts.vectors[*part], document.part_vectors.get(*part)))
+                .collect();
+        }
+        if config.options.explain {
+            candidate.explanation = explain_similarity(vocab, &input_combined_vector, self_dot, compared);
+        }
     }
-    z_scores.truncate(TOP_N);
     // Filter all where similarity is 0.0
-    z_scores.retain(|candidate| candidate.similarity > 0.0);
+    retain_traced(trace, &mut z_scores, FilterStage::ZeroSimilarity, |candidate| candidate.similarity > 0.0);
     // Filter all where similarity is below similarity_threshold and if overlap_adjustment or jaro_winkler_adjustment is set
     if let Some(similarity_threshold) = config.options.similarity_threshold {
         match (config.options.overlap_adjustment, config.options.jaro_winkler_adjustment) {
             (Some(_), _) | (_, true) => {
-                z_scores.retain(|candidate| candidate.similarity >= similarity_threshold);
+                retain_traced(trace, &mut z_scores, FilterStage::AdjustedSimilarityThreshold, |candidate| candidate.similarity >= similarity_threshold);
             },
             _ => {}
         }
@@ -363,27 +1286,140 @@ fn process_record(config: &Config, record: &JsonRecord, vocab: &Vocab, dataset_v
     z_scores
 }
 
-fn process_one_item(config: &Config, input_combined_vector: &[(u32, f32)], self_dot: f32, record: &JsonRecord, document: &DatasetWeightedVector, source_data_records: &FxHashMap<String, SourceRecord>) -> MatchCandidate {
-    if config.options.excluded_ids.contains(&document.id) {
-        MatchCandidate::new(&document.id, 0.0) // Exclude this id by setting similarity to 0.0
+// Maximum number of removed candidates logged per filter and record with -O trace-filtering
+const TRACE_CANDIDATES: usize = 10;
+
+// With -O trace-filtering=N, one in N records (picked by a hash of the card name, so the same
+// records are traced in every run) or every record with -v
+fn traced_card<'a>(config: &Config, card: &'a str) -> Option<&'a str> {
+    let every = config.options.trace_filtering?;
+    if config.verbose || stable_hash(card.as_bytes()).is_multiple_of(every.max(1) as u64) {
+        Some(card)
+    } else {
+        None
+    }
+}
+
+// Same as retain, logging the removed candidates when traced
+fn retain_traced(trace: Option<&str>, candidates: &mut Vec<MatchCandidate>, stage: FilterStage, keep: impl Fn(&MatchCandidate) -> bool) {
+    let Some(card) = trace else {
+        candidates.retain(keep);
+        return;
+    };
+    let (kept, dropped): (Vec<MatchCandidate>, Vec<MatchCandidate>) = std::mem::take(candidates).into_iter().partition(|candidate| keep(candidate));
+    *candidates = kept;
+    for line in trace_lines(card, stage, &dropped) {
+        eprintln!("{}", line);
+    }
+}
+
+// Same as truncate, logging the removed candidates when traced
+fn truncate_traced(trace: Option<&str>, candidates: &mut Vec<MatchCandidate>, len: usize, stage: FilterStage) {
+    if let Some(card) = trace {
+        if candidates.len() > len {
+            for line in trace_lines(card, stage, &candidates[len..]) {
+                eprintln!("{}", line);
+            }
+        }
+    }
+    candidates.truncate(len);
+}
+
+// One line per removed candidate (up to TRACE_CANDIDATES). Candidates that process_one_item gave
+// similarity 0.0 are removed by whichever filter comes first, so the reason they got 0.0 is given instead.
+fn trace_lines(card: &str, stage: FilterStage, dropped: &[MatchCandidate]) -> Vec<String> {
+    let mut lines = dropped.iter().take(TRACE_CANDIDATES).map(|candidate| {
+        format!("Trace {}: {} (similarity {:.4}, z-score {:.4}) removed by {}",
+            card, candidate.id, candidate.similarity, candidate.zscore, candidate.dropped_by.unwrap_or(stage))
+    }).collect::<Vec<String>>();
+    if dropped.len() > TRACE_CANDIDATES {
+        lines.push(format!("Trace {}: {} more candidates removed by {}", card, dropped.len() - TRACE_CANDIDATES, stage));
+    }
+    lines
+}
+
+// 1-based rank of the known id among the candidates sorted by similarity (before any truncation),
+// -1 if it is not among them or has similarity 0.0 (excluded or no common tokens)
+fn expected_rank(config: &Config, candidates: &[MatchCandidate], known_id: &str) -> i32 {
+    candidates.iter()
+        .take_while(|candidate| candidate.similarity > 0.0)
+        .position(|candidate| report::is_gold_id(config, &candidate.id, known_id))
+        .map_or(-1, |index| index as i32 + 1)
+}
+
+fn process_one_item(config: &Config, input_combined_vector: &[(u32, f32)], self_dot: f32, input_parts: &InputParts, record: &JsonRecord, document: &DatasetWeightedVector, context: &MatchContext) -> MatchCandidate {
+    let source_data_records = &context.source_data_records;
+    let excluded = if context.excluded_ids.contains(&document.id) {
+        Some(FilterStage::Excluded)
+    } else if config.options.require_type_match && publication_type_mismatch(record, source_data_records.get(&document.id)) {
+        Some(FilterStage::TypeMismatch)
+    } else if excluded_where(config, source_data_records.get(&document.id)) {
+        Some(FilterStage::ExcludeWhere)
     } else {
-        let mut similarity = calculate_similarity_score(config, record, source_data_records.get(&document.id), input_combined_vector, self_dot, document);
-        if let Some(threshold) = config.options.similarity_threshold {
-            if similarity < threshold {
+        None
+    };
+    if excluded.is_some() {
+        let mut candidate = MatchCandidate::new(&document.id, 0.0); // Exclude this id by setting similarity to 0.0
+        candidate.dropped_by = excluded;
+        candidate
+    } else {
+        let compared = document.compared_vector(input_parts.all_fallback);
+        let mut similarity = calculate_similarity_score(config, record, source_data_records.get(&document.id), input_combined_vector, self_dot, compared);
+        similarity *= empty_author_factor(config.options.empty_author_penalty, record, source_data_records.get(&document.id));
+        let mut dropped_by = None;
+        // With -O score-transform the threshold applies to the transformed similarity, see apply_transformed_similarity_threshold
+        if let Some(threshold) = config.options.similarity_threshold.filter(|_| config.options.score_transform == ScoreTransform::None) {
+            if similarity > 0.0 && similarity < threshold {
                 similarity = 0.0;
+                dropped_by = Some(FilterStage::SimilarityThreshold);
             }
         }
-        MatchCandidate::new(&document.id.clone(), similarity)
+        if similarity > 0.0 && below_part_thresholds(config, &input_parts.vectors, document) {
+            similarity = 0.0;
+            dropped_by = Some(FilterStage::PartThreshold);
+        }
+        let mut candidate = MatchCandidate::new(&document.id.clone(), similarity);
+        candidate.dropped_by = dropped_by;
+        candidate
     }
 }
 
-fn calculate_similarity_score(config: &Config, record: &JsonRecord, source_record_opt: Option<&SourceRecord>, input_combined_vector: &[(u32, f32)], self_dot: f32, document: &DatasetWeightedVector) -> f32 {
+// An empty author on the card only leaves the author part out of the weighted average, so a source record
+// with an author is not held against it. With -O empty-author-penalty, cards without author prefer
+// source records without author by taking this share off the similarity of the others.
+fn empty_author_factor(penalty: f32, record: &JsonRecord, source_record: Option<&SourceRecord>) -> f32 {
+    match source_record {
+        Some(source_record) if penalty > 0.0 && record.author.trim().is_empty() && !source_record.author.trim().is_empty() => 1.0 - penalty,
+        _ => 1.0,
+    }
+}
+
+// Any of the -O exclude-where predicates is true for the source record
+fn excluded_where(config: &Config, source_record: Option<&SourceRecord>) -> bool {
+    let Some(source_record) = source_record else {
+        return false;
+    };
+    config.options.exclude_where.iter().any(|predicate| predicate.matches(source_record, &config.options.matched_id_sep))
+}
+
+// The card and the source record have different publication types (compared without case).
+// Never a mismatch when either type is unknown, so source data built without publication-type-field is not affected.
+fn publication_type_mismatch(record: &JsonRecord, source_record: Option<&SourceRecord>) -> bool {
+    let Some(source_record) = source_record else {
+        return false;
+    };
+    let card_type = record.publication_type.trim();
+    let source_type = source_record.publication_type.trim();
+    !card_type.is_empty() && !source_type.is_empty() && !card_type.eq_ignore_ascii_case(source_type)
+}
+
+fn calculate_similarity_score(config: &Config, record: &JsonRecord, source_record_opt: Option<&SourceRecord>, input_combined_vector: &[(u32, f32)], self_dot: f32, document: ComparedVector) -> f32 {
     if !config.options.force_year {
         return calculate_base_similarity(input_combined_vector, self_dot, document);
     }
     if config.options.force_year && config.options.year_tolerance.is_none() {
         if let Some(source_record) = source_record_opt {
-            return calculate_similarity_forced_year(config, record, source_record, input_combined_vector, self_dot, document);
+            return calculate_similarity_forced_year(record, source_record, input_combined_vector, self_dot, document);
         } else {
             return calculate_base_similarity(input_combined_vector, self_dot, document);
         }
@@ -400,27 +1436,31 @@ fn calculate_similarity_score(config: &Config, record: &JsonRecord, source_recor
 
 // Allow for the following:
 // If record.year is "0", just calculate base similarity
-// If json_schema_version >= 2 and record.allowed_years does not contain source_record.year, return 0.0
-// If json_schema_version < 2 and record.year != source_record.year, return 0.0
+// If the record was read with JSON schema version >= 2 and none of record.allowed_years is within the year span of source_record, return 0.0
+// If the record was read with JSON schema version < 2 and record.year is not within the year span of source_record, return 0.0
 // Otherwise, return base similarity
-fn calculate_similarity_forced_year(config: &Config, record: &JsonRecord, source_record: &SourceRecord, input_combined_vector: &[(u32, f32)], self_dot: f32, document: &DatasetWeightedVector) -> f32 {
+// The year span of source_record is just its year unless last_year is set (see SourceRecord::year_span)
+fn calculate_similarity_forced_year(record: &JsonRecord, source_record: &SourceRecord, input_combined_vector: &[(u32, f32)], self_dot: f32, document: ComparedVector) -> f32 {
     // If record.year is "0", just calculate base similarity
     if record.year == "0" {
         return calculate_base_similarity(input_combined_vector, self_dot, document);
     }
-    // If json_schema_version >= 2 and record.allowed_years does not contain source_record.year, return 0.0
-    if config.options.json_schema_version >= 2 {
-        if let Ok(source_year) = source_record.year.parse::<u32>() {
-            if !record.allowed_years.contains(&source_year) {
+    // If json_schema_version >= 2 and none of record.allowed_years is within the source year span, return 0.0
+    if record.schema_version >= 2 {
+        if let Some((first_year, last_year)) = source_record.year_span() {
+            if !record.allowed_years.iter().any(|year| (first_year..=last_year).contains(&(*year as i32))) {
                 return 0.0;
             }
         } else {
             return 0.0; // Source year is not a valid number
         }
     } else {
-        // If json_schema_version < 2 and record.year != source_record.year, return 0.0
+        // If json_schema_version < 2 and record.year is not within the source year span, return 0.0
         if record.year != source_record.year {
-            return 0.0;
+            match record.year.parse::<i32>() {
+                Ok(record_year) if source_record.year_distance(record_year) == Some(0) => {},
+                _ => return 0.0,
+            }
         }
     }
     // Otherwise, return base similarity
@@ -429,9 +1469,9 @@ fn calculate_similarity_forced_year(config: &Config, record: &JsonRecord, source
 
 // Allow for the following (only record.year, allowed_years is ignored):
 // If record.year is "0", just calculate base similarity
-// If the absolute difference between record.year and source_record.year is greater than year_tolerance, return 0.0
+// If the distance between record.year and the year span of source_record is greater than year_tolerance, return 0.0
 // Otherwise, calculate base similarity and apply a penalty based on the year difference
-fn calculate_similarity_within_year_tolerance(config: &Config, record: &JsonRecord, source_record: &SourceRecord, input_combined_vector: &[(u32, f32)], self_dot: f32, document: &DatasetWeightedVector) -> f32 {
+fn calculate_similarity_within_year_tolerance(config: &Config, record: &JsonRecord, source_record: &SourceRecord, input_combined_vector: &[(u32, f32)], self_dot: f32, document: ComparedVector) -> f32 {
     // If record.year is "0", just calculate base similarity
     if record.year == "0" {
         return calculate_base_similarity(input_combined_vector, self_dot, document);
@@ -439,8 +1479,7 @@ fn calculate_similarity_within_year_tolerance(config: &Config, record: &JsonReco
     // If year_tolerance is set to a positive integer
     if let Some(tolerance) = config.options.year_tolerance {
         if let Ok(record_year) = record.year.parse::<i32>() {
-            if let Ok(source_year) = source_record.year.parse::<i32>() {
-                let year_diff = (record_year - source_year).abs();
+            if let Some(year_diff) = source_record.year_distance(record_year) {
                 if year_diff <= tolerance {
                     let base_similarity = calculate_base_similarity(input_combined_vector, self_dot, document);
                     // Apply a penalty based on how far the year is from the source year
@@ -460,8 +1499,8 @@ fn calculate_similarity_within_year_tolerance(config: &Config, record: &JsonReco
     calculate_base_similarity(input_combined_vector, self_dot, document)
 }
 
-fn calculate_base_similarity(input_combined_vector: &[(u32, f32)], self_dot: f32, document: &DatasetWeightedVector) -> f32 {
-    cosine_similarity(input_combined_vector, self_dot, &document.vector, document.dot)
+fn calculate_base_similarity(input_combined_vector: &[(u32, f32)], self_dot: f32, document: ComparedVector) -> f32 {
+    cosine_similarity(input_combined_vector, self_dot, document.vector, document.dot)
 }
 
 // If author has a single comma, split it and join in reverse order with a space
@@ -474,7 +1513,7 @@ fn swap_author(author: &str) -> String {
     }
 }
 
-fn combine_title_and_author(title: &str, author: &str) -> String {
+fn combine_title_and_author(title: &str, author: &str, author_normalize: AuthorNormalize) -> String {
     // Combine title and author with a slash
     if title.is_empty() && author.is_empty() {
         return "".to_string();
@@ -487,8 +1526,13 @@ fn combine_title_and_author(title: &str, author: &str) -> String {
     }
     // If both title and author are present, strip any trailing whitespace and punctuation from the title
     let title = title.trim_end_matches(|c: char| c.is_whitespace() || c.is_ascii_punctuation());
-    // Swap author if it has a single comma
-    format!("{} / {}", title, swap_author(author))
+    // Swap author if it has a single comma. With -O author-normalize all authors are given
+    // with given names first, as in a statement of responsibility.
+    let author = match author_normalize {
+        AuthorNormalize::None => swap_author(author),
+        _ => tokenizer::normalize_authors(author, AuthorNormalize::GivenFirst),
+    };
+    format!("{} / {}", title, author)
 }
 
 fn apply_overlap_score(config: &Config, top_n: &mut Vec<MatchCandidate>, input_record: &JsonRecord, source_data_records: &FxHashMap<String, SourceRecord>) {
@@ -498,7 +1542,9 @@ fn apply_overlap_score(config: &Config, top_n: &mut Vec<MatchCandidate>, input_r
     // Calculate the overlap score for each top_n item
     for candidate in top_n.iter_mut() {
         if let Some(source_record) = source_data_records.get(&candidate.id) {
-            let score = overlap_score(config, &source_record.title, &input_record.title);
+            let source_text = overlap_text(config, &source_record.title, &source_record.author, &source_record.location);
+            let input_text = overlap_text(config, &input_record.title, &input_record.author, &input_record.location);
+            let score = overlap_score(config, &source_text, &input_text);
             candidate.overlap_score = score;
             let score = overlap_score_adjust(score);
             candidate.adjusted_overlap_score = score;
@@ -507,6 +1553,74 @@ fn apply_overlap_score(config: &Config, top_n: &mut Vec<MatchCandidate>, input_r
     }
 }
 
+// Multiply the similarity of candidates listed in the re-rank table for this card and re-sort.
+// The source id in the table is either the full id or its last path segment (as matched_ID in the output).
+// If several card prefixes match, the longest one is used.
+fn apply_rerank_table(config: &Config, top_n: &mut [MatchCandidate], card: &str) {
+    if config.options.rerank_entries.is_empty() {
+        return; // No re-rank table configured, so return
+    }
+    for candidate in top_n.iter_mut() {
+        let short_id = output::matched_id(&candidate.id, &config.options.matched_id_sep);
+        let entries = config.options.rerank_entries.get(&candidate.id).or_else(|| config.options.rerank_entries.get(short_id));
+        let multiplier = entries.and_then(|entries| {
+            entries.iter()
+                .filter(|(card_prefix, _)| card.starts_with(card_prefix.as_str()))
+                .max_by_key(|(card_prefix, _)| card_prefix.len())
+                .map(|(_, multiplier)| *multiplier)
+        });
+        if let Some(multiplier) = multiplier {
+            candidate.similarity *= multiplier;
+        }
+    }
+    top_n.sort_by(|a, b| b.similarity.partial_cmp(&a.similarity).unwrap());
+}
+
+// Replace the similarity with its square root or its normalized rank in the pool (-O score-transform).
+// The rank is 1.0 for the best candidate and 1/n for the last, with equal similarities sharing the best rank.
+// Candidates with similarity 0.0 keep it, so that they are still removed as non-matches.
+fn apply_score_transform(transform: ScoreTransform, top_n: &mut [MatchCandidate]) {
+    match transform {
+        ScoreTransform::None => {},
+        ScoreTransform::Sqrt => {
+            for candidate in top_n.iter_mut() {
+                candidate.similarity = candidate.similarity.max(0.0).sqrt();
+            }
+        },
+        ScoreTransform::Rank => {
+            top_n.sort_by(|a, b| b.similarity.partial_cmp(&a.similarity).unwrap());
+            let pool_size = top_n.len() as f32;
+            let mut rank = 0;
+            let mut previous_similarity = None;
+            for (index, candidate) in top_n.iter_mut().enumerate() {
+                if previous_similarity != Some(candidate.similarity) {
+                    rank = index;
+                    previous_similarity = Some(candidate.similarity);
+                }
+                if candidate.similarity > 0.0 {
+                    candidate.similarity = (pool_size - rank as f32) / pool_size;
+                }
+            }
+        },
+    }
+}
+
+// With -O score-transform, similarity-threshold is applied to the transformed similarity, which is the
+// similarity in the output, rather than to the cosine when each candidate is scored (process_one_item).
+// Candidates below it get similarity 0.0, the same as there.
+fn apply_transformed_similarity_threshold(config: &Config, top_n: &mut [MatchCandidate]) {
+    let Some(threshold) = config.options.similarity_threshold else {
+        return;
+    };
+    if config.options.score_transform == ScoreTransform::None {
+        return;
+    }
+    for candidate in top_n.iter_mut().filter(|candidate| candidate.similarity > 0.0 && candidate.similarity < threshold) {
+        candidate.similarity = 0.0;
+        candidate.dropped_by = Some(FilterStage::SimilarityThreshold);
+    }
+}
+
 fn truncate_string_to_unicode_boundary(s: &str, max_len: usize) -> String {
     if s.len() <= max_len {
         return s.to_string();
@@ -582,6 +1696,11 @@ fn overlap_score(config: &Config, source_string: &str, input_string: &str) -> f3
     if config.options.overlap_adjustment.is_none() {
         return 1.0; // No overlap adjustment configured, so return 1.0 keeping the similarity score unchanged
     }
+    if title_below_overlap_floor(input_string, config.options.overlap_min_title_len) {
+        return 1.0; // Too short for a meaningful overlap, keep the similarity score unchanged
+    }
+    let source_string = truncate_for_overlap(source_string, config.options.overlap_max_len);
+    let input_string = truncate_for_overlap(input_string, config.options.overlap_max_len);
     let overlap_threshold = config.options.overlap_adjustment.unwrap() as usize;
     // If input_string is shorter than overlap_threshold, reduce the threshold to the length of input_string
     let overlap_threshold = overlap_threshold.min(input_string.len());
@@ -600,6 +1719,42 @@ fn overlap_score(config: &Config, source_string: &str, input_string: &str) -> f3
     filtered_overlap.iter().map(|o| o.len() as f32).sum::<f32>() / input_string.len() as f32
 }
 
+// Very short titles ("A", "Nr 3") trivially overlap completely, so they are not adjusted (-O overlap-min-title-len)
+// The text of a record compared by the overlap adjustment (-O overlap-field). The combined text
+// has the author, title and location in the combined-order, the year is left out.
+fn overlap_text(config: &Config, title: &str, author: &str, location: &str) -> String {
+    match config.options.overlap_field {
+        OverlapField::Title => title.to_string(),
+        OverlapField::Author => author.to_string(),
+        OverlapField::Combined => config.options.combined_order.iter()
+            .filter_map(|field| match field.as_str() {
+                "author" => Some(author),
+                "title" => Some(title),
+                "location" => Some(location),
+                _ => None,
+            })
+            .filter(|text| !text.trim().is_empty())
+            .collect::<Vec<&str>>()
+            .join(" "),
+    }
+}
+
+// The first max_len characters of the string (-O overlap-max-len), the whole string if it is shorter.
+// The suffix automaton of the overlap grows with the length of the source title.
+fn truncate_for_overlap(string: &str, max_len: Option<i32>) -> &str {
+    let Some(max_len) = max_len else {
+        return string;
+    };
+    match string.char_indices().nth(max_len.max(0) as usize) {
+        Some((end, _)) => &string[..end],
+        None => string,
+    }
+}
+
+fn title_below_overlap_floor(title: &str, min_title_len: i32) -> bool {
+    (title.trim().chars().count() as i64) < min_title_len as i64
+}
+
 #[allow(dead_code)]
 fn debug_overlap(source_data_records: &FxHashMap<String, SourceRecord>, top: &[(String, f32, f32)], input_document: &JsonRecord) {
     if top.is_empty() {
@@ -633,13 +1788,38 @@ fn cosine_similarity(vector1: &[(u32, f32)], vector1_selfdot: f32, vector2: &[(u
 
 fn dot_product(vector1: &[(u32, f32)], vector2: &[(u32, f32)]) -> f32 {
     let mut sum = 0.0;
+    merge_walk(vector1, vector2, |_, product| sum += product);
+    sum
+}
+
+// The products value1 * value2 for each index present in both vectors
+fn dot_product_contributions(vector1: &[(u32, f32)], vector2: &[(u32, f32)]) -> Vec<(u32, f32)> {
+    let mut contributions = vec![];
+    merge_walk(vector1, vector2, |index, product| contributions.push((index, product)));
+    contributions
+}
+
+// Jaccard coefficient of the sets of indexes present in two sparse vectors: |shared| / |union|
+fn ngram_jaccard(vector1: &[(u32, f32)], vector2: &[(u32, f32)]) -> f32 {
+    let mut shared = 0;
+    merge_walk(vector1, vector2, |_, _| shared += 1);
+    let union = vector1.len() + vector2.len() - shared;
+    if union == 0 {
+        return 0.0;
+    }
+    shared as f32 / union as f32
+}
+
+// Walk two sparse vectors (sorted by index) and call add with the product for every shared index
+#[inline(always)]
+fn merge_walk(vector1: &[(u32, f32)], vector2: &[(u32, f32)], mut add: impl FnMut(u32, f32)) {
     let mut i = 0;
     let mut j = 0;
     while i < vector1.len() && j < vector2.len() {
         let (index1, value1) = vector1[i];
         let (index2, value2) = vector2[j];
         if index1 == index2 {
-            sum += value1 * value2;
+            add(index1, value1 * value2);
             i += 1;
             j += 1;
         } else if index1 < index2 {
@@ -648,14 +1828,39 @@ fn dot_product(vector1: &[(u32, f32)], vector2: &[(u32, f32)]) -> f32 {
             j += 1;
         }
     }
-    sum
+}
+
+// Number of tokens listed in the explanation of a candidate
+const EXPLAIN_TOKENS: usize = 10;
+
+// Explain the base (cosine) similarity of a candidate as the tokens contributing the most to it,
+// as "token:share" pairs where the share of all tokens sum to the cosine similarity.
+// The start and end of a string in n-grams are shown as ^ and $.
+fn explain_similarity(vocab: &Vocab, input_combined_vector: &[(u32, f32)], self_dot: f32, document: ComparedVector) -> String {
+    let norm = self_dot * document.dot;
+    if norm == 0.0 {
+        return "".to_string();
+    }
+    let mut contributions = dot_product_contributions(input_combined_vector, document.vector);
+    contributions.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    contributions.iter().take(EXPLAIN_TOKENS).map(|(index, product)| {
+        let token = vocab.words.get(*index as usize).map(|word| display_token(word)).unwrap_or_default();
+        format!("{}:{:.4}", token, product / norm)
+    }).collect::<Vec<String>>().join("; ")
+}
+
+pub fn display_token(token: &str) -> String {
+    token.replace(tokenizer::STARTSYMBOL, "^").replace(tokenizer::ENDSYMBOL, "$").replace(tokenizer::UNKNOWN, "?")
 }
 
 // Document contains a: vectors: HashMap<String, Vec<(VectorIndex, f32)>> with a sparse vector for each part
 // The sparse vectors are weighted by the values from the weights hashmap with a simple multiplication
 // The return vector is a sparse vector with the weighted average of all the vectors.
 // If one part is missing, it is ignored, it is NOT treated as a zero vector or that would skew the result.
-fn weighted_averaged_vector(document: &Document, weights: &FxHashMap<String, f32>) -> Vec<(u32, f32)> {
+// With length_normalize, each part is also scaled by its pivoted length normalization factor.
+// Parts without a weight (e.g. a part added to the vectors after the weights file was written) get weight 0.0.
+fn weighted_averaged_vector(document: &Document, weights: &FxHashMap<String, f32>, length_normalize: bool, verbose: bool) -> Vec<(u32, f32)> {
+    let pivot = length_pivot(document);
     let mut active_parts = 0;
     let mut intermediate_vector = BTreeMap::new();
     for (part, vector) in &document.vectors {
@@ -664,7 +1869,11 @@ fn weighted_averaged_vector(document: &Document, weights: &FxHashMap<String, f32
             continue;
         }
         active_parts += 1;
-        let weight = weights.get(part).unwrap();
+        let weight = if length_normalize {
+            part_weight(weights, part, verbose) * length_normalization(vector.len(), pivot)
+        } else {
+            part_weight(weights, part, verbose)
+        };
 
         // If active_parts is 1, we initialize the intermediate_vector map with the first vector
         if active_parts == 1 {
@@ -686,15 +1895,101 @@ fn weighted_averaged_vector(document: &Document, weights: &FxHashMap<String, f32
     combined_vector
 }
 
+static MISSING_WEIGHT_WARNING: std::sync::Once = std::sync::Once::new();
+
+fn part_weight(weights: &FxHashMap<String, f32>, part: &str, verbose: bool) -> f32 {
+    match weights.get(part) {
+        Some(weight) => *weight,
+        None => {
+            if verbose {
+                MISSING_WEIGHT_WARNING.call_once(|| eprintln!("Warning: no weight for part {}, it is ignored (weight 0.0)", part));
+            }
+            0.0
+        }
+    }
+}
+
+// Slope of the pivoted length normalization, same as the b parameter in Okapi BM25
+const LENGTH_NORMALIZATION_SLOPE: f32 = 0.75;
+
+// The pivot is the average number of tokens over the non-empty parts of the document
+fn length_pivot(document: &Document) -> f32 {
+    let lengths = document.vectors.values().map(|vector| vector.len()).filter(|len| *len > 0).collect::<Vec<usize>>();
+    if lengths.is_empty() {
+        return 1.0;
+    }
+    lengths.iter().sum::<usize>() as f32 / lengths.len() as f32
+}
+
+// Factor for a part with part_length tokens: 1 / ((1 - b) + b * part_length / pivot)
+// A part longer than the pivot contributes less, a shorter part contributes more.
+fn length_normalization(part_length: usize, pivot: f32) -> f32 {
+    1.0 / ((1.0 - LENGTH_NORMALIZATION_SLOPE) + LENGTH_NORMALIZATION_SLOPE * part_length as f32 / pivot)
+}
+
 pub fn vector_weights(config: &Config) -> FxHashMap<String, f32> {
     // WeightsFile is a JSON file with a hashmap of part -> weight
-    if let Some(ref filename) = config.options.weights_file {
+    let weights: FxHashMap<String, f32> = if let Some(weights) = &config.options.config_weights {
+        weights.clone()
+    } else if let Some(ref filename) = config.options.weights_file {
         let file = std::fs::File::open(filename).unwrap();
         let reader = std::io::BufReader::new(file);
         serde_json::from_reader(reader).unwrap()
     } else {
         default_weights()
+    };
+    weight_overrides(config, weights)
+}
+
+// A named set of weights in the -O weights-profiles file
+#[derive(Deserialize)]
+struct WeightsProfileEntry {
+    name: String,
+    weights: FxHashMap<String, f32>,
+}
+
+// The weights to match with, by profile name. The profiles from -O weights-profiles if given
+// (each with use-combined-only and weight-<part> applied on top), otherwise only the vector_weights as "default".
+fn weights_profiles(config: &Config) -> Vec<(String, FxHashMap<String, f32>)> {
+    let Some(filename) = &config.options.weights_profiles else {
+        return vec![("default".to_string(), vector_weights(config))];
+    };
+    let content = std::fs::read_to_string(filename).unwrap_or_else(|e| {
+        eprintln!("Failed to read weights profiles file {}: {}", filename, e);
+        std::process::exit(1);
+    });
+    let entries: Vec<WeightsProfileEntry> = serde_json::from_str(&content).unwrap_or_else(|e| {
+        eprintln!("Failed to parse weights profiles file {}, expected an array of {{\"name\": ..., \"weights\": {{...}}}}: {}", filename, e);
+        std::process::exit(1);
+    });
+    if entries.is_empty() {
+        eprintln!("Weights profiles file {} has no profiles", filename);
+        std::process::exit(1);
+    }
+    entries.into_iter().map(|entry| {
+        let weights = weight_overrides(config, entry.weights);
+        if let Some(part) = WEIGHT_PARTS.iter().find(|part| !weights.contains_key(**part)) {
+            eprintln!("Weights profile {} in {} has no weight for {}", entry.name, filename, part);
+            std::process::exit(1);
+        }
+        (entry.name, weights)
+    }).collect()
+}
+
+// Apply use-combined-only and the inline weights to file or default weights
+fn weight_overrides(config: &Config, mut weights: FxHashMap<String, f32>) -> FxHashMap<String, f32> {
+    // Use only the combined text, for cards where the individual parts are unreliable
+    if config.options.use_combined_only {
+        for part in ["author", "title", "location", "year"] {
+            weights.insert(part.to_string(), 0.0);
+        }
+        weights.insert("all".to_string(), 1.0);
+    }
+    // Inline weights (-O weight-<part>=N) override the file or default weights
+    for (part, weight) in &config.options.inline_weights {
+        weights.insert(part.clone(), *weight);
     }
+    weights
 }
 
 fn default_weights() -> FxHashMap<String, f32> {
@@ -712,20 +2007,20 @@ fn default_weights() -> FxHashMap<String, f32> {
 // The ZIP-file optionally contains a prompt file.
 // Therefor the return type is (String, Vec<(String, JsonRecord)>)
 // where the first String is the prompt used, if provided, and the list is ("card", "record")
-fn read_json_zip_file(config: &Config, filename: &str) -> (String, Vec<(String, JsonRecord)>) {
-    // If filename has extension .zip, read from zip file, otherwise read as normal with an empty prompt
+fn open_json_zip_file(config: &Config, filename: &str, warnings: &mut Vec<zipfile::InputWarning>) -> zipfile::JsonFileInput {
+    // If filename has extension .zip, read from zip file
     if filename.ends_with(".zip") {
         if config.verbose {
             println!("Reading zip file: {}", filename);
         }
-        return zipfile::read_zip_file(config, filename, config.options.json_schema_version);
+        return zipfile::JsonFileInput::open(filename, warnings);
     }
     if zipfile::is_directory(filename) {
         // Secretly allow directories as well.
         if config.verbose {
             println!("Reading directory: {}", filename);
         }
-        return zipfile::read_zip_file(config, filename, config.options.json_schema_version);
+        return zipfile::JsonFileInput::open(filename, warnings);
     }
     // Officially only support zip-files.
     panic!("Only zip-files are supported as input for match-json-zip");
@@ -733,11 +2028,14 @@ fn read_json_zip_file(config: &Config, filename: &str) -> (String, Vec<(String,
 
 /// Calculate z-scores for a vector of (ID, similarity) pairs.
 /// Returns a vector of (ID, similarity, z-score) tuples.
-fn calculate_z_scores(mut data: Vec<MatchCandidate>) -> Vec<MatchCandidate> {
+fn calculate_z_scores(mut data: Vec<MatchCandidate>, method: ZscoreMethod) -> Vec<MatchCandidate> {
     let n = data.len();
     if n == 0 {
         return Vec::new();
     }
+    if method == ZscoreMethod::Robust {
+        return calculate_robust_z_scores(data);
+    }
 
     // Calculate mean
     let mean: f32 = data.iter().map(|candidate| candidate.similarity).sum::<f32>() / n as f32;
@@ -762,3 +2060,366 @@ fn calculate_z_scores(mut data: Vec<MatchCandidate>) -> Vec<MatchCandidate> {
         });
     data
 }
+
+fn below_min_pool(pool_size: usize, min_pool_for_z: Option<i32>) -> bool {
+    min_pool_for_z.is_some_and(|min_pool| (pool_size as i64) < min_pool as i64)
+}
+
+// Z-scores of the pool, or 0.0 for every candidate when the pool is smaller than min-pool-for-z,
+// since a std over a handful of similarities gives large z-scores whatever the similarities are.
+// The stable sort on the z-score then keeps the similarity order.
+fn pool_z_scores(mut data: Vec<MatchCandidate>, method: ZscoreMethod, min_pool_for_z: Option<i32>) -> Vec<MatchCandidate> {
+    if below_min_pool(data.len(), min_pool_for_z) {
+        data.iter_mut().for_each(|candidate| candidate.zscore = 0.0);
+        return data;
+    }
+    calculate_z_scores(data, method)
+}
+
+// Spread of the similarities the z-scores are calculated from, as the standard deviation whatever the
+// z-score method. The MAD of zscore-method=robust is 0.0 for a peaked pool where most similarities are equal,
+// which would make it look flat.
+fn pool_spread(data: &[MatchCandidate]) -> f32 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let similarities = data.iter().map(|candidate| candidate.similarity).collect::<Vec<f32>>();
+    let mean = similarities.iter().sum::<f32>() / similarities.len() as f32;
+    let variance = similarities.iter().map(|similarity| (similarity - mean).powi(2)).sum::<f32>() / similarities.len() as f32;
+    variance.sqrt()
+}
+
+// Ratio of the median absolute deviation (MAD) to the mean absolute deviation for normally distributed values
+const MAD_PER_MEAN_ABSOLUTE_DEVIATION: f32 = 0.8453;
+
+// Same as calculate_z_scores with the median instead of the mean and the median absolute deviation (MAD)
+// instead of the standard deviation, which are not pulled along by the many near-zero similarities in the pool.
+// The MAD is 0.0 when more than half of the similarities are equal, then the mean absolute deviation
+// (scaled to the MAD) is used, so that a single clear match in an otherwise uniform pool still stands out.
+fn calculate_robust_z_scores(mut data: Vec<MatchCandidate>) -> Vec<MatchCandidate> {
+    let median = median_of(data.iter().map(|candidate| candidate.similarity).collect());
+    let deviations = data.iter().map(|candidate| (candidate.similarity - median).abs()).collect::<Vec<f32>>();
+    let mut spread = median_of(deviations.clone());
+    if spread == 0.0 {
+        spread = deviations.iter().sum::<f32>() / deviations.len() as f32 * MAD_PER_MEAN_ABSOLUTE_DEVIATION;
+    }
+    data.iter_mut()
+        .for_each(|candidate| {
+            candidate.zscore = if spread == 0.0 {
+                0.0 // Same as a std_dev of 0, all similarities are equal
+            } else {
+                (candidate.similarity - median) / spread
+            };
+        });
+    data
+}
+
+// Median of a non-empty list of values
+fn median_of(mut values: Vec<f32>) -> f32 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_transformed_similarity_threshold, combine_title_and_author, DatasetWeightedVector, JsonRecord, MatchCandidate, MatchStat, MatchStatistics, apply_score_transform, pool_spread, pool_z_scores, same_title, summary_line, trace_lines, empty_author_factor, FilterStage, best_expected_rank, calculate_z_scores, expected_rank, default_weights, weighted_averaged_vector, input_namespaces, length_normalization, length_pivot, ngram_jaccard, overlap_score_adjust, part_similarity, part_vectors, title_below_overlap_floor, truncate_for_overlap};
+    use crate::args::{AuthorNormalize, Config, ScoreTransform, ZscoreMethod};
+    use crate::source_data::SourceRecord;
+    use crate::vectorize::Document;
+    use std::collections::HashMap;
+
+    #[test]
+    fn allowed_years_as_compact_ranges() {
+        let record = JsonRecord {
+            edition: 0,
+            title: String::new(),
+            author: String::new(),
+            location: String::new(),
+            year: "1949".to_string(),
+            publication_type: String::new(),
+            allowed_years: vec![1953, 1949, 1951, 1952, 1954, 1951],
+            schema_version: 2,
+        };
+        assert_eq!(record.allowed_years_string(), "1949;1951-1954");
+    }
+
+    #[test]
+    fn empty_author_penalty_only_without_card_author() {
+        let record = |author: &str| JsonRecord {
+            edition: 0,
+            title: "Titel".to_string(),
+            author: author.to_string(),
+            location: String::new(),
+            year: "1950".to_string(),
+            publication_type: String::new(),
+            allowed_years: vec![],
+            schema_version: 2,
+        };
+        let source_record = |author: &str| SourceRecord {
+            id: "source/1".to_string(),
+            title: "Titel".to_string(),
+            author: author.to_string(),
+            location: String::new(),
+            year: "1950".to_string(),
+            last_year: String::new(),
+            publication_type: String::new(),
+        };
+        assert_eq!(empty_author_factor(0.1, &record(""), Some(&source_record("Strindberg, August"))), 0.9);
+        assert_eq!(empty_author_factor(0.1, &record(" "), Some(&source_record(""))), 1.0);
+        assert_eq!(empty_author_factor(0.1, &record("Strindberg"), Some(&source_record("Strindberg, August"))), 1.0);
+        assert_eq!(empty_author_factor(0.0, &record(""), Some(&source_record("Strindberg, August"))), 1.0);
+        assert_eq!(empty_author_factor(0.1, &record(""), None), 1.0);
+    }
+
+    #[test]
+    fn length_normalization_favours_shorter_parts() {
+        let mut vectors = HashMap::new();
+        vectors.insert("title".to_string(), (0..6).map(|index| (index, 1.0)).collect());
+        vectors.insert("year".to_string(), vec![(10, 1.0), (11, 1.0)]);
+        vectors.insert("location".to_string(), vec![]);
+        let document = Document { id: "1".to_string(), vectors };
+        let pivot = length_pivot(&document);
+        assert_eq!(pivot, 4.0);
+        assert!(length_normalization(6, pivot) < 1.0);
+        assert!(length_normalization(2, pivot) > 1.0);
+        assert_eq!(length_normalization(4, pivot), 1.0);
+    }
+
+    #[test]
+    fn ngram_jaccard_of_index_sets() {
+        let input = vec![(1, 0.5), (2, 0.1), (4, 2.0)];
+        let document = vec![(2, 3.0), (3, 1.0), (4, 0.2)];
+        assert_eq!(ngram_jaccard(&input, &document), 0.5);
+        assert_eq!(ngram_jaccard(&input, &input), 1.0);
+        assert_eq!(ngram_jaccard(&[], &[]), 0.0);
+    }
+
+    #[test]
+    fn part_similarity_of_retained_parts() {
+        let mut vectors = HashMap::new();
+        vectors.insert("title".to_string(), vec![(1, 1.0), (2, 1.0)]);
+        vectors.insert("author".to_string(), vec![(3, 1.0)]);
+        let input = part_vectors(&Document { id: "input".to_string(), vectors }, &["title", "author"]);
+        let mut vectors = HashMap::new();
+        vectors.insert("title".to_string(), vec![(1, 1.0)]);
+        let source = part_vectors(&Document { id: "source".to_string(), vectors }, &["title"]);
+        assert!((part_similarity(&input["title"], source.get("title")) - 0.70710677).abs() < 1e-6);
+        // Parts that were not retained, or are empty, have no similarity
+        assert_eq!(part_similarity(&input["author"], source.get("author")), 0.0);
+    }
+
+    #[test]
+    fn all_fallback_compares_the_all_part() {
+        let mut vectors = HashMap::new();
+        vectors.insert("all".to_string(), vec![(5, 2.0)]);
+        let document = DatasetWeightedVector {
+            id: "source/1".to_string(),
+            vector: vec![(1, 1.0)],
+            dot: 1.0,
+            part_vectors: part_vectors(&Document { id: "source/1".to_string(), vectors }, &["all"]),
+        };
+        assert_eq!(document.compared_vector(false).vector, &[(1, 1.0)]);
+        assert_eq!(document.compared_vector(true).vector, &[(5, 2.0)]);
+        assert_eq!(document.compared_vector(true).dot, 2.0);
+    }
+
+    #[test]
+    fn expected_rank_in_full_candidate_list() {
+        let config = Config::from_args(&["-c", "match-single-json", "-s", "test"]);
+        let candidates = vec![
+            MatchCandidate::new("source/1", 0.9),
+            MatchCandidate::new("source/2", 0.5),
+            MatchCandidate::new("source/3", 0.0),
+        ];
+        assert_eq!(expected_rank(&config, &candidates, "source/2"), 2);
+        assert_eq!(expected_rank(&config, &candidates, "1"), 1);
+        assert_eq!(expected_rank(&config, &candidates, "source/3"), -1);
+        assert_eq!(expected_rank(&config, &candidates, "source/4"), -1);
+        assert_eq!(best_expected_rank(Some(-1), Some(7)), Some(7));
+        assert_eq!(best_expected_rank(Some(3), Some(7)), Some(3));
+    }
+
+    #[test]
+    fn same_title_after_normalization() {
+        assert!(same_title("Röda rummet", "röda  rummet."));
+        assert!(same_title("Röda rummet: skildringar", "Röda rummet : skildringar"));
+        assert!(!same_title("Röda rummet", "Röda rummet, del 2"));
+        // An empty title is never exact
+        assert!(!same_title("", ""));
+    }
+
+    #[test]
+    fn score_transform_rank_and_sqrt() {
+        let candidates = || [0.25, 0.64, 0.25, 0.0].iter().enumerate()
+            .map(|(index, similarity)| MatchCandidate::new(&index.to_string(), *similarity))
+            .collect::<Vec<MatchCandidate>>();
+        let mut ranked = candidates();
+        apply_score_transform(ScoreTransform::Rank, &mut ranked);
+        let ranked = ranked.iter().map(|candidate| (candidate.id.as_str(), candidate.similarity)).collect::<Vec<(&str, f32)>>();
+        assert_eq!(ranked, vec![("1", 1.0), ("0", 0.75), ("2", 0.75), ("3", 0.0)]);
+        let mut square_roots = candidates();
+        apply_score_transform(ScoreTransform::Sqrt, &mut square_roots);
+        assert_eq!(square_roots.iter().map(|candidate| candidate.similarity).collect::<Vec<f32>>(), vec![0.5, 0.8, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn transformed_similarity_held_to_threshold() {
+        let config = Config::from_args(&["-c", "match-single-json", "-s", "test", "-O", "score-transform=sqrt", "-O", "similarity-threshold=0.35"]);
+        // Cosines 0.16 and 0.1 are below the threshold, their square roots 0.4 and 0.316 are the output values
+        let mut candidates = vec![MatchCandidate::new("1", 0.16), MatchCandidate::new("2", 0.1)];
+        apply_score_transform(config.options.score_transform, &mut candidates);
+        apply_transformed_similarity_threshold(&config, &mut candidates);
+        assert!((candidates[0].similarity - 0.4).abs() < 1e-6);
+        assert_eq!(candidates[0].dropped_by, None);
+        assert_eq!(candidates[1].similarity, 0.0);
+        assert_eq!(candidates[1].dropped_by, Some(FilterStage::SimilarityThreshold));
+    }
+
+    #[test]
+    fn parts_without_weight_are_ignored() {
+        let mut vectors = HashMap::new();
+        vectors.insert("title".to_string(), vec![(1, 1.0)]);
+        vectors.insert("publication_type".to_string(), vec![(2, 1.0)]);
+        let document = Document { id: "1".to_string(), vectors };
+        // Weights without publication_type, as in weights files written before the part existed
+        let weights = default_weights();
+        let combined = weighted_averaged_vector(&document, &weights, false, false);
+        assert_eq!(combined, vec![(1, weights["title"] / 2.0), (2, 0.0)]);
+    }
+
+    #[test]
+    fn input_namespaces_from_file_names() {
+        let single = vec!["in/week1.zip".to_string()];
+        assert_eq!(input_namespaces(&single), vec![None]);
+        let inputs = vec!["in/week1.zip".to_string(), "in/week2.jsonl".to_string()];
+        assert_eq!(input_namespaces(&inputs), vec![Some("week1".to_string()), Some("week2".to_string())]);
+        let same_names = vec!["a/cards.zip".to_string(), "b/cards.zip".to_string()];
+        assert_eq!(input_namespaces(&same_names), vec![Some("a/cards".to_string()), Some("b/cards".to_string())]);
+    }
+
+    #[test]
+    fn overlap_max_len_only_cuts_long_titles() {
+        let title = "Röda rummet: skildringar ur artist- och författarlivet";
+        assert_eq!(truncate_for_overlap(title, None), title);
+        assert_eq!(truncate_for_overlap(title, Some(200)), title);
+        assert_eq!(truncate_for_overlap(title, Some(title.chars().count() as i32)), title);
+        assert_eq!(truncate_for_overlap(title, Some(4)), "Röda");
+    }
+
+    #[test]
+    fn short_titles_below_overlap_floor_are_not_adjusted() {
+        // Default 0 adjusts every title, as before
+        assert!(!title_below_overlap_floor("A", 0));
+        assert!(title_below_overlap_floor("A", 5));
+        assert!(title_below_overlap_floor(" Nr 3 ", 5));
+        assert!(!title_below_overlap_floor("Nr 33", 5));
+        // Skipped titles get overlap score 1.0, which leaves the similarity unchanged
+        assert_eq!(overlap_score_adjust(1.0), 1.0);
+    }
+
+    #[test]
+    fn summary_line_percentages() {
+        let mut statistics = MatchStatistics::default();
+        assert_eq!(summary_line(&statistics, 0), "Matched 0 records (0 cards): single 0.0%, multiple 0.0%, no match 0.0%, elapsed 0s");
+        statistics.update(&MatchStat::SingleMatch, "001_1.json");
+        statistics.update(&MatchStat::SingleMatch, "001_2.json");
+        statistics.update(&MatchStat::MultipleMatches, "001_2.json");
+        statistics.update(&MatchStat::NoMatch, "001_3.json");
+        assert_eq!(summary_line(&statistics, 125), "Matched 4 records (3 cards): single 50.0%, multiple 25.0%, no match 25.0%, elapsed 2m05s");
+        statistics.update(&MatchStat::ExactMatch, "001_4.json");
+        assert_eq!(summary_line(&statistics, 125), "Matched 5 records (4 cards): single 40.0%, exact 20.0%, multiple 20.0%, no match 20.0%, elapsed 2m05s");
+    }
+
+    #[test]
+    fn pool_spread_of_flat_and_peaked_pools() {
+        let pool = |similarities: &[f32]| similarities.iter().enumerate()
+            .map(|(index, similarity)| MatchCandidate::new(&index.to_string(), *similarity))
+            .collect::<Vec<MatchCandidate>>();
+        let flat = pool(&[0.31, 0.30, 0.30, 0.29]);
+        let peaked = pool(&[0.9, 0.1, 0.1, 0.1]);
+        assert!(pool_spread(&flat) < 0.01);
+        // Most of the pool is equal (a MAD of 0.0), but it is not flat
+        assert!((pool_spread(&peaked) - 0.34641016).abs() < 1e-6);
+        assert_eq!(pool_spread(&[]), 0.0);
+    }
+
+    #[test]
+    fn trace_lines_give_the_reason_of_zero_similarity() {
+        let mut excluded = MatchCandidate::new("source/2", 0.0);
+        excluded.dropped_by = Some(FilterStage::Excluded);
+        let dropped = vec![MatchCandidate::new("source/1", 0.25), excluded];
+        assert_eq!(trace_lines("001_1.json", FilterStage::ZThreshold, &dropped), vec![
+            "Trace 001_1.json: source/1 (similarity 0.2500, z-score 0.0000) removed by z-threshold",
+            "Trace 001_1.json: source/2 (similarity 0.0000, z-score 0.0000) removed by exclude-file",
+        ]);
+        let many = (0..12).map(|index| MatchCandidate::new(&format!("source/{}", index), 0.1)).collect::<Vec<MatchCandidate>>();
+        let lines = trace_lines("001_1.json", FilterStage::Pool, &many);
+        assert_eq!(lines.len(), 11);
+        assert_eq!(lines[10], "Trace 001_1.json: 2 more candidates removed by z-score pool of 200");
+    }
+
+    #[test]
+    fn small_pool_has_no_z_scores() {
+        let pool = || vec![MatchCandidate::new("source/1", 0.42), MatchCandidate::new("source/2", 0.40)];
+        // Two candidates always give z-scores of +1 and -1, however close the similarities are
+        let z_scores = pool_z_scores(pool(), ZscoreMethod::Mean, None);
+        assert!((z_scores[0].zscore - 1.0).abs() < 1e-4);
+        assert!((z_scores[1].zscore + 1.0).abs() < 1e-4);
+        let z_scores = pool_z_scores(pool(), ZscoreMethod::Mean, Some(3));
+        assert_eq!(z_scores.iter().map(|candidate| candidate.zscore).collect::<Vec<f32>>(), vec![0.0, 0.0]);
+        assert_eq!(z_scores[0].id, "source/1");
+        let z_scores = pool_z_scores(pool(), ZscoreMethod::Mean, Some(2));
+        assert!(z_scores[0].zscore > 0.0);
+    }
+
+    #[test]
+    fn robust_z_scores_on_skewed_similarities() {
+        // One clear match, a few plausible candidates and a long tail of near-zero similarities
+        let mut similarities = vec![0.9, 0.5, 0.4, 0.3];
+        similarities.extend((0..16).map(|index| 0.01 + index as f32 * 0.001));
+        let candidates = || similarities.iter().enumerate()
+            .map(|(index, similarity)| MatchCandidate::new(&index.to_string(), *similarity))
+            .collect::<Vec<MatchCandidate>>();
+        let mean = calculate_z_scores(candidates(), ZscoreMethod::Mean);
+        let robust = calculate_z_scores(candidates(), ZscoreMethod::Robust);
+        // Both methods keep the order of the similarities
+        for (first, second) in [(0, 1), (1, 2), (3, 19), (19, 4)] {
+            assert!(mean[first].zscore > mean[second].zscore);
+            assert!(robust[first].zscore > robust[second].zscore);
+        }
+        // The clear match pulls the mean up and the standard deviation is large,
+        // while the median (0.0195) and MAD (0.005) are set by the tail
+        assert!(mean[0].zscore < 4.0);
+        assert!((robust[0].zscore - 176.1).abs() < 0.1);
+        assert!((robust[19].zscore - 1.1).abs() < 0.01);
+    }
+
+    #[test]
+    fn robust_z_scores_when_most_similarities_are_equal() {
+        // The MAD is 0.0, the mean absolute deviation from the median is 0.08
+        let mut similarities = vec![0.9];
+        similarities.extend([0.1; 9]);
+        let candidates = similarities.iter().enumerate()
+            .map(|(index, similarity)| MatchCandidate::new(&index.to_string(), *similarity))
+            .collect::<Vec<MatchCandidate>>();
+        let robust = calculate_z_scores(candidates, ZscoreMethod::Robust);
+        assert!((robust[0].zscore - 0.8 / (0.08 * 0.8453)).abs() < 0.01);
+        assert_eq!(robust[1].zscore, 0.0);
+        let uniform = calculate_z_scores(vec![MatchCandidate::new("1", 0.2), MatchCandidate::new("2", 0.2)], ZscoreMethod::Robust);
+        assert!(uniform.iter().all(|candidate| candidate.zscore == 0.0));
+    }
+
+    #[test]
+    fn author_added_to_title_swapped_unless_normalized() {
+        let author = "Petersen, Ib Hoy & Naae, Viggo";
+        assert_eq!(combine_title_and_author("Titel.", "Petersen, Ib", AuthorNormalize::None), "Titel / Ib Petersen");
+        assert_eq!(combine_title_and_author("Titel", author, AuthorNormalize::None), "Titel / Petersen, Ib Hoy & Naae, Viggo");
+        assert_eq!(combine_title_and_author("Titel", author, AuthorNormalize::SurnameFirst), "Titel / Ib Hoy Petersen; Viggo Naae");
+        assert_eq!(combine_title_and_author("Titel", author, AuthorNormalize::GivenFirst), "Titel / Ib Hoy Petersen; Viggo Naae");
+    }
+}
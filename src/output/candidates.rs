@@ -0,0 +1,81 @@
+use crate::args::Config;
+use crate::matcher::MatchCandidate;
+use super::Output;
+use serde::Serialize;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// Sidecar file with the ranked candidates for each input record (-O candidates-dump=N),
+// one JSON object per line (NDJSON), written while matching.
+
+#[derive(Debug, Serialize)]
+struct CandidatesLine<'a> {
+    card: &'a str,
+    edition_idx: usize,
+    candidates: Vec<CandidateEntry>,
+}
+
+#[derive(Debug, Serialize)]
+struct CandidateEntry {
+    rank: usize,
+    id: String,
+    similarity: f32,
+    zscore: f32,
+}
+
+// Open the sidecar file, named as the output file but with the suffix -candidates.ndjson
+// instead of the original extension. Returns None if the option is not set or output is stdout.
+pub fn open_candidates_file(config: &Config) -> Option<BufWriter<std::fs::File>> {
+    config.options.candidates_dump?;
+    let output_filename = match &config.output {
+        Output::File(filename) => filename,
+        Output::Stdout => {
+            eprintln!("Warning: candidates-dump is ignored when writing to stdout");
+            return None;
+        }
+    };
+    let filename = candidates_file_name(output_filename);
+    if let Some(parent) = filename.parent() {
+        std::fs::create_dir_all(parent).expect("Unable to create output directory");
+    }
+    let file = std::fs::File::create(&filename).unwrap();
+    if config.verbose {
+        eprintln!("Writing candidates to {}", filename.display());
+    }
+    Some(BufWriter::new(file))
+}
+
+// The output file name with its extension (not a dot in a directory name) replaced by -candidates.ndjson
+fn candidates_file_name(output_filename: &str) -> PathBuf {
+    let path = Path::new(output_filename);
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    path.with_file_name(format!("{}-candidates.ndjson", stem))
+}
+
+pub fn write_candidates(config: &Config, writer: &mut BufWriter<std::fs::File>, card: &str, edition: usize, candidates: &[MatchCandidate]) {
+    let line = CandidatesLine {
+        card,
+        edition_idx: edition,
+        candidates: candidates.iter().enumerate().map(|(index, candidate)| CandidateEntry {
+            rank: index + 1,
+            id: super::output_id(config, &candidate.id),
+            similarity: candidate.similarity,
+            zscore: candidate.zscore,
+        }).collect(),
+    };
+    serde_json::to_writer(&mut *writer, &line).unwrap();
+    writer.write_all(b"\n").unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::candidates_file_name;
+    use std::path::PathBuf;
+
+    #[test]
+    fn candidates_file_next_to_output() {
+        assert_eq!(candidates_file_name("out/results.xlsx"), PathBuf::from("out/results-candidates.ndjson"));
+        assert_eq!(candidates_file_name("out.d/results"), PathBuf::from("out.d/results-candidates.ndjson"));
+        assert_eq!(candidates_file_name("results.tar.json"), PathBuf::from("results.tar-candidates.ndjson"));
+    }
+}
@@ -0,0 +1,37 @@
+use crate::args::Config;
+use crate::matcher::{MatchStat, MatchStatistics, OutputRecord};
+use super::Output;
+use std::io::{BufWriter, Write};
+
+// Crosswalk for catalog ingestion (-F crosswalk): tab separated card_ID and matched source id,
+// one row per single (or exact) match. All other records (multiple matches, no match, ...) are left out.
+
+pub fn output_records(config: &Config, output: &Output, records: &[OutputRecord], _statistics: Option<&MatchStatistics>) {
+    let result = match output {
+        Output::File(path) => {
+            let file = std::fs::File::create(path).expect("Unable to create file");
+            write_crosswalk(config, &mut BufWriter::new(file), records)
+        },
+        Output::Stdout => write_crosswalk(config, &mut std::io::stdout().lock(), records),
+    };
+    result.expect("Unable to write crosswalk");
+}
+
+fn write_crosswalk(config: &Config, writer: &mut dyn Write, records: &[OutputRecord]) -> std::io::Result<()> {
+    writeln!(writer, "card_ID\tsource_id")?;
+    for record in records {
+        if !matches!(record.stats, MatchStat::SingleMatch | MatchStat::ExactMatch) {
+            continue;
+        }
+        let Some(candidate) = record.top.first() else {
+            continue;
+        };
+        let source_id = match &candidate.source_record {
+            Some(source_record) => &source_record.id,
+            None => &candidate.id,
+        };
+        let card_id = super::with_edition(config, super::card_id(&record.card), record.record.edition);
+        writeln!(writer, "{}\t{}", card_id, super::output_id(config, source_id))?;
+    }
+    writer.flush()
+}
@@ -2,12 +2,44 @@ use std::fs::File;
 use std::io::{BufWriter, Write};
 use crate::args::Config;
 use crate::output::Cell;
-use crate::matcher::OutputRecord;
+use crate::matcher::{MatchStatistics, OutputRecord};
 
-pub fn output_records(config: &Config, path: &str, records: &[OutputRecord]) {
-    let headers = build_headers(config);
-    let rows = build_rows(config, records);
-    output_csv_file(path, &headers, &rows);
+pub fn output_records(config: &Config, path: &str, records: &[OutputRecord], statistics: Option<&MatchStatistics>) {
+    let mut writer = CsvWriter::create(config, path, super::run_id(statistics));
+    writer.write_batch(config, records);
+    writer.finish();
+}
+
+// CSV file written one batch of records at a time, the header is written when the file is created
+pub struct CsvWriter {
+    writer: BufWriter<File>,
+    run_id: Option<String>,
+}
+
+impl CsvWriter {
+    pub fn create(config: &Config, path: &str, run_id: Option<&str>) -> CsvWriter {
+        let file = File::create(path).expect("Unable to create file");
+        let mut writer = BufWriter::new(file);
+        let mut headers = build_headers(config);
+        super::add_run_id_column(&mut headers, &mut [], run_id);
+        output_csv_header(&mut writer, &headers);
+        CsvWriter {
+            writer,
+            run_id: run_id.map(str::to_string),
+        }
+    }
+
+    pub fn write_batch(&mut self, config: &Config, records: &[OutputRecord]) {
+        let mut rows = build_rows(config, records);
+        super::add_run_id_cells(&mut rows, self.run_id.as_deref());
+        for row in rows {
+            output_csv_row(&mut self.writer, &row);
+        }
+    }
+
+    pub fn finish(mut self) {
+        self.writer.flush().expect("Unable to write CSV file");
+    }
 }
 
 fn build_headers(config: &Config) -> Vec<String> {
@@ -24,24 +56,25 @@ fn build_headers(config: &Config) -> Vec<String> {
 fn build_rows(config: &Config, records: &[OutputRecord]) -> Vec<Vec<Cell>> {
     records.iter().flat_map(|record| {
         let mut rows = vec![];
+        if record.top.is_empty() && config.options.include_source_data {
+            // A row for the record without match, excluded or without edition, with empty id, similarity,
+            // zscore and source columns, so that every record is in the file and all rows have the same columns
+            let mut row = record_cells(config, record);
+            row.extend(super::empty_cells(3 + 4));
+            rows.push(row);
+        }
         for candidate in &record.top {
             let source_record_id = if let Some(source_record) = &candidate.source_record {
-                source_record.id.clone()
+                super::output_id(config, &source_record.id)
             } else {
                 "".to_string()
             };
-            let mut row = vec![
-                Cell::String(record.card.clone()),
-                Cell::Number(record.record.edition as f64),
-                Cell::String(record.record.title.clone()),
-                Cell::String(record.record.author.clone()),
-                Cell::String(record.record.location.clone()),
-                Cell::String(record.record.year.to_string()),
-                Cell::String(record.stats.to_string()),
+            let mut row = record_cells(config, record);
+            row.extend([
                 Cell::String(source_record_id),
-                Cell::Number(candidate.similarity as f64),
-                Cell::Number(candidate.zscore as f64),
-            ];
+                super::score_cell(config, candidate.similarity),
+                super::score_cell(config, candidate.zscore),
+            ]);
             if config.options.include_source_data {
                 if let Some(source_record) = &candidate.source_record {
                     row.push(Cell::String(source_record.title.clone()));
@@ -49,10 +82,7 @@ fn build_rows(config: &Config, records: &[OutputRecord]) -> Vec<Vec<Cell>> {
                     row.push(Cell::String(source_record.location.clone()));
                     row.push(Cell::String(source_record.year.to_string()));
                 } else {
-                    row.push(Cell::String("".to_string()));
-                    row.push(Cell::String("".to_string()));
-                    row.push(Cell::String("".to_string()));
-                    row.push(Cell::String("".to_string()));
+                    row.extend(super::empty_cells(4));
                 }
             }
             rows.push(row);
@@ -61,18 +91,23 @@ fn build_rows(config: &Config, records: &[OutputRecord]) -> Vec<Vec<Cell>> {
     }).collect()
 }
 
-fn output_csv_file(path: &str, headers: &[String], rows: &[Vec<Cell>]) {
-    let file = File::create(path).expect("Unable to create file");
-    let mut writer = BufWriter::new(&file);
-    output_csv_header(&mut writer, headers);
-    for row in rows {
-        output_csv_row(&mut writer, row);
-    }
+// The columns of the input record and its match_stat, the same on all rows of the record
+fn record_cells(config: &Config, record: &OutputRecord) -> Vec<Cell> {
+    vec![
+        Cell::String(super::with_edition(config, record.card.clone(), record.record.edition)),
+        Cell::Number(record.record.edition as f64),
+        Cell::String(record.record.title.clone()),
+        Cell::String(record.record.author.clone()),
+        Cell::String(record.record.location.clone()),
+        Cell::String(record.record.year.to_string()),
+        Cell::String(record.stats.to_string()),
+    ]
 }
 
 // When outputting in CSV format, there are two options based on the include-source-data option:
 // 1. If include-source-data is set, the output will include the source data for the matched records
 //  => card, edition_idx, title, author, location, year, match_stat, id, similarity, zscore, source_title, source_author, source_location, source_year
+//     Records without candidates get one row with empty id, similarity, zscore and source columns
 // 2. If include-source-data is not set, the output will only include the matched records
 //  => card, edition_idx, title, author, location, year, match_stat, id, similarity, zscore
 fn output_csv_header(output: &mut dyn Write, headers: &[String]) {
@@ -82,7 +117,7 @@ fn output_csv_header(output: &mut dyn Write, headers: &[String]) {
 fn output_csv_row(output: &mut dyn Write, row: &[Cell]) {
     let row_str = row.iter().map(|cell| match cell {
         Cell::String(s) => s.to_string(),
-        Cell::Number(n) => n.to_string(),
+        Cell::Number(n) | Cell::Score(n) => n.to_string(),
     }).collect::<Vec<String>>().join("\t");
     let _ = writeln!(output, "{}", row_str);
 }
@@ -1,6 +1,11 @@
-use crate::args::Config;
-use crate::matcher::OutputRecord;
+use crate::args::{Config, OutputFormat};
+use crate::cmd::Cmd;
+use crate::report;
+use crate::matcher::{MatchCandidate, MatchStatistics, OutputRecord};
+use super::Output;
+use rustc_hash::FxHashSet;
 use serde::Serialize;
+use std::io::Write;
 
 
 /// Writes data to a JSON file (.json)
@@ -19,7 +24,7 @@ use serde::Serialize;
 enum JsonRow {
     Normal(JsonRowNormal),
     Empty(JsonRowEmpty),
-    Extended(JsonRowExtended),
+    Extended(Box<JsonRowExtended>),
     EmptyExtended(JsonRowEmptyExtended),
 }
 
@@ -54,6 +59,14 @@ struct JsonRowEmpty {
     location: String,
     year: String,
     match_stat: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_author: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_location: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_year: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -74,6 +87,8 @@ struct JsonRowExtended {
     author: String,
     location: String,
     year: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowed_years: Option<String>,
     match_stat: String,
     id: String,
     similarity: f64,
@@ -90,6 +105,25 @@ struct JsonRowExtended {
     overlap_score: f64,
     adjusted_overlap_score: f64,
     jaro_winkler_score: f64,
+    ngram_jaccard: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explanation: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weights_profile: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title_similarity: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author_similarity: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location_similarity: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    year_similarity: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    confidence: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected_rank: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    combined_text: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -107,12 +141,240 @@ struct JsonRowEmptyExtended {
     author: String,
     location: String,
     year: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowed_years: Option<String>,
     match_stat: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_author: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_location: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_year: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expected_rank: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    combined_text: Option<String>,
+}
+
+// Row with the run id (see run_id.rs) as its first field
+#[derive(Debug, Serialize)]
+struct JsonRowWithRunId<'a> {
+    run_id: &'a str,
+    #[serde(flatten)]
+    row: &'a JsonRow,
+}
+
+// Metadata of the run in the envelope object of -O json-envelope
+#[derive(Debug, Serialize)]
+pub struct JsonMeta {
+    source: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    run_id: Option<String>,
+    options_summary: String, // The arguments of the command line in the report
+}
+
+// With -O json-envelope the rows are written as {"meta": {...}, "records": [...]} instead of a bare array
+#[derive(Debug, Serialize)]
+struct JsonEnvelope<'a, T: Serialize> {
+    meta: &'a JsonMeta,
+    records: T,
+}
+
+pub fn envelope_meta(config: &Config, run_id: Option<&str>) -> Option<JsonMeta> {
+    if !config.options.json_envelope {
+        return None;
+    }
+    Some(JsonMeta {
+        source: config.source.clone(),
+        run_id: run_id.map(str::to_string),
+        options_summary: report::command_line_options(config),
+    })
+}
+
+pub fn output_records(config: &Config, path: &str, records: &[OutputRecord], statistics: Option<&MatchStatistics>) {
+    let mut writer = create_writer(config, path, super::run_id(statistics));
+    writer.write_batch(config, records);
+    writer.finish();
+}
+
+// The JSON file writer, which for match-json-zip with -O resume-from starts with the rows of the previous output.
+// The previous output is read before the file is created, so it may be the same file.
+pub fn create_writer(config: &Config, path: &str, run_id: Option<&str>) -> JsonWriter {
+    let previous_rows = match &config.options.resume_from {
+        Some(previous) if matches!(config.cmd, Cmd::MatchJsonZip) => read_previous_rows(previous),
+        _ => vec![],
+    };
+    let mut writer = JsonWriter::create(path, run_id, envelope_meta(config, run_id));
+    for row in previous_rows {
+        let json = serde_json::to_string_pretty(&row).expect("Unable to serialize JSON row");
+        writer.write_element(&json).expect("Unable to write JSON file");
+    }
+    writer
+}
+
+// Records (card and edition) in the previous output of -O resume-from, which are not matched again.
+// The previous output must have the same kind of rows (extended or not) as this run, since they are
+// written to the same file. Cards are compared by card_ID in extended output, otherwise by the card
+// as given in the input, see record_key.
+pub fn previous_records(config: &Config) -> Option<FxHashSet<(String, usize)>> {
+    let path = config.options.resume_from.as_ref()?;
+    if !matches!((config.output_format, &config.output), (OutputFormat::Json, Output::File(_))) {
+        eprintln!("-O resume-from needs JSON output to a file (-F json -o <file>)");
+        std::process::exit(1);
+    }
+    let rows = read_previous_rows(path);
+    let extended_rows = rows.iter().any(|row| row.get("card_ID").is_some());
+    if !rows.is_empty() && extended_rows != config.options.extended_output {
+        let kind = |extended: bool| if extended { "extended" } else { "normal" };
+        eprintln!("Previous output {} has {} rows, but this run writes {} rows", path, kind(extended_rows), kind(config.options.extended_output));
+        eprintln!("Resume with the same extended-output setting as the previous run");
+        std::process::exit(1);
+    }
+    Some(rows.iter().filter_map(|row| previous_record(config, row)).collect())
+}
+
+// Key of an input record to look up in previous_records, the card as it is written to the output
+pub fn record_key(config: &Config, card: &str, edition: usize) -> (String, usize) {
+    let card = if config.options.extended_output { super::card_id(card) } else { card.to_string() };
+    (super::with_edition(config, card, edition), edition)
+}
+
+fn previous_record(config: &Config, row: &serde_json::Value) -> Option<(String, usize)> {
+    let card_field = if config.options.extended_output { "card_ID" } else { "card" };
+    let card = row.get(card_field)?.as_str()?;
+    let edition = row.get("edition_idx")?.as_u64()?;
+    Some((card.to_string(), edition as usize))
+}
+
+fn read_previous_rows(path: &str) -> Vec<serde_json::Value> {
+    let content = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Failed to read previous output {}: {}", path, e);
+        std::process::exit(1);
+    });
+    parse_previous_rows(&content).unwrap_or_else(|| {
+        eprintln!("Previous output {} is not a JSON output of match-json-zip", path);
+        std::process::exit(1);
+    })
+}
+
+// The rows of a bare array or an envelope (-O json-envelope). An output that was interrupted while
+// written in batches is cut after the last complete row, and one that was never written to has no rows.
+fn parse_previous_rows(content: &str) -> Option<Vec<serde_json::Value>> {
+    if content.trim().is_empty() {
+        return Some(vec![]);
+    }
+    let value = serde_json::from_str(content).ok().or_else(|| close_truncated(content))?;
+    match value {
+        serde_json::Value::Array(rows) => Some(rows),
+        serde_json::Value::Object(mut envelope) => match envelope.remove("records") {
+            Some(serde_json::Value::Array(rows)) => Some(rows),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// A complete row ends with a closing brace at the indentation of the rows, see JsonWriter
+fn close_truncated(content: &str) -> Option<serde_json::Value> {
+    let (row_end, array_end) = if content.trim_start().starts_with('{') {
+        ("\n    }", "\n  ]\n}")
+    } else {
+        ("\n  }", "\n]")
+    };
+    let end = content.rfind(row_end)? + row_end.len();
+    serde_json::from_str(&format!("{}{}", &content[..end], array_end)).ok()
+}
+
+// JSON file (a pretty printed array of rows) written one batch of records at a time.
+// The rows are written as elements of the array as they come, and the array is closed by finish,
+// giving the same file as serializing all rows at once. With an envelope the array is the
+// "records" field of the envelope object, written after "meta".
+pub struct JsonWriter {
+    writer: std::io::BufWriter<std::fs::File>,
+    run_id: Option<String>,
+    rows_written: usize,
+    // Text before the array, indentation of the elements and text after the array
+    start: String,
+    indent: &'static str,
+    end: &'static str,
+}
+
+impl JsonWriter {
+    pub fn create(path: &str, run_id: Option<&str>, meta: Option<JsonMeta>) -> JsonWriter {
+        let file = std::fs::File::create(path).expect("Unable to create file");
+        let (start, indent, end) = match meta {
+            Some(meta) => {
+                let meta = serde_json::to_string_pretty(&meta).expect("Unable to serialize JSON meta");
+                (format!("{{\n  \"meta\": {},\n  \"records\": ", meta.replace('\n', "\n  ")), "    ", "\n}")
+            },
+            None => (String::new(), "  ", ""),
+        };
+        JsonWriter {
+            writer: std::io::BufWriter::new(file),
+            run_id: run_id.map(str::to_string),
+            rows_written: 0,
+            start,
+            indent,
+            end,
+        }
+    }
+
+    pub fn write_batch(&mut self, config: &Config, records: &[OutputRecord]) {
+        for row in build_rows(config, records) {
+            let json = match &self.run_id {
+                Some(run_id) => serde_json::to_string_pretty(&JsonRowWithRunId { run_id, row: &row }),
+                None => serde_json::to_string_pretty(&row),
+            }.expect("Unable to serialize JSON row");
+            self.write_element(&json).expect("Unable to write JSON file");
+        }
+    }
+
+    // Each element indented one level deeper than the array, as serde_json::to_writer_pretty does
+    fn write_element(&mut self, json: &str) -> std::io::Result<()> {
+        if self.rows_written == 0 {
+            writeln!(self.writer, "{}[", self.start)?;
+        } else {
+            self.writer.write_all(b",\n")?;
+        }
+        for (index, line) in json.lines().enumerate() {
+            if index > 0 {
+                self.writer.write_all(b"\n")?;
+            }
+            write!(self.writer, "{}{}", self.indent, line)?;
+        }
+        self.rows_written += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) {
+        let array_end = if self.rows_written == 0 {
+            format!("{}[]", self.start)
+        } else {
+            format!("\n{}]", &self.indent[2..])
+        };
+        write!(self.writer, "{}{}", array_end, self.end)
+            .and_then(|_| self.writer.flush())
+            .expect("Unable to write JSON file");
+    }
 }
 
-pub fn output_records(config: &Config, path: &str, records: &[OutputRecord]) {
+// The rows as compact JSON on a single line, used for replies from the match server
+pub fn records_to_json_line(config: &Config, records: &[OutputRecord]) -> String {
     let rows = build_rows(config, records);
-    write_json_file(path, &rows).expect("Unable to write JSON file");
+    serde_json::to_string(&rows).expect("Unable to serialize JSON")
+}
+
+pub fn output_records_to_stdout(config: &Config, records: &[OutputRecord], statistics: Option<&MatchStatistics>) {
+    let rows = build_rows(config, records);
+    let stdout = std::io::stdout();
+    let result = match envelope_meta(config, super::run_id(statistics)) {
+        Some(meta) => serde_json::to_writer_pretty(stdout.lock(), &JsonEnvelope { meta: &meta, records: &rows }),
+        None => serde_json::to_writer_pretty(stdout.lock(), &rows),
+    };
+    result.expect("Unable to write JSON to stdout");
+    println!();
 }
 
 fn translate_publication_type(publication_type: &str) -> String {
@@ -128,28 +390,37 @@ fn translate_publication_type(publication_type: &str) -> String {
     }
 }
 
+// Source fields of no-match rows, empty strings with -O include-source-data so that all rows have the same fields
+fn empty_source_field(config: &Config) -> Option<String> {
+    config.options.include_source_data.then(String::new)
+}
+
 fn build_normal_row(config: &Config, record: &OutputRecord, rows: &mut Vec<JsonRow>) {
     if record.top.len() == 0 {
         // Special case when there are no matches (top is empty), we write a single row with the record data and No match, and nothing else
         rows.push(JsonRow::Empty(JsonRowEmpty {
-            card: record.card.clone(),
+            card: super::with_edition(config, record.card.clone(), record.record.edition),
             edition_idx: record.record.edition as u32,
             title: record.record.title.clone(),
             author: record.record.author.clone(),
             location: record.record.location.clone(),
             year: record.record.year.to_string(),
             match_stat: record.stats.to_string(),
+            source_title: empty_source_field(config),
+            source_author: empty_source_field(config),
+            source_location: empty_source_field(config),
+            source_year: empty_source_field(config),
         }));
         return;
     }
     for candidate in &record.top {
         let source_record_id = if let Some(source_record) = &candidate.source_record {
-            source_record.id.clone()
+            super::output_id(config, &source_record.id)
         } else {
             "".to_string()
         };
         let mut row = JsonRowNormal {
-            card: record.card.clone(),
+            card: super::with_edition(config, record.card.clone(), record.record.edition),
             edition_idx: record.record.edition as u32,
             title: record.record.title.clone(),
             author: record.record.author.clone(),
@@ -157,12 +428,12 @@ fn build_normal_row(config: &Config, record: &OutputRecord, rows: &mut Vec<JsonR
             year: record.record.year.to_string(),
             match_stat: record.stats.to_string(),
             id: source_record_id.clone(),
-            similarity: candidate.similarity as f64,
-            zscore: candidate.zscore as f64,
-            source_title: None,
-            source_author: None,
-            source_location: None,
-            source_year: None,
+            similarity: super::score(config, candidate.similarity),
+            zscore: super::score(config, candidate.zscore),
+            source_title: empty_source_field(config),
+            source_author: empty_source_field(config),
+            source_location: empty_source_field(config),
+            source_year: empty_source_field(config),
         };
         if config.options.include_source_data {
             if let Some(source_record) = &candidate.source_record {
@@ -177,15 +448,14 @@ fn build_normal_row(config: &Config, record: &OutputRecord, rows: &mut Vec<JsonR
 }
 
 fn build_extended_row(config: &Config, record: &OutputRecord, rows: &mut Vec<JsonRow>) {
-    // record.card is of style "box/card.json" (e.g. "003_00153.json")
-    // This gives: box="003", card="00153", json="003_00153.json" (record.card)
-    let parts: Vec<&str> = record.card.split('_').collect();
-    let box_name = parts.get(0).unwrap_or(&"").to_string();
-    let card_name = parts.get(1).unwrap_or(&"").replace(".json", "");
+    let (box_name, card_name) = super::box_and_card_name(&record.card);
     let json_name = record.card.clone();
-    let card_id = format!("{}_{}", box_name, card_name);
     let match_object_id = format!("{}_{}_{}", box_name, card_name, record.record.edition);
+    let card_name = super::with_edition(config, card_name, record.record.edition);
+    let card_id = super::with_edition(config, super::card_id(&record.card), record.record.edition);
     let card_type = translate_publication_type(&record.record.publication_type);
+    let allowed_years = if config.options.parse_year_ranges { Some(record.record.allowed_years_string()) } else { None };
+    let combined_text = config.options.output_combined_text.then(|| super::combined_text(config, record));
     if record.top.len() == 0 {
         // Special case when there are no matches (top is empty), we write a single row with the record data and No match, and nothing else
         rows.push(JsonRow::EmptyExtended(JsonRowEmptyExtended {
@@ -199,18 +469,25 @@ fn build_extended_row(config: &Config, record: &OutputRecord, rows: &mut Vec<Jso
             author: record.record.author.clone(),
             location: record.record.location.clone(),
             year: record.record.year.to_string(),
+            allowed_years: allowed_years.clone(),
             match_stat: record.stats.to_string(),
+            source_title: empty_source_field(config),
+            source_author: empty_source_field(config),
+            source_location: empty_source_field(config),
+            source_year: empty_source_field(config),
+            expected_rank: record.expected_rank,
+            combined_text: combined_text.clone(),
         }));
         return;
     }
-    for candidate in &record.top {
+    for (index, candidate) in record.top.iter().enumerate() {
         let source_record_id = if let Some(source_record) = &candidate.source_record {
-            source_record.id.clone()
+            super::output_id(config, &source_record.id)
         } else {
             "".to_string()
         };
         // matched_ID is the last part of the source_record.id after the last slash
-        let matched_id = source_record_id.split('/').last().unwrap_or("");
+        let matched_id = super::matched_id(&source_record_id, &config.options.matched_id_sep);
         let mut row = JsonRowExtended {
             box_name: box_name.clone(),
             card: card_name.clone(),
@@ -224,18 +501,29 @@ fn build_extended_row(config: &Config, record: &OutputRecord, rows: &mut Vec<Jso
             author: record.record.author.clone(),
             location: record.record.location.clone(),
             year: record.record.year.to_string(),
+            allowed_years: allowed_years.clone(),
             match_stat: record.stats.to_string(),
             id: source_record_id.clone(),
-            similarity: candidate.similarity as f64,
-            zscore: candidate.zscore as f64,
-            source_title: None,
-            source_author: None,
-            source_location: None,
-            source_year: None,
-            original_similarity: candidate.original_similarity as f64,
-            overlap_score: candidate.overlap_score as f64,
-            adjusted_overlap_score: candidate.adjusted_overlap_score as f64,
-            jaro_winkler_score: candidate.jaro_winkler_score as f64,
+            similarity: super::score(config, candidate.similarity),
+            zscore: super::score(config, candidate.zscore),
+            source_title: empty_source_field(config),
+            source_author: empty_source_field(config),
+            source_location: empty_source_field(config),
+            source_year: empty_source_field(config),
+            original_similarity: super::score(config, candidate.original_similarity),
+            overlap_score: super::score(config, candidate.overlap_score),
+            adjusted_overlap_score: super::score(config, candidate.adjusted_overlap_score),
+            jaro_winkler_score: super::score(config, candidate.jaro_winkler_score),
+            ngram_jaccard: super::score(config, candidate.ngram_jaccard),
+            explanation: if config.options.explain { Some(candidate.explanation.clone()) } else { None },
+            weights_profile: config.options.weights_profiles.as_ref().map(|_| candidate.weights_profile.clone()),
+            title_similarity: part_similarity(config, candidate, 0),
+            author_similarity: part_similarity(config, candidate, 1),
+            location_similarity: part_similarity(config, candidate, 2),
+            year_similarity: part_similarity(config, candidate, 3),
+            confidence: super::confidence(config, &record.top, index),
+            expected_rank: record.expected_rank,
+            combined_text: combined_text.clone(),
         };
         if config.options.include_source_data {
             if let Some(source_record) = &candidate.source_record {
@@ -245,8 +533,16 @@ fn build_extended_row(config: &Config, record: &OutputRecord, rows: &mut Vec<Jso
                 row.source_year = Some(source_record.year.to_string());
             }
         }
-        rows.push(JsonRow::Extended(row));
+        rows.push(JsonRow::Extended(Box::new(row)));
+    }
+}
+
+// Index into SIMILARITY_PARTS, None without -O output-part-similarities
+fn part_similarity(config: &Config, candidate: &MatchCandidate, index: usize) -> Option<f64> {
+    if !config.options.output_part_similarities {
+        return None;
     }
+    candidate.part_similarities.get(index).map(|similarity| super::score(config, *similarity))
 }
 
 fn build_rows(config: &Config, records: &[OutputRecord]) -> Vec<JsonRow> {
@@ -261,10 +557,68 @@ fn build_rows(config: &Config, records: &[OutputRecord]) -> Vec<JsonRow> {
     }).collect()
 }
 
-fn write_json_file(path: &str, rows: &[JsonRow]) -> Result<(), std::io::Error> {
-    let file = std::fs::File::create(path)?;
-    let writer = std::io::BufWriter::new(file);
-    serde_json::to_writer_pretty(writer, rows)?;
-    Ok(())
-}
+#[cfg(test)]
+mod tests {
+    use super::{parse_previous_rows, JsonEnvelope, JsonMeta, JsonWriter};
+    use serde_json::json;
+
+    #[test]
+    fn batches_give_the_same_file_as_one_array() {
+        let rows = vec![json!({"card": "001_1.json", "similarity": 0.5}), json!({"card": "001_2.json", "similarity": 0.25})];
+        let path = std::env::temp_dir().join(format!("json-writer-test-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut writer = JsonWriter::create(path, None, None);
+        for row in &rows {
+            writer.write_element(&serde_json::to_string_pretty(row).unwrap()).unwrap();
+        }
+        writer.finish();
+        let content = std::fs::read_to_string(path).unwrap();
+        assert_eq!(content, serde_json::to_string_pretty(&rows).unwrap());
 
+        JsonWriter::create(path, None, None).finish();
+        let content = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(content, "[]");
+    }
+
+    #[test]
+    fn batches_in_envelope_give_the_same_file_as_one_object() {
+        let meta = || JsonMeta {
+            source: "libris".to_string(),
+            run_id: Some("20240131T120000Z-1a2b3c4d".to_string()),
+            options_summary: "-c match-json-zip -s libris".to_string(),
+        };
+        let rows = vec![json!({"card": "001_1.json", "similarity": 0.5}), json!({"card": "001_2.json", "similarity": 0.25})];
+        let path = std::env::temp_dir().join(format!("json-envelope-test-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut writer = JsonWriter::create(path, None, Some(meta()));
+        for row in &rows {
+            writer.write_element(&serde_json::to_string_pretty(row).unwrap()).unwrap();
+        }
+        writer.finish();
+        let content = std::fs::read_to_string(path).unwrap();
+        assert_eq!(content, serde_json::to_string_pretty(&JsonEnvelope { meta: &meta(), records: &rows }).unwrap());
+
+        JsonWriter::create(path, None, Some(meta())).finish();
+        let content = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let empty: Vec<serde_json::Value> = vec![];
+        assert_eq!(content, serde_json::to_string_pretty(&JsonEnvelope { meta: &meta(), records: &empty }).unwrap());
+    }
+
+    #[test]
+    fn previous_rows_of_interrupted_output() {
+        let meta = JsonMeta { source: "libris".to_string(), run_id: None, options_summary: "".to_string() };
+        let rows = vec![json!({"card": "001_1.json", "edition_idx": 0}), json!({"card": "001_2.json", "edition_idx": 1})];
+        let array = serde_json::to_string_pretty(&rows).unwrap();
+        let envelope = serde_json::to_string_pretty(&JsonEnvelope { meta: &meta, records: &rows }).unwrap();
+        assert_eq!(parse_previous_rows(&array), Some(rows.clone()));
+        assert_eq!(parse_previous_rows(&envelope), Some(rows.clone()));
+        // Cut in the middle of the second row, only the first is complete
+        let cut = |content: &str| content[..content.find("001_2").unwrap()].to_string();
+        assert_eq!(parse_previous_rows(&cut(&array)), Some(rows[..1].to_vec()));
+        assert_eq!(parse_previous_rows(&cut(&envelope)), Some(rows[..1].to_vec()));
+        assert_eq!(parse_previous_rows(""), Some(vec![]));
+        assert_eq!(parse_previous_rows("{\"error\": 1}"), None);
+    }
+}
@@ -2,15 +2,20 @@ pub mod csv;
 pub mod xlsx;
 pub mod text;
 pub mod json;
+pub mod candidates;
+pub mod query_vectors;
+pub mod crosswalk;
 
 use crate::args::Config;
-use crate::matcher::OutputRecord;
+use crate::matcher::{MatchCandidate, MatchStatistics, OutputRecord};
+use crate::elastic::Record as ElasticRecord;
 use crate::args::OutputFormat;
 use serde::{Deserialize, Serialize};
 
 pub enum Cell {
     String(String),
     Number(f64),
+    Score(f64), // Similarity or other score, rounded with -O output-precision
 }
 
 #[allow(dead_code)]
@@ -20,19 +25,225 @@ pub enum Output {
     File(String),
 }
 
-pub fn output_records(config: &Config, records: &[OutputRecord]) {
-    // Create the output directory for options with path if it does not exist
+// The statistics of the run are given by match-json-zip, and are complete when the output is written.
+// Formats that include a summary take it from them, and the run id (see run_id.rs) is added as the
+// first column when given. Without statistics (match-single-json) there is no run id either.
+pub fn output_records(config: &Config, records: &[OutputRecord], statistics: Option<&MatchStatistics>) {
+    create_output_dir(config);
+    match (config.output_format, &config.output) {
+        (OutputFormat::Text, _) => text::output_records(config, records, statistics),
+        (OutputFormat::Json, Output::Stdout) => json::output_records_to_stdout(config, records, statistics),
+        (OutputFormat::Json, Output::File(path)) => json::output_records(config, path, records, statistics),
+        (OutputFormat::CSV, Output::File(path)) => csv::output_records(config, path, records, statistics),
+        (OutputFormat::XLSX, Output::File(path)) => xlsx::output_records(config, path, records, statistics),
+        (OutputFormat::Crosswalk, output) => crosswalk::output_records(config, output, records, statistics),
+        _ => unimplemented!("Output format not implemented"),
+    }
+}
+
+fn run_id(statistics: Option<&MatchStatistics>) -> Option<&str> {
+    statistics.map(|statistics| statistics.run_id.as_str())
+}
+
+// Create the output directory for options with path if it does not exist
+fn create_output_dir(config: &Config) {
     if let Output::File(path) = &config.output {
         if let Some(parent) = std::path::Path::new(path).parent() {
             std::fs::create_dir_all(parent).expect("Unable to create output directory");
         }
     }
-    match (config.output_format, &config.output) {
-        (OutputFormat::Text, Output::Stdout) => text::output_records(config,  records),
-        (OutputFormat::Json, Output::File(path)) => json::output_records(config, path, records),
-        (OutputFormat::CSV, Output::File(path)) => csv::output_records(config, path, records),
-        (OutputFormat::XLSX, Output::File(path)) => xlsx::output_records(config, path, records),
-        _ => unimplemented!("Output format not implemented"),
+}
+
+// Output written a batch of records at a time while matching (-O output-batch-size), so that
+// not all records are kept in memory. Only csv and json files can be appended to,
+// all other formats (xlsx, text, crosswalk and stdout) are written at once with output_records.
+pub enum OutputWriter {
+    Csv(csv::CsvWriter),
+    Json(json::JsonWriter),
+}
+
+impl OutputWriter {
+    // None if the output format cannot be written in batches
+    pub fn open(config: &Config, run_id: Option<&str>) -> Option<OutputWriter> {
+        let Output::File(path) = &config.output else {
+            return None;
+        };
+        create_output_dir(config);
+        let writer = match config.output_format {
+            OutputFormat::CSV => OutputWriter::Csv(csv::CsvWriter::create(config, path, run_id)),
+            OutputFormat::Json => OutputWriter::Json(json::create_writer(config, path, run_id)),
+            _ => return None,
+        };
+        Some(writer)
     }
+
+    pub fn write_batch(&mut self, config: &Config, records: &[OutputRecord]) {
+        match self {
+            OutputWriter::Csv(writer) => writer.write_batch(config, records),
+            OutputWriter::Json(writer) => writer.write_batch(config, records),
+        }
+    }
+
+    pub fn finish(self) {
+        match self {
+            OutputWriter::Csv(writer) => writer.finish(),
+            OutputWriter::Json(writer) => writer.finish(),
+        }
+    }
+}
+
+// The source record id as written to the output. With -O normalize-output-ids surrounding
+// whitespace and trailing slashes are removed, and with normalize-output-ids=lowercase it is also lowercased.
+// The id in the source data is not changed.
+pub fn output_id(config: &Config, id: &str) -> String {
+    if !config.options.normalize_output_ids {
+        return id.to_string();
+    }
+    let id = id.trim().trim_end_matches('/').trim_end();
+    if config.options.lowercase_output_ids {
+        id.to_lowercase()
+    } else {
+        id.to_string()
+    }
+}
+
+// record.card is of style "box_card.json" (e.g. "003_00153.json")
+// This gives: box="003", card="00153"
+pub fn box_and_card_name(card: &str) -> (String, String) {
+    let parts: Vec<&str> = card.split('_').collect();
+    let box_name = parts.first().unwrap_or(&"").to_string();
+    let card_name = parts.get(1).unwrap_or(&"").replace(".json", "");
+    (box_name, card_name)
 }
 
+// A card identifier as written to the output (the card, or the card and card_ID of extended output).
+// With -O edition-in-card-id the edition is added after a "#" ("003_00153.json#1"), so that each
+// edition of a multi-edition card has an identifier of its own.
+pub fn with_edition(config: &Config, card: String, edition: usize) -> String {
+    if config.options.edition_in_card_id {
+        format!("{}#{}", card, edition)
+    } else {
+        card
+    }
+}
+
+// card_ID in extended output and the crosswalk, "003_00153" for "003_00153.json"
+pub fn card_id(card: &str) -> String {
+    let (box_name, card_name) = box_and_card_name(card);
+    format!("{}_{}", box_name, card_name)
+}
+
+// matched_ID in extended output, the last part of the output id after the last separator
+// (-O matched-id-sep, "/" by default). Also used for the "matched_id" field of exclude files.
+pub fn matched_id<'a>(output_id: &'a str, separator: &str) -> &'a str {
+    output_id.rsplit(separator).next().unwrap_or("")
+}
+
+// Decimals of the scores with -O output-precision, limited to what an f64 can hold
+fn output_precision(config: &Config) -> Option<i32> {
+    config.options.output_precision.map(|precision| precision.clamp(0, 15))
+}
+
+// A score as written to the output, rounded to output-precision decimals if set.
+// The same value is used by all formats, so the numbers of a run do not depend on the format.
+pub fn score(config: &Config, value: f32) -> f64 {
+    match output_precision(config) {
+        Some(precision) => round_to(value as f64, precision),
+        None => value as f64,
+    }
+}
+
+fn round_to(value: f64, decimals: i32) -> f64 {
+    let factor = 10f64.powi(decimals);
+    (value * factor).round() / factor
+}
+
+pub fn score_cell(config: &Config, value: f32) -> Cell {
+    Cell::Score(score(config, value))
+}
+
+// confidence in extended output (-O confidence-score), 0-100 from a logistic of the similarity of the
+// candidate, its margin to the next candidate (to 0 for the last one) and its z-score:
+// steepness * (similarity - midpoint + margin_weight * margin) + z_weight * zscore.
+// With the defaults (midpoint 0.5, steepness 10, no margin or z-score weight) only the similarity counts,
+// similarity 0.5 gives 50, 0.7 gives 88 and 0.3 gives 12. None without the option.
+pub fn confidence(config: &Config, candidates: &[MatchCandidate], index: usize) -> Option<i32> {
+    if !config.options.confidence_score {
+        return None;
+    }
+    let options = &config.options;
+    let candidate = &candidates[index];
+    let margin = candidate.similarity - candidates.get(index + 1).map_or(0.0, |next| next.similarity);
+    let x = options.confidence_steepness * (candidate.similarity - options.confidence_midpoint + options.confidence_margin_weight * margin)
+        + options.confidence_z_weight * candidate.zscore;
+    Some(logistic_percent(x))
+}
+
+fn logistic_percent(x: f32) -> i32 {
+    (100.0 / (1.0 + (-x).exp())).round() as i32
+}
+
+// combined_text in extended output (-O output-combined-text): the combined ("all") text of the
+// input record as it is tokenized, in the combined-order and after add-author-to-title
+pub fn combined_text(config: &Config, record: &OutputRecord) -> String {
+    ElasticRecord::from(&record.record).combined(&config.options.combined_order)
+}
+
+// Blank cells for the columns of no-match rows with -O include-source-data
+pub fn empty_cells(count: usize) -> Vec<Cell> {
+    (0..count).map(|_| Cell::String("".to_string())).collect()
+}
+
+// Add the run id as a constant first column, first since rows without matches are shorter
+fn add_run_id_column(headers: &mut Vec<String>, rows: &mut [Vec<Cell>], run_id: Option<&str>) {
+    if run_id.is_some() {
+        headers.insert(0, "run_id".to_string());
+    }
+    add_run_id_cells(rows, run_id);
+}
+
+fn add_run_id_cells(rows: &mut [Vec<Cell>], run_id: Option<&str>) {
+    if let Some(run_id) = run_id {
+        for row in rows.iter_mut() {
+            row.insert(0, Cell::String(run_id.to_string()));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{logistic_percent, matched_id, round_to};
+    use crate::args::Config;
+    use crate::report::is_gold_id;
+
+    #[test]
+    fn matched_id_after_last_separator() {
+        assert_eq!(matched_id("http://libris.kb.se/bib/123", "/"), "123");
+        assert_eq!(matched_id("lib:sys:12345", ":"), "12345");
+        assert_eq!(matched_id("lib:sys:12345", "/"), "lib:sys:12345");
+        assert_eq!(matched_id("lib::sys::12345", "::"), "12345");
+        // Gold, known and re-rank ids may be given as the matched id
+        let config = Config::from_args(&["-c", "match-single-json", "-s", "test", "-O", "matched-id-sep=:"]);
+        assert!(is_gold_id(&config, "lib:sys:12345", "12345"));
+        assert!(is_gold_id(&config, "lib:sys:12345", "lib:sys:12345"));
+        assert!(!is_gold_id(&config, "lib:sys:12345", "sys:12345"));
+    }
+
+    #[test]
+    fn scores_rounded_to_precision() {
+        assert_eq!(round_to(0.7314285714285714, 3), 0.731);
+        assert_eq!(round_to(0.7315, 3).to_string(), "0.732");
+        assert_eq!(round_to(-1.25, 0), -1.0);
+    }
+
+    #[test]
+    fn confidence_from_logistic() {
+        assert_eq!(logistic_percent(0.0), 50);
+        // Similarity 0.7 and 0.3 with the default midpoint and steepness
+        assert_eq!(logistic_percent(2.0), 88);
+        assert_eq!(logistic_percent(-2.0), 12);
+        assert_eq!(logistic_percent(50.0), 100);
+        assert_eq!(logistic_percent(-50.0), 0);
+    }
+}
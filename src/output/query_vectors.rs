@@ -0,0 +1,57 @@
+use crate::args::Config;
+use crate::matcher::display_token;
+use crate::vocab::Vocab;
+use serde::Serialize;
+use std::io::{BufWriter, Write};
+
+// Debug file with the weighted average vector of each input record as it is compared to the
+// dataset vectors (-O dump-query-vectors=path), one JSON object per line (NDJSON), written while matching.
+
+#[derive(Debug, Serialize)]
+struct QueryVectorLine<'a> {
+    card: &'a str,
+    edition_idx: usize,
+    weights_profile: &'a str,
+    tokens: Vec<QueryToken>,
+}
+
+#[derive(Debug, Serialize)]
+struct QueryToken {
+    token: String,
+    value: f32,
+}
+
+pub fn open_query_vectors_file(config: &Config) -> Option<BufWriter<std::fs::File>> {
+    let filename = config.options.dump_query_vectors.as_ref()?;
+    if let Some(parent) = std::path::Path::new(filename).parent() {
+        std::fs::create_dir_all(parent).expect("Unable to create output directory");
+    }
+    let file = std::fs::File::create(filename).unwrap_or_else(|e| {
+        eprintln!("Failed to create query vectors file {}: {}", filename, e);
+        std::process::exit(1);
+    });
+    if config.verbose {
+        eprintln!("Writing query vectors to {}", filename);
+    }
+    Some(BufWriter::new(file))
+}
+
+// One line per weights profile, with the tokens ordered by value (highest first).
+// Records that were not matched (excluded, no edition, ...) have no vectors and are not written.
+pub fn write_query_vectors(writer: &mut BufWriter<std::fs::File>, vocab: &Vocab, card: &str, edition: usize, query_vectors: &[(String, Vec<(u32, f32)>)]) {
+    for (weights_profile, vector) in query_vectors {
+        let mut tokens = vector.iter().map(|(index, value)| QueryToken {
+            token: vocab.words.get(*index as usize).map(|word| display_token(word)).unwrap_or_default(),
+            value: *value,
+        }).collect::<Vec<QueryToken>>();
+        tokens.sort_by(|a, b| b.value.partial_cmp(&a.value).unwrap());
+        let line = QueryVectorLine {
+            card,
+            edition_idx: edition,
+            weights_profile,
+            tokens,
+        };
+        serde_json::to_writer(&mut *writer, &line).unwrap();
+        writer.write_all(b"\n").unwrap();
+    }
+}
@@ -2,9 +2,16 @@ use std::io::Write;
 use crate::args::Config;
 use crate::output::Output;
 use crate::matcher::OutputRecord;
-use crate::matcher::TOP_N;
+use crate::matcher::{MatchCandidate, MatchStatistics, TOP_N};
 
-pub fn output_records(config: &Config, records: &[OutputRecord]) {
+// Column widths of the source data in -O text-table, longer values are cut
+const TABLE_TITLE_WIDTH: usize = 40;
+const TABLE_AUTHOR_WIDTH: usize = 25;
+const TABLE_LOCATION_WIDTH: usize = 20;
+const TABLE_YEAR_WIDTH: usize = 9;
+const TABLE_SCORE_WIDTH: usize = 10;
+
+pub fn output_records(config: &Config, records: &[OutputRecord], _statistics: Option<&MatchStatistics>) {
     let mut writer: Box<dyn Write> = match &config.output {
         Output::Stdout => Box::new(std::io::stdout()),
         Output::File(filename) => {
@@ -13,6 +20,7 @@ pub fn output_records(config: &Config, records: &[OutputRecord]) {
         }
     };
     write_text_file(config, &mut writer, records);
+    writer.flush().expect("Unable to write text output");
 }
 
 fn write_text_file(config: &Config, writer: &mut dyn Write, records: &[OutputRecord]) {
@@ -28,11 +36,15 @@ fn output_header_text(_config: &Config, output: &mut dyn Write) {
 
 fn output_record_text(config: &Config, output: &mut dyn Write, record: &OutputRecord) {
     writeln!(output, "\n\nTop {} matches for record {} {}: {:?}", TOP_N, record.card, record.record.edition, record.record).unwrap();
+    if config.options.text_table {
+        output_candidates_table(config, output, &record.top);
+        return;
+    }
     for candidate in &record.top {
         let source_record_id = if let Some(source_record) = &candidate.source_record {
-            &source_record.id
+            super::output_id(config, &source_record.id)
         } else {
-            ""
+            "".to_string()
         };
         if config.options.include_source_data {
             if let Some(source_record) = &candidate.source_record {
@@ -41,7 +53,46 @@ fn output_record_text(config: &Config, output: &mut dyn Write, record: &OutputRe
                 continue;
             }
         } else {
-            println!("{}: {}  /  {}", source_record_id, candidate.similarity, candidate.zscore);
+            let _ = writeln!(output, "{}: {}  /  {}", source_record_id, candidate.similarity, candidate.zscore);
         }
     }
 }
+
+// The candidates as a table with one line per candidate and the columns padded to fixed widths
+// (the id column to the longest id, since ids are never cut). Source data columns are only
+// included with include-source-data, and are blank for candidates without source data.
+fn output_candidates_table(config: &Config, output: &mut dyn Write, candidates: &[MatchCandidate]) {
+    let candidates = &candidates[..candidates.len().min(TOP_N)];
+    let ids = candidates.iter().map(|candidate| match &candidate.source_record {
+        Some(source_record) => super::output_id(config, &source_record.id),
+        None => candidate.id.clone(),
+    }).collect::<Vec<String>>();
+    let id_width = ids.iter().map(|id| id.chars().count()).max().unwrap_or(0).max("id".len());
+    let mut header = format!("{:<id_width$}  {:>TABLE_SCORE_WIDTH$}  {:>TABLE_SCORE_WIDTH$}", "id", "similarity", "zscore");
+    if config.options.include_source_data {
+        header.push_str(&format!("  {:<TABLE_TITLE_WIDTH$}  {:<TABLE_AUTHOR_WIDTH$}  {:<TABLE_LOCATION_WIDTH$}  {:<TABLE_YEAR_WIDTH$}", "title", "author", "location", "year"));
+    }
+    let _ = writeln!(output, "{}", header.trim_end());
+    for (candidate, id) in candidates.iter().zip(ids) {
+        let mut line = format!("{:<id_width$}  {:>TABLE_SCORE_WIDTH$.4}  {:>TABLE_SCORE_WIDTH$.4}", id, candidate.similarity, candidate.zscore);
+        if config.options.include_source_data {
+            let (title, author, location, year) = match &candidate.source_record {
+                Some(source_record) => (source_record.title.as_str(), source_record.author.as_str(), source_record.location.as_str(), source_record.year.as_str()),
+                None => ("", "", "", ""),
+            };
+            line.push_str(&format!("  {:<TABLE_TITLE_WIDTH$}  {:<TABLE_AUTHOR_WIDTH$}  {:<TABLE_LOCATION_WIDTH$}  {:<TABLE_YEAR_WIDTH$}",
+                cut(title, TABLE_TITLE_WIDTH), cut(author, TABLE_AUTHOR_WIDTH), cut(location, TABLE_LOCATION_WIDTH), cut(year, TABLE_YEAR_WIDTH)));
+        }
+        let _ = writeln!(output, "{}", line.trim_end());
+    }
+}
+
+// Cut text to at most width characters, ending with "~" when it was cut
+fn cut(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut cut = text.chars().take(width - 1).collect::<String>();
+    cut.push('~');
+    cut
+}
@@ -1,7 +1,8 @@
-use rust_xlsxwriter::{Workbook, XlsxError, Format};
+use rust_xlsxwriter::{Workbook, Worksheet, XlsxError, Format};
 use crate::output::Cell;
 use crate::args::Config;
-use crate::matcher::OutputRecord;
+use crate::matcher::{MatchStatistics, OutputRecord, SIMILARITY_PARTS};
+use crate::report::{self, StatisticsValue};
 
 /// Writes data to either an Excel (.xlsx) or OpenDocument Spreadsheet (.ods) file.
 ///
@@ -14,10 +15,31 @@ use crate::matcher::OutputRecord;
 ///
 /// Returns an error if the file extension is not supported or if there is an issue writing the file.
 
-pub fn output_records(config: &Config, path: &str, records: &[OutputRecord]) {
-    let headers = build_headers(config);
-    let rows = build_rows(config, records);
-    write_excel_file(path, &headers, &rows).expect("Unable to write Excel file");
+pub fn output_records(config: &Config, path: &str, records: &[OutputRecord], statistics: Option<&MatchStatistics>) {
+    let mut headers = build_headers(config);
+    let mut rows = build_rows(config, records);
+    super::add_run_id_column(&mut headers, &mut rows, super::run_id(statistics));
+    let summary = match statistics {
+        Some(statistics) if config.options.xlsx_summary_sheet => Some(summary_rows(statistics)),
+        _ => None,
+    };
+    write_excel_file(path, &headers, &rows, summary.as_deref(), super::output_precision(config)).expect("Unable to write Excel file");
+}
+
+// The statistics of the markdown report as field and value, with the run id first and the prompt last
+fn summary_rows(statistics: &MatchStatistics) -> Vec<(String, Cell)> {
+    let mut rows = vec![("run_id".to_string(), Cell::String(statistics.run_id.clone()))];
+    rows.extend(report::statistics_rows(statistics).into_iter().map(|(label, value)| {
+        let cell = match value {
+            StatisticsValue::Count(count) => Cell::Number(count as f64),
+            StatisticsValue::Percent(percent) => Cell::Number((percent as f64 * 100.0).round() / 100.0),
+        };
+        (label.to_string(), cell)
+    }));
+    if !statistics.prompt_used.is_empty() {
+        rows.push(("Prompt".to_string(), Cell::String(statistics.prompt_used.clone())));
+    }
+    rows
 }
 
 fn build_headers(config: &Config) -> Vec<String> {
@@ -43,11 +65,16 @@ fn build_headers_extended(config: &Config) -> Vec<String> {
         "author".to_string(),
         "location".to_string(),
         "year".to_string(),
+    ];
+    if config.options.parse_year_ranges {
+        headers.push("allowed_years".to_string());
+    }
+    headers.extend(vec![
         "match_stat".to_string(),
         "id".to_string(),
         "similarity".to_string(),
         "zscore".to_string(),
-    ];
+    ]);
     if config.options.include_source_data {
         headers.push("source_title".to_string());
         headers.push("source_author".to_string());
@@ -58,6 +85,25 @@ fn build_headers_extended(config: &Config) -> Vec<String> {
     headers.push("overlap_score".to_string());
     headers.push("adjusted_overlap_score".to_string());
     headers.push("jaro_winkler_score".to_string());
+    headers.push("ngram_jaccard".to_string());
+    if config.options.explain {
+        headers.push("explanation".to_string());
+    }
+    if config.options.weights_profiles.is_some() {
+        headers.push("weights_profile".to_string());
+    }
+    if config.options.output_part_similarities {
+        headers.extend(SIMILARITY_PARTS.iter().map(|part| format!("{}_similarity", part)));
+    }
+    if config.options.confidence_score {
+        headers.push("confidence".to_string());
+    }
+    if config.options.known_ids_file.is_some() {
+        headers.push("expected_rank".to_string());
+    }
+    if config.options.output_combined_text {
+        headers.push("combined_text".to_string());
+    }
     headers
 }
 
@@ -89,7 +135,7 @@ fn build_normal_row(config: &Config, record: &OutputRecord, rows: &mut Vec<Vec<C
     if record.top.len() == 0 {
         // Special case when there are no matches (top is empty), we write a single row with the record data and No match, and nothing else
         rows.push(vec![
-            Cell::String(record.card.clone()),
+            Cell::String(super::with_edition(config, record.card.clone(), record.record.edition)),
             Cell::Number(record.record.edition as f64),
             Cell::String(record.record.title.clone()),
             Cell::String(record.record.author.clone()),
@@ -97,16 +143,20 @@ fn build_normal_row(config: &Config, record: &OutputRecord, rows: &mut Vec<Vec<C
             Cell::String(record.record.year.to_string()),
             Cell::String(record.stats.to_string()),
         ]);
+        if config.options.include_source_data {
+            // Empty id, similarity, zscore and source columns, so that all rows have the same columns
+            rows.last_mut().unwrap().extend(super::empty_cells(7));
+        }
         return;
     }
     for candidate in &record.top {
         let source_record_id = if let Some(source_record) = &candidate.source_record {
-            source_record.id.clone()
+            super::output_id(config, &source_record.id)
         } else {
             "".to_string()
         };
         let mut row = vec![
-            Cell::String(record.card.clone()),
+            Cell::String(super::with_edition(config, record.card.clone(), record.record.edition)),
             Cell::Number(record.record.edition as f64),
             Cell::String(record.record.title.clone()),
             Cell::String(record.record.author.clone()),
@@ -114,8 +164,8 @@ fn build_normal_row(config: &Config, record: &OutputRecord, rows: &mut Vec<Vec<C
             Cell::String(record.record.year.to_string()),
             Cell::String(record.stats.to_string()),
             Cell::String(source_record_id),
-            Cell::Number(candidate.similarity as f64),
-            Cell::Number(candidate.zscore as f64),
+            super::score_cell(config, candidate.similarity),
+            super::score_cell(config, candidate.zscore),
         ];
         if config.options.include_source_data {
             if let Some(source_record) = &candidate.source_record {
@@ -135,18 +185,15 @@ fn build_normal_row(config: &Config, record: &OutputRecord, rows: &mut Vec<Vec<C
 }
 
 fn build_extended_row(config: &Config, record: &OutputRecord, rows: &mut Vec<Vec<Cell>>) {
-    // record.card is of style "box/card.json" (e.g. "003_00153.json")
-    // This gives: box="003", card="00153", json="003_00153.json" (record.card)
-    let parts: Vec<&str> = record.card.split('_').collect();
-    let box_name = parts.get(0).unwrap_or(&"").to_string();
-    let card_name = parts.get(1).unwrap_or(&"").replace(".json", "");
+    let (box_name, card_name) = super::box_and_card_name(&record.card);
     let json_name = record.card.clone();
-    let card_id = format!("{}_{}", box_name, card_name);
     let match_object_id = format!("{}_{}_{}", box_name, card_name, record.record.edition);
+    let card_name = super::with_edition(config, card_name, record.record.edition);
+    let card_id = super::with_edition(config, super::card_id(&record.card), record.record.edition);
     let card_type = translate_publication_type(&record.record.publication_type);
     if record.top.len() == 0 {
         // Special case when there are no matches (top is empty), we write a single row with the record data and No match, and nothing else
-        rows.push(vec![
+        let mut row = vec![
             Cell::String(box_name),
             Cell::String(card_name),
             Cell::String(card_id),
@@ -159,18 +206,37 @@ fn build_extended_row(config: &Config, record: &OutputRecord, rows: &mut Vec<Vec
             Cell::String(record.record.author.clone()),
             Cell::String(record.record.location.clone()),
             Cell::String(record.record.year.to_string()),
-            Cell::String(record.stats.to_string()),
-        ]);
+        ];
+        if config.options.parse_year_ranges {
+            row.push(Cell::String(record.record.allowed_years_string()));
+        }
+        row.push(Cell::String(record.stats.to_string()));
+        if config.options.include_source_data || config.options.known_ids_file.is_some() || config.options.output_combined_text || config.options.output_part_similarities || config.options.confidence_score {
+            // Empty cells for the rest of the columns, so that all rows have the same columns
+            let source_columns = if config.options.include_source_data { 4 } else { 0 };
+            let score_columns = 5 + config.options.explain as usize + config.options.weights_profiles.is_some() as usize
+                + if config.options.output_part_similarities { SIMILARITY_PARTS.len() } else { 0 }
+                + config.options.confidence_score as usize;
+            row.extend(super::empty_cells(3 + source_columns + score_columns));
+        }
+        if config.options.known_ids_file.is_some() {
+            row.push(expected_rank_cell(record));
+        }
+        if config.options.output_combined_text {
+            row.push(Cell::String(super::combined_text(config, record)));
+        }
+        rows.push(row);
         return;
     }
-    for candidate in &record.top {
+    let combined_text = config.options.output_combined_text.then(|| super::combined_text(config, record));
+    for (index, candidate) in record.top.iter().enumerate() {
         let source_record_id = if let Some(source_record) = &candidate.source_record {
-            source_record.id.clone()
+            super::output_id(config, &source_record.id)
         } else {
             "".to_string()
         };
         // matched_ID is the last part of the source_record.id after the last slash
-        let matched_id = source_record_id.split('/').last().unwrap_or("");
+        let matched_id = super::matched_id(&source_record_id, &config.options.matched_id_sep);
         let mut row = vec![
             Cell::String(box_name.clone()),
             Cell::String(card_name.clone()),
@@ -184,11 +250,16 @@ fn build_extended_row(config: &Config, record: &OutputRecord, rows: &mut Vec<Vec
             Cell::String(record.record.author.clone()),
             Cell::String(record.record.location.clone()),
             Cell::String(record.record.year.to_string()),
+        ];
+        if config.options.parse_year_ranges {
+            row.push(Cell::String(record.record.allowed_years_string()));
+        }
+        row.extend(vec![
             Cell::String(record.stats.to_string()),
             Cell::String(source_record_id),
-            Cell::Number(candidate.similarity as f64),
-            Cell::Number(candidate.zscore as f64),
-        ];
+            super::score_cell(config, candidate.similarity),
+            super::score_cell(config, candidate.zscore),
+        ]);
         if config.options.include_source_data {
             if let Some(source_record) = &candidate.source_record {
                 row.push(Cell::String(source_record.title.clone()));
@@ -202,14 +273,41 @@ fn build_extended_row(config: &Config, record: &OutputRecord, rows: &mut Vec<Vec
                 row.push(Cell::String("".to_string()));
             }
         }
-        row.push(Cell::Number(candidate.original_similarity as f64));
-        row.push(Cell::Number(candidate.overlap_score as f64));
-        row.push(Cell::Number(candidate.adjusted_overlap_score as f64));
-        row.push(Cell::Number(candidate.jaro_winkler_score as f64));
+        row.push(super::score_cell(config, candidate.original_similarity));
+        row.push(super::score_cell(config, candidate.overlap_score));
+        row.push(super::score_cell(config, candidate.adjusted_overlap_score));
+        row.push(super::score_cell(config, candidate.jaro_winkler_score));
+        row.push(super::score_cell(config, candidate.ngram_jaccard));
+        if config.options.explain {
+            row.push(Cell::String(candidate.explanation.clone()));
+        }
+        if config.options.weights_profiles.is_some() {
+            row.push(Cell::String(candidate.weights_profile.clone()));
+        }
+        if config.options.output_part_similarities {
+            row.extend(candidate.part_similarities.iter().map(|similarity| super::score_cell(config, *similarity)));
+        }
+        if let Some(confidence) = super::confidence(config, &record.top, index) {
+            row.push(Cell::Number(confidence as f64));
+        }
+        if config.options.known_ids_file.is_some() {
+            row.push(expected_rank_cell(record));
+        }
+        if let Some(combined_text) = &combined_text {
+            row.push(Cell::String(combined_text.clone()));
+        }
         rows.push(row);
     }
 }
 
+// Empty for cards that are not in the known ids file
+fn expected_rank_cell(record: &OutputRecord) -> Cell {
+    match record.expected_rank {
+        Some(rank) => Cell::Number(rank as f64),
+        None => Cell::String("".to_string()),
+    }
+}
+
 fn build_rows(config: &Config, records: &[OutputRecord]) -> Vec<Vec<Cell>> {
     records.iter().flat_map(|record| {
         let mut rows = vec![];
@@ -222,14 +320,23 @@ fn build_rows(config: &Config, records: &[OutputRecord]) -> Vec<Vec<Cell>> {
     }).collect()
 }
 
-fn write_excel_file(path: &str, headers: &[String], rows: &[Vec<Cell>]) -> Result<(), XlsxError> {
+// Scores are rounded already with a precision, and shown with that many decimals
+fn write_excel_file(path: &str, headers: &[String], rows: &[Vec<Cell>], summary: Option<&[(String, Cell)]>, precision: Option<i32>) -> Result<(), XlsxError> {
     let mut workbook = Workbook::new();
-    let worksheet = workbook.add_worksheet();
+    if let Some(summary) = summary {
+        write_summary_sheet(workbook.add_worksheet(), summary)?;
+    }
+    // The data sheet keeps the name it has without a summary sheet
+    let worksheet = workbook.add_worksheet().set_name("Sheet1")?;
 
     // Write the headers: card, edition, title, author, location, year
     // in bold
     let bold = Format::new().set_bold();
     let wrap = Format::new().set_text_wrap();
+    let score_format = precision.map(|precision| match precision {
+        0 => Format::new().set_num_format("0"),
+        _ => Format::new().set_num_format(format!("0.{}", "0".repeat(precision as usize))),
+    });
 
     // Write header row (row 0, 0-indexed column)
     for (col_idx, header) in headers.iter().enumerate() {
@@ -247,6 +354,14 @@ fn write_excel_file(path: &str, headers: &[String], rows: &[Vec<Cell>]) -> Resul
                 Cell::Number(n) => {
                     worksheet.write_number(row_idx, col_idx as u16, *n)?;
                 }
+                Cell::Score(n) => match &score_format {
+                    Some(format) => {
+                        worksheet.write_number_with_format(row_idx, col_idx as u16, *n, format)?;
+                    }
+                    None => {
+                        worksheet.write_number(row_idx, col_idx as u16, *n)?;
+                    }
+                },
             }
         }
     }
@@ -255,3 +370,26 @@ fn write_excel_file(path: &str, headers: &[String], rows: &[Vec<Cell>]) -> Resul
     Ok(())
 }
 
+fn write_summary_sheet(worksheet: &mut Worksheet, summary: &[(String, Cell)]) -> Result<(), XlsxError> {
+    worksheet.set_name("Summary")?;
+    let bold = Format::new().set_bold();
+    let wrap = Format::new().set_text_wrap();
+    worksheet.write_with_format(0, 0, "Field", &bold)?;
+    worksheet.write_with_format(0, 1, "Value", &bold)?;
+    for (row_idx, (field, value)) in summary.iter().enumerate() {
+        let row_idx = (row_idx + 1) as u32;
+        worksheet.write(row_idx, 0, field)?;
+        match value {
+            Cell::String(s) => {
+                worksheet.write_with_format(row_idx, 1, s, &wrap)?;
+            }
+            Cell::Number(n) | Cell::Score(n) => {
+                worksheet.write_number(row_idx, 1, *n)?;
+            }
+        }
+    }
+    worksheet.set_column_width(0, 40)?;
+    worksheet.set_column_width(1, 60)?;
+    Ok(())
+}
+
@@ -1,21 +1,112 @@
-use crate::args::{Config, JaroTruncate};
-use crate::matcher::{vector_weights, MatchStatistics, MatchStat};
+use crate::args::{AuthorNormalize, Config, DedupInput, JaroTruncate, OptionSource, OverlapField, ReportKind, ScoreTransform, TokenizerMode, YearGranularity, ZscoreMethod, json_schema_version_name, gold_card_name};
+use crate::matcher::{vector_weights, MatchStatistics, MatchStat, OutputRecord};
 use crate::output::Output;
+use crate::elastic;
 use rustc_hash::FxHashMap;
+use std::collections::BTreeMap;
 use std::io::Write;
 use serde::{Serialize, Deserialize};
 
 #[derive(Serialize, Deserialize, Debug)]
 struct JsonReport {
+    run_id: String,
     source: String,
     input: String,
+    inputs: Vec<String>,
     output: Output,
     vocab_file: String,
     dataset_vector_file: String,
     source_data_file: String,
+    dataset_vector_files: Vec<String>,
+    source_data_files: Vec<String>,
     weights: FxHashMap<String, f32>,
     options: JsonReportConfigOptions,
     stats: JsonMatchStatistics,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gold: Option<GoldEvaluation>,
+}
+
+// Values of k for the top-k hit rates in the gold evaluation
+const GOLD_TOP_K: [usize; 3] = [1, 5, 10];
+
+// Agreement between the matches and a gold file (-O gold-file), counted per card.
+// The prediction for a card is its best candidate over all editions. Cards that are
+// not in the gold file are not counted, neither are gold cards that are not in the input.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct GoldEvaluation {
+    pub cards: usize, // Cards in both the input and the gold file
+    pub expected_matches: usize, // Cards where the gold file has a source id
+    pub predicted_matches: usize, // Cards with at least one candidate
+    pub correct_matches: usize, // Best candidate is the gold source id
+    pub correct_no_matches: usize, // No candidates, and no source id in the gold file
+    pub precision: f32, // correct_matches / predicted_matches
+    pub recall: f32, // correct_matches / expected_matches
+    pub accuracy: f32, // (correct_matches + correct_no_matches) / cards
+    pub top_k_hit_rate: BTreeMap<usize, f32>, // Share of expected_matches with the gold source id among the k best candidates
+}
+
+// Compare the output records to the gold file, None if no gold file is given
+pub fn evaluate_gold(config: &Config, output_records: &[OutputRecord]) -> Option<GoldEvaluation> {
+    config.options.gold_file.as_ref()?;
+    // Card => (similarity, source id) of the candidates of all its editions
+    let mut card_candidates: FxHashMap<String, Vec<(f32, String)>> = FxHashMap::default();
+    for output_record in output_records {
+        let candidates = card_candidates.entry(gold_card_name(&output_record.card)).or_default();
+        candidates.extend(output_record.top.iter().map(|candidate| (candidate.similarity, candidate.id.clone())));
+    }
+    let ranked_ids = card_candidates.into_iter().map(|(card, mut candidates)| {
+        candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        let mut ids: Vec<String> = vec![];
+        for (_, id) in candidates {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+        (card, ids)
+    }).collect::<FxHashMap<String, Vec<String>>>();
+    Some(gold_evaluation(config, &config.options.gold_ids, &ranked_ids))
+}
+
+// Source ids in the gold file may be the full id or its last part (as matched_ID in the output, after -O matched-id-sep)
+pub fn is_gold_id(config: &Config, id: &str, gold_id: &str) -> bool {
+    id == gold_id || crate::output::matched_id(id, &config.options.matched_id_sep) == gold_id
+}
+
+fn gold_evaluation(config: &Config, gold_ids: &FxHashMap<String, String>, ranked_ids: &FxHashMap<String, Vec<String>>) -> GoldEvaluation {
+    let mut evaluation = GoldEvaluation::default();
+    let mut top_k_hits = [0; GOLD_TOP_K.len()];
+    for (card, gold_id) in gold_ids {
+        let Some(ids) = ranked_ids.get(card) else {
+            continue;
+        };
+        evaluation.cards += 1;
+        if !ids.is_empty() {
+            evaluation.predicted_matches += 1;
+        }
+        if gold_id.is_empty() {
+            if ids.is_empty() {
+                evaluation.correct_no_matches += 1;
+            }
+            continue;
+        }
+        evaluation.expected_matches += 1;
+        if ids.first().is_some_and(|id| is_gold_id(config, id, gold_id)) {
+            evaluation.correct_matches += 1;
+        }
+        for (index, k) in GOLD_TOP_K.iter().enumerate() {
+            if ids.iter().take(*k).any(|id| is_gold_id(config, id, gold_id)) {
+                top_k_hits[index] += 1;
+            }
+        }
+    }
+    let ratio = |count: usize, total: usize| if total == 0 { 0.0 } else { count as f32 / total as f32 };
+    evaluation.precision = ratio(evaluation.correct_matches, evaluation.predicted_matches);
+    evaluation.recall = ratio(evaluation.correct_matches, evaluation.expected_matches);
+    evaluation.accuracy = ratio(evaluation.correct_matches + evaluation.correct_no_matches, evaluation.cards);
+    for (index, k) in GOLD_TOP_K.iter().enumerate() {
+        evaluation.top_k_hit_rate.insert(*k, ratio(top_k_hits[index], evaluation.expected_matches));
+    }
+    evaluation
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -23,6 +114,9 @@ struct JsonMatchStatistics {
     number_of_records: usize,
     match_types: FxHashMap<String, usize>,
     prompt: String,
+    warnings: usize,
+    duplicate_cards: usize,
+    resumed_records: usize,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -30,30 +124,105 @@ struct JsonReportConfigOptions {
     force_year: bool,
     year_tolerance: Option<i32>,
     year_tolerance_penalty: f32,
+    empty_author_penalty: f32,
+    parse_year_ranges: bool,
+    use_first_parsed_year: bool,
     include_source_data: bool,
+    text_table: bool,
+    require_type_match: bool,
     similarity_threshold: Option<f32>,
+    title_threshold: Option<f32>,
+    author_threshold: Option<f32>,
     z_threshold: Option<f32>,
+    adaptive_z: Option<f32>,
+    min_pool_for_z: Option<i32>,
+    zscore_method: String,
+    score_transform: String,
     min_single_similarity: Option<f32>,
     min_multiple_similarity: Option<f32>,
+    ambiguous_spread: Option<f32>,
+    detect_exact: bool,
+    fail_if_nomatch_above: Option<f32>,
+    quiet: bool,
     weights_file: Option<String>,
+    weights_profiles: Option<String>,
+    inline_weights: FxHashMap<String, f32>,
+    use_combined_only: bool,
+    all_fallback: Option<i32>,
+    length_normalize: bool,
     extended_output: bool,
+    explain: bool,
+    output_combined_text: bool,
+    xlsx_summary_sheet: bool,
+    json_envelope: bool,
+    output_part_similarities: bool,
+    confidence_score: bool,
+    confidence_midpoint: f32,
+    confidence_steepness: f32,
+    confidence_margin_weight: f32,
+    confidence_z_weight: f32,
+    normalize_output_ids: bool,
+    lowercase_output_ids: bool,
+    matched_id_sep: String,
+    merge_editions: bool,
+    max_rows_per_card: Option<i32>,
+    output_batch_size: Option<i32>,
+    edition_in_card_id: bool,
+    resume_from: Option<String>,
+    output_precision: Option<i32>,
+    report: String,
+    candidates_dump: Option<i32>,
+    trace_filtering: Option<i32>,
+    dump_query_vectors: Option<String>,
+    location_gazetteer: Option<String>,
+    strip_leading_articles: Option<String>,
+    acronym_file: Option<String>,
+    rerank_table: Option<String>,
+    gold_file: Option<String>,
+    known_ids_file: Option<String>,
+    prompt_file: Option<String>,
+    tokenizer: String,
+    year_granularity: String,
+    sentinels: bool,
+    numeric_tokens: bool,
+    clean_year: bool,
+    part_idf: bool,
+    author_normalize: String,
+    combined_order: Vec<String>,
+    skip_source_check: bool,
+    lazy_vectors: bool,
+    no_vector_cache: bool,
     add_author_to_title: bool,
+    skip_empty_title: bool,
     overlap_adjustment: Option<i32>,
+    overlap_min_title_len: i32,
+    overlap_max_len: Option<i32>,
+    overlap_field: String,
     jaro_winkler_adjustment: bool,
     jaro_winkler_author_adjustment: bool,
     jaro_winkler_truncate: Option<String>,
-    json_schema_version: i32,
+    json_schema_version: i32, // 0 is auto
+    dedup_input: String,
     dataset_dir: String,
     exclude_files: Vec<String>,
+    exclude_where: Vec<String>,
+    exclude_id_field: String,
     input_exclude_files: Vec<String>,
 }
 
 pub fn output_report(config: &Config, stats: &MatchStatistics) {
     // Output JSON report
-    output_json_report(config, stats);
+    if config.options.report.json() {
+        output_json_report(config, stats);
+    }
 
     // Output markdown report
-    output_markdown_report(config, stats);
+    if config.options.report.markdown() {
+        output_markdown_report(config, stats);
+    }
+
+    // Output the input warnings
+    output_warnings(config, stats);
 
 }
 
@@ -68,45 +237,123 @@ fn output_json_report(config: &Config, stats: &MatchStatistics) {
     for (key, value) in &stats.match_types {
         match_types.insert(key.to_string(), *value);
     }
+    let run_id = stats.run_id.clone();
+    let gold = stats.gold.clone();
     let stats = JsonMatchStatistics {
         number_of_records: stats.number_of_records,
         match_types,
         prompt: stats.prompt_used.clone(),
+        warnings: stats.warnings.len(),
+        duplicate_cards: stats.duplicate_cards(),
+        resumed_records: stats.resumed_records,
     };
 
     let options = JsonReportConfigOptions {
         force_year: config.options.force_year,
         year_tolerance: config.options.year_tolerance,
         year_tolerance_penalty: config.options.year_tolerance_penalty,
+        empty_author_penalty: config.options.empty_author_penalty,
+        parse_year_ranges: config.options.parse_year_ranges,
+        use_first_parsed_year: config.options.use_first_parsed_year,
         include_source_data: config.options.include_source_data,
+        text_table: config.options.text_table,
+        require_type_match: config.options.require_type_match,
         similarity_threshold: config.options.similarity_threshold,
+        title_threshold: config.options.title_threshold,
+        author_threshold: config.options.author_threshold,
         z_threshold: config.options.z_threshold,
+        adaptive_z: config.options.adaptive_z,
+        min_pool_for_z: config.options.min_pool_for_z,
+        zscore_method: config.options.zscore_method.to_string(),
+        score_transform: config.options.score_transform.to_string(),
         min_single_similarity: config.options.min_single_similarity,
         min_multiple_similarity: config.options.min_multiple_similarity,
+        ambiguous_spread: config.options.ambiguous_spread,
+        detect_exact: config.options.detect_exact,
+        fail_if_nomatch_above: config.options.fail_if_nomatch_above,
+        quiet: config.options.quiet,
         weights_file: config.options.weights_file.clone(),
+        weights_profiles: config.options.weights_profiles.clone(),
+        inline_weights: config.options.inline_weights.clone(),
+        use_combined_only: config.options.use_combined_only,
+        all_fallback: config.options.all_fallback,
+        length_normalize: config.options.length_normalize,
         extended_output: config.options.extended_output,
+        explain: config.options.explain,
+        output_combined_text: config.options.output_combined_text,
+        xlsx_summary_sheet: config.options.xlsx_summary_sheet,
+        json_envelope: config.options.json_envelope,
+        output_part_similarities: config.options.output_part_similarities,
+        confidence_score: config.options.confidence_score,
+        confidence_midpoint: config.options.confidence_midpoint,
+        confidence_steepness: config.options.confidence_steepness,
+        confidence_margin_weight: config.options.confidence_margin_weight,
+        confidence_z_weight: config.options.confidence_z_weight,
+        normalize_output_ids: config.options.normalize_output_ids,
+        lowercase_output_ids: config.options.lowercase_output_ids,
+        matched_id_sep: config.options.matched_id_sep.clone(),
+        merge_editions: config.options.merge_editions,
+        max_rows_per_card: config.options.max_rows_per_card,
+        output_batch_size: config.options.output_batch_size,
+        edition_in_card_id: config.options.edition_in_card_id,
+        resume_from: config.options.resume_from.clone(),
+        output_precision: config.options.output_precision,
+        report: config.options.report.to_string(),
+        candidates_dump: config.options.candidates_dump,
+        trace_filtering: config.options.trace_filtering,
+        dump_query_vectors: config.options.dump_query_vectors.clone(),
+        location_gazetteer: config.options.location_gazetteer.clone(),
+        strip_leading_articles: config.options.strip_leading_articles.clone(),
+        acronym_file: config.options.acronym_file.clone(),
+        rerank_table: config.options.rerank_table.clone(),
+        gold_file: config.options.gold_file.clone(),
+        known_ids_file: config.options.known_ids_file.clone(),
+        prompt_file: config.options.prompt_file.clone(),
+        tokenizer: config.options.tokenizer.to_string(),
+        year_granularity: config.options.year_granularity.to_string(),
+        sentinels: config.options.sentinels,
+        numeric_tokens: config.options.numeric_tokens,
+        clean_year: config.options.clean_year,
+        part_idf: config.options.part_idf,
+        author_normalize: config.options.author_normalize.to_string(),
+        combined_order: config.options.combined_order.clone(),
+        skip_source_check: config.options.skip_source_check,
+        lazy_vectors: config.options.lazy_vectors,
+        no_vector_cache: config.options.no_vector_cache,
         add_author_to_title: config.options.add_author_to_title,
+        skip_empty_title: config.options.skip_empty_title,
         overlap_adjustment: config.options.overlap_adjustment,
+        overlap_min_title_len: config.options.overlap_min_title_len,
+        overlap_max_len: config.options.overlap_max_len,
+        overlap_field: config.options.overlap_field.to_string(),
         jaro_winkler_adjustment: config.options.jaro_winkler_adjustment,
         jaro_winkler_author_adjustment: config.options.jaro_winkler_author_adjustment,
         jaro_winkler_truncate: config.options.jaro_winkler_truncate.into(),
         json_schema_version: config.options.json_schema_version,
+        dedup_input: config.options.dedup_input.to_string(),
         dataset_dir: config.options.dataset_dir.clone(),
         exclude_files: config.options.exclude_files.clone(),
+        exclude_where: config.options.exclude_where.iter().map(|predicate| predicate.to_string()).collect(),
+        exclude_id_field: config.options.exclude_id_field.clone(),
         input_exclude_files: config.options.input_exclude_files.clone(),
     };
 
     // Create a JSON report
     let report = JsonReport {
+        run_id,
         source: config.source.clone(),
         input: config.input.clone(),
+        inputs: config.inputs.clone(),
         output: config.output.clone(),
         vocab_file: config.vocab_file.clone(),
         dataset_vector_file: config.dataset_vector_file.clone(),
         source_data_file: config.source_data_file.clone(),
+        dataset_vector_files: config.dataset_vector_files.clone(),
+        source_data_files: config.source_data_files.clone(),
         weights: vector_weights(config),
         options: options,
         stats: stats,
+        gold,
     };
 
     // Write the report to a file in the same name standard as the markdown report,
@@ -157,6 +404,23 @@ fn output_markdown_report(config: &Config, stats: &MatchStatistics) {
     report_file.write_all(markdown.as_bytes()).unwrap();
 }
 
+// Write the warnings collected while loading the input files to a file with the same name
+// as the output file, but with the suffix -warnings.json instead of the original extension.
+// The file is written even when there are no warnings, as a record that the input was checked.
+fn output_warnings(config: &Config, stats: &MatchStatistics) {
+    let mut warnings_filename = match &config.output {
+        Output::File(filename) => filename.clone(),
+        Output::Stdout => return,
+    };
+    if let Some(pos) = warnings_filename.rfind('.') {
+        warnings_filename = warnings_filename[..pos].to_string();
+    }
+    warnings_filename.push_str("-warnings.json");
+    let mut warnings_file = std::fs::File::create(warnings_filename).unwrap();
+    let json = serde_json::to_string_pretty(&stats.warnings).unwrap();
+    warnings_file.write_all(json.as_bytes()).unwrap();
+}
+
 // The markdown will contain the following:
 // The source used.
 // The weights used.
@@ -168,16 +432,17 @@ fn create_markdown(config: &Config, stats: &MatchStatistics) -> String {
     // Output a table of data values, source, input file, output file, vocab file, vector file
     markdown.push_str(&format!("| {} | {} |\n", "Field", "Value"));
     markdown.push_str("| --- | --- |\n");
+    markdown.push_str(&format!("| {} | {} |\n", "run_id", stats.run_id));
     markdown.push_str(&format!("| {} | {} |\n", "source", config.source));
-    markdown.push_str(&format!("| {} | {} |\n", "input file", config.input));
+    markdown.push_str(&format!("| {} | {} |\n", "input file", config.inputs.join(", ")));
     if let Output::File(filename) = &config.output {
         markdown.push_str(&format!("| {} | {} |\n", "output file", filename));
     } else {
         markdown.push_str(&format!("| {} | {} |\n", "output file", "stdout"));
     }
     markdown.push_str(&format!("| {} | {} |\n", "vocab file", config.vocab_file));
-    markdown.push_str(&format!("| {} | {} |\n", "vector file", config.dataset_vector_file));
-    markdown.push_str(&format!("| {} | {} |\n", "source data file", config.source_data_file));
+    markdown.push_str(&format!("| {} | {} |\n", "vector file", config.dataset_vector_files.join(", ")));
+    markdown.push_str(&format!("| {} | {} |\n", "source data file", config.source_data_files.join(", ")));
     markdown.push_str("\n");
     markdown.push_str("## Weights\n\n");
     // Output the weights in a table
@@ -192,78 +457,242 @@ fn create_markdown(config: &Config, stats: &MatchStatistics) -> String {
     markdown.push_str("\n");
     markdown.push_str("## Options\n\n");
     // Output the options in a table
-    markdown.push_str(&format!("| {} | {} |\n", "Option", "Value"));
-    markdown.push_str("| --- | --- |\n");
-    markdown.push_str(&format!("| {} | {} |\n", "force_year", config.options.force_year));
+    markdown.push_str(&format!("| {} | {} | {} |\n", "Option", "Value", "Source"));
+    markdown.push_str("| --- | --- | --- |\n");
+    option_row(&mut markdown, config, "force_year", config.options.force_year);
     if config.options.force_year {
-        markdown.push_str(&format!("| {} | {} |\n", "year_tolerance", if let Some(tol) = config.options.year_tolerance { tol.to_string() } else { "none".to_string() }));
-        markdown.push_str(&format!("| {} | {} |\n", "year_tolerance_penalty", if let Some(_tol) = config.options.year_tolerance { config.options.year_tolerance_penalty.to_string() } else { "N/A".to_string() }));
-    }
-    markdown.push_str(&format!("| {} | {} |\n", "include_source_data", config.options.include_source_data));
-    markdown.push_str(&format!("| {} | {} |\n", "similarity_threshold", config.options.similarity_threshold.unwrap_or(0.0)));
-    markdown.push_str(&format!("| {} | {} |\n", "z_threshold", config.options.z_threshold.unwrap_or(0.0)));
-    markdown.push_str(&format!("| {} | {} |\n", "min_single_similarity", config.options.min_single_similarity.unwrap_or(0.0)));
-    markdown.push_str(&format!("| {} | {} |\n", "weights_file", config.options.weights_file.as_ref().unwrap_or(&"default weights".to_string())));
-    markdown.push_str(&format!("| {} | {} |\n", "extended_output", config.options.extended_output));
-    markdown.push_str(&format!("| {} | {} |\n", "add_author_to_title", config.options.add_author_to_title));
-    markdown.push_str(&format!("| {} | {} |\n", "overlap_adjustment", config.options.overlap_adjustment.unwrap_or(-1)));
-    markdown.push_str(&format!("| {} | {} |\n", "jaro_winkler_adjustment", config.options.jaro_winkler_adjustment));
-    markdown.push_str(&format!("| {} | {} |\n", "jaro_winkler_author_adjustment", config.options.jaro_winkler_author_adjustment));
-    markdown.push_str(&format!("| {} | {} |\n", "jaro_winkler_truncate", &config.options.jaro_winkler_truncate));
-    markdown.push_str(&format!("| {} | {} |\n", "json_schema_version", config.options.json_schema_version));
-    markdown.push_str(&format!("| {} | {} |\n", "dataset_dir", config.options.dataset_dir));
-    markdown.push_str(&format!("| {} | {} |\n", "exclude_files", if config.options.exclude_files.is_empty() { "none".to_string() } else { config.options.exclude_files.join(", ") }));
-    markdown.push_str(&format!("| {} | {} |\n", "input_exclude_files", if config.options.input_exclude_files.is_empty() { "none".to_string() } else { config.options.input_exclude_files.join(", ") }));
-    markdown.push_str(&format!("| {} | {} |\n", "min-multiple_similarity", config.options.min_multiple_similarity.unwrap_or(0.0)));
+        option_row(&mut markdown, config, "year_tolerance", if let Some(tol) = config.options.year_tolerance { tol.to_string() } else { "none".to_string() });
+        option_row(&mut markdown, config, "year_tolerance_penalty", if let Some(_tol) = config.options.year_tolerance { config.options.year_tolerance_penalty.to_string() } else { "N/A".to_string() });
+    }
+    option_row(&mut markdown, config, "empty_author_penalty", config.options.empty_author_penalty);
+    option_row(&mut markdown, config, "parse_year_ranges", config.options.parse_year_ranges);
+    if config.options.parse_year_ranges {
+        option_row(&mut markdown, config, "use_first_parsed_year", config.options.use_first_parsed_year);
+    }
+    option_row(&mut markdown, config, "include_source_data", config.options.include_source_data);
+    option_row(&mut markdown, config, "text_table", config.options.text_table);
+    option_row(&mut markdown, config, "require_type_match", config.options.require_type_match);
+    option_row(&mut markdown, config, "similarity_threshold", config.options.similarity_threshold.unwrap_or(0.0));
+    option_row(&mut markdown, config, "title_threshold", config.options.title_threshold.map(|threshold| threshold.to_string()).unwrap_or("none".to_string()));
+    option_row(&mut markdown, config, "author_threshold", config.options.author_threshold.map(|threshold| threshold.to_string()).unwrap_or("none".to_string()));
+    option_row(&mut markdown, config, "z_threshold", config.options.z_threshold.unwrap_or(0.0));
+    option_row(&mut markdown, config, "adaptive_z", config.options.adaptive_z.map(|floor| floor.to_string()).unwrap_or("off".to_string()));
+    option_row(&mut markdown, config, "min_pool_for_z", config.options.min_pool_for_z.map(|size| size.to_string()).unwrap_or("off".to_string()));
+    option_row(&mut markdown, config, "zscore_method", config.options.zscore_method);
+    option_row(&mut markdown, config, "score_transform", config.options.score_transform);
+    option_row(&mut markdown, config, "min_single_similarity", config.options.min_single_similarity.unwrap_or(0.0));
+    let weights_file = match (&config.options.weights_file, &config.options.config_weights) {
+        (Some(weights_file), _) => weights_file.clone(),
+        (None, Some(_)) => "config file weights".to_string(),
+        (None, None) => "default weights".to_string(),
+    };
+    option_row(&mut markdown, config, "weights_file", weights_file);
+    option_row(&mut markdown, config, "weights_profiles", config.options.weights_profiles.as_ref().unwrap_or(&"none".to_string()));
+    option_row(&mut markdown, config, "inline_weights", inline_weights_markdown(config));
+    option_row(&mut markdown, config, "use_combined_only", config.options.use_combined_only);
+    option_row(&mut markdown, config, "all_fallback", config.options.all_fallback.map_or("off".to_string(), |x| x.to_string()));
+    option_row(&mut markdown, config, "length_normalize", config.options.length_normalize);
+    option_row(&mut markdown, config, "extended_output", config.options.extended_output);
+    option_row(&mut markdown, config, "explain", config.options.explain);
+    option_row(&mut markdown, config, "output_combined_text", config.options.output_combined_text);
+    option_row(&mut markdown, config, "xlsx_summary_sheet", config.options.xlsx_summary_sheet);
+    option_row(&mut markdown, config, "json_envelope", config.options.json_envelope);
+    option_row(&mut markdown, config, "output_part_similarities", config.options.output_part_similarities);
+    option_row(&mut markdown, config, "confidence_score", config.options.confidence_score);
+    option_row(&mut markdown, config, "confidence_midpoint", config.options.confidence_midpoint);
+    option_row(&mut markdown, config, "confidence_steepness", config.options.confidence_steepness);
+    option_row(&mut markdown, config, "confidence_margin_weight", config.options.confidence_margin_weight);
+    option_row(&mut markdown, config, "confidence_z_weight", config.options.confidence_z_weight);
+    option_row(&mut markdown, config, "normalize_output_ids", if config.options.lowercase_output_ids { "lowercase".to_string() } else { config.options.normalize_output_ids.to_string() });
+    option_row(&mut markdown, config, "matched_id_sep", &config.options.matched_id_sep);
+    option_row(&mut markdown, config, "merge_editions", config.options.merge_editions);
+    option_row(&mut markdown, config, "report", config.options.report);
+    option_row(&mut markdown, config, "max_rows_per_card", config.options.max_rows_per_card.map(|max_rows| max_rows.to_string()).unwrap_or("none".to_string()));
+    option_row(&mut markdown, config, "output_batch_size", config.options.output_batch_size.map(|size| size.to_string()).unwrap_or("none".to_string()));
+    option_row(&mut markdown, config, "edition_in_card_id", config.options.edition_in_card_id);
+    option_row(&mut markdown, config, "resume_from", config.options.resume_from.as_ref().unwrap_or(&"none".to_string()));
+    option_row(&mut markdown, config, "output_precision", config.options.output_precision.map(|precision| precision.to_string()).unwrap_or("full".to_string()));
+    option_row(&mut markdown, config, "candidates_dump", if let Some(n) = config.options.candidates_dump { n.to_string() } else { "none".to_string() });
+    option_row(&mut markdown, config, "trace_filtering", if let Some(n) = config.options.trace_filtering { n.to_string() } else { "off".to_string() });
+    option_row(&mut markdown, config, "dump_query_vectors", config.options.dump_query_vectors.as_ref().unwrap_or(&"none".to_string()));
+    option_row(&mut markdown, config, "skip_source_check", config.options.skip_source_check);
+    option_row(&mut markdown, config, "lazy_vectors", config.options.lazy_vectors);
+    option_row(&mut markdown, config, "no_vector_cache", config.options.no_vector_cache);
+    option_row(&mut markdown, config, "location_gazetteer", config.options.location_gazetteer.as_ref().unwrap_or(&"none".to_string()));
+    option_row(&mut markdown, config, "strip_leading_articles", config.options.strip_leading_articles.as_ref().unwrap_or(&"none".to_string()));
+    option_row(&mut markdown, config, "acronym_file", config.options.acronym_file.as_ref().unwrap_or(&"none".to_string()));
+    option_row(&mut markdown, config, "rerank_table", config.options.rerank_table.as_ref().unwrap_or(&"none".to_string()));
+    option_row(&mut markdown, config, "gold_file", config.options.gold_file.as_ref().unwrap_or(&"none".to_string()));
+    option_row(&mut markdown, config, "known_ids_file", config.options.known_ids_file.as_ref().unwrap_or(&"none".to_string()));
+    option_row(&mut markdown, config, "prompt_file", config.options.prompt_file.as_ref().unwrap_or(&"none".to_string()));
+    option_row(&mut markdown, config, "tokenizer", config.options.tokenizer);
+    option_row(&mut markdown, config, "year_granularity", config.options.year_granularity);
+    option_row(&mut markdown, config, "sentinels", if config.options.sentinels { "on" } else { "off" });
+    option_row(&mut markdown, config, "numeric_tokens", config.options.numeric_tokens);
+    option_row(&mut markdown, config, "clean_year", config.options.clean_year);
+    option_row(&mut markdown, config, "part_idf", config.options.part_idf);
+    option_row(&mut markdown, config, "author_normalize", config.options.author_normalize);
+    option_row(&mut markdown, config, "combined_order", config.options.combined_order.join(","));
+    option_row(&mut markdown, config, "add_author_to_title", config.options.add_author_to_title);
+    option_row(&mut markdown, config, "skip_empty_title", config.options.skip_empty_title);
+    option_row(&mut markdown, config, "overlap_adjustment", config.options.overlap_adjustment.unwrap_or(-1));
+    option_row(&mut markdown, config, "overlap_min_title_len", config.options.overlap_min_title_len);
+    option_row(&mut markdown, config, "overlap_field", config.options.overlap_field);
+    option_row(&mut markdown, config, "overlap_max_len", config.options.overlap_max_len.map(|max_len| max_len.to_string()).unwrap_or("none".to_string()));
+    option_row(&mut markdown, config, "jaro_winkler_adjustment", config.options.jaro_winkler_adjustment);
+    option_row(&mut markdown, config, "jaro_winkler_author_adjustment", config.options.jaro_winkler_author_adjustment);
+    option_row(&mut markdown, config, "jaro_winkler_truncate", config.options.jaro_winkler_truncate);
+    option_row(&mut markdown, config, "json_schema_version", json_schema_version_name(config.options.json_schema_version));
+    option_row(&mut markdown, config, "dedup_input", config.options.dedup_input);
+    option_row(&mut markdown, config, "dataset_dir", &config.options.dataset_dir);
+    option_row(&mut markdown, config, "exclude_files", if config.options.exclude_files.is_empty() { "none".to_string() } else { config.options.exclude_files.join(", ") });
+    option_row(&mut markdown, config, "exclude_where", if config.options.exclude_where.is_empty() { "none".to_string() } else { config.options.exclude_where.iter().map(|predicate| predicate.to_string()).collect::<Vec<String>>().join(", ") });
+    if !config.options.excluded_external_ids.is_empty() {
+        option_row(&mut markdown, config, "exclude_id_field", &config.options.exclude_id_field);
+    }
+    option_row(&mut markdown, config, "input_exclude_files", if config.options.input_exclude_files.is_empty() { "none".to_string() } else { config.options.input_exclude_files.join(", ") });
+    option_row(&mut markdown, config, "min-multiple_similarity", config.options.min_multiple_similarity.unwrap_or(0.0));
+    option_row(&mut markdown, config, "fail_if_nomatch_above", config.options.fail_if_nomatch_above.map_or("none".to_string(), |x| x.to_string()));
+    option_row(&mut markdown, config, "quiet", config.options.quiet);
+    option_row(&mut markdown, config, "ambiguous_spread", config.options.ambiguous_spread.map_or("none".to_string(), |x| x.to_string()));
+    option_row(&mut markdown, config, "detect_exact", config.options.detect_exact);
     markdown.push_str("\n");
     markdown.push_str("## Statistics\n\n");
     // Output the statistics in a table
     markdown.push_str(&format!("| {} | {} |\n", "Field", "Value"));
     markdown.push_str("| --- | --- |\n");
-    markdown.push_str(&format!("| {} | {} |\n", "Number of cards", stats.number_of_cards()));
-    markdown.push_str(&format!("| {} | {} |\n", "Number of match entities", stats.number_of_records));
+    for (label, value) in statistics_rows(stats) {
+        markdown.push_str(&format!("| {} | {} |\n", label, value));
+    }
+    if let Some(gold) = &stats.gold {
+        gold_markdown(&mut markdown, gold);
+    }
+    cmdline_to_run(&mut markdown, config);
+    if stats.prompt_used.len() > 0 {
+        prompt_markdown(&mut markdown, &stats.prompt_used);
+    }
+
+    markdown
+}
+
+// A value in the statistics table, percentages are written with two decimals
+pub enum StatisticsValue {
+    Count(usize),
+    Percent(f32),
+}
+
+impl std::fmt::Display for StatisticsValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StatisticsValue::Count(count) => write!(f, "{}", count),
+            StatisticsValue::Percent(percent) => write!(f, "{:.2}", percent),
+        }
+    }
+}
+
+// Rows of the statistics table of the markdown report, also used by the xlsx summary sheet.
+// The counts and percentages of the match types are only given for types that occur.
+pub fn statistics_rows(stats: &MatchStatistics) -> Vec<(&'static str, StatisticsValue)> {
+    let mut rows = vec![
+        ("Number of cards", StatisticsValue::Count(stats.number_of_cards())),
+        ("Number of match entities", StatisticsValue::Count(stats.number_of_records)),
+        ("Input warnings (see -warnings.json)", StatisticsValue::Count(stats.warnings.len())),
+    ];
+    if stats.duplicate_cards() > 0 {
+        rows.push(("Input files with a duplicate card name", StatisticsValue::Count(stats.duplicate_cards())));
+    }
+    if stats.resumed_records > 0 {
+        rows.push(("Records kept from the previous output (resume-from)", StatisticsValue::Count(stats.resumed_records)));
+    }
     if stats.match_stat(&MatchStat::SingleMatch) > 0 {
-        markdown.push_str(&format!("| {} | {} |\n", "Number of single matches", stats.match_stat(&MatchStat::SingleMatch)));
+        rows.push(("Number of single matches", StatisticsValue::Count(stats.match_stat(&MatchStat::SingleMatch))));
+    }
+    if stats.match_stat(&MatchStat::ExactMatch) > 0 {
+        rows.push(("Number of exact single matches", StatisticsValue::Count(stats.match_stat(&MatchStat::ExactMatch))));
     }
     if stats.match_stat(&MatchStat::Unqualified) > 0 {
-        markdown.push_str(&format!("| {} | {} |\n", "Number of unqualified single matches", stats.match_stat(&MatchStat::Unqualified)));
+        rows.push(("Number of unqualified single matches", StatisticsValue::Count(stats.match_stat(&MatchStat::Unqualified))));
     }
     if stats.match_stat(&MatchStat::MultipleMatches) > 0 {
-        markdown.push_str(&format!("| {} | {} |\n", "Number of multiple matches", stats.match_stat(&MatchStat::MultipleMatches)));
+        rows.push(("Number of multiple matches", StatisticsValue::Count(stats.match_stat(&MatchStat::MultipleMatches))));
+    }
+    if stats.match_stat(&MatchStat::Ambiguous) > 0 {
+        rows.push(("Number of ambiguous multiple matches", StatisticsValue::Count(stats.match_stat(&MatchStat::Ambiguous))));
     }
     if stats.match_stat(&MatchStat::UnqualifiedMultipleMatches) > 0 {
-        markdown.push_str(&format!("| {} | {} |\n", "Number of unqualified multiple matches", stats.match_stat(&MatchStat::UnqualifiedMultipleMatches)));
+        rows.push(("Number of unqualified multiple matches", StatisticsValue::Count(stats.match_stat(&MatchStat::UnqualifiedMultipleMatches))));
     }
     if stats.match_stat(&MatchStat::NoMatch) > 0 {
-        markdown.push_str(&format!("| {} | {} |\n", "Number of no matches", stats.match_stat(&MatchStat::NoMatch)));
+        rows.push(("Number of no matches", StatisticsValue::Count(stats.match_stat(&MatchStat::NoMatch))));
+    }
+    if stats.match_stat(&MatchStat::SkippedEmpty) > 0 {
+        rows.push(("Number of skipped empty titles", StatisticsValue::Count(stats.match_stat(&MatchStat::SkippedEmpty))));
     }
     if stats.match_stat(&MatchStat::NoEdition) > 0 {
-        markdown.push_str(&format!("| {} | {} |\n", "Cards without editions", stats.match_stat(&MatchStat::NoEdition)));
+        rows.push(("Cards without editions", StatisticsValue::Count(stats.match_stat(&MatchStat::NoEdition))));
+    }
+    if stats.match_stat(&MatchStat::InvalidJSON) > 0 {
+        rows.push(("Cards with invalid JSON", StatisticsValue::Count(stats.match_stat(&MatchStat::InvalidJSON))));
     }
     if stats.match_stat(&MatchStat::SingleMatch) > 0 {
-        markdown.push_str(&format!("| {} | {:.2} |\n", "Single match percentage", stats.match_stat_percent(&MatchStat::SingleMatch)));
+        rows.push(("Single match percentage", StatisticsValue::Percent(stats.match_stat_percent(&MatchStat::SingleMatch))));
+    }
+    if stats.match_stat(&MatchStat::ExactMatch) > 0 {
+        rows.push(("Exact single match percentage", StatisticsValue::Percent(stats.match_stat_percent(&MatchStat::ExactMatch))));
     }
     if stats.match_stat(&MatchStat::Unqualified) > 0 {
-        markdown.push_str(&format!("| {} | {:.2} |\n", "Unqualified single match percentage", stats.match_stat_percent(&MatchStat::Unqualified)));
+        rows.push(("Unqualified single match percentage", StatisticsValue::Percent(stats.match_stat_percent(&MatchStat::Unqualified))));
     }
     if stats.match_stat(&MatchStat::MultipleMatches) > 0 {
-        markdown.push_str(&format!("| {} | {:.2} |\n", "Multiple match percentage", stats.match_stat_percent(&MatchStat::MultipleMatches)));
+        rows.push(("Multiple match percentage", StatisticsValue::Percent(stats.match_stat_percent(&MatchStat::MultipleMatches))));
+    }
+    if stats.match_stat(&MatchStat::Ambiguous) > 0 {
+        rows.push(("Ambiguous multiple match percentage", StatisticsValue::Percent(stats.match_stat_percent(&MatchStat::Ambiguous))));
     }
     if stats.match_stat(&MatchStat::UnqualifiedMultipleMatches) > 0 {
-        markdown.push_str(&format!("| {} | {:.2} |\n", "Unqualified multiple match percentage", stats.match_stat_percent(&MatchStat::UnqualifiedMultipleMatches)));
+        rows.push(("Unqualified multiple match percentage", StatisticsValue::Percent(stats.match_stat_percent(&MatchStat::UnqualifiedMultipleMatches))));
     }
     if stats.match_stat(&MatchStat::NoMatch) > 0 {
-        markdown.push_str(&format!("| {} | {:.2} |\n", "No match percentage", stats.match_stat_percent(&MatchStat::NoMatch)));
+        rows.push(("No match percentage", StatisticsValue::Percent(stats.match_stat_percent(&MatchStat::NoMatch))));
+    }
+    if stats.match_stat(&MatchStat::SkippedEmpty) > 0 {
+        rows.push(("Skipped empty title percentage", StatisticsValue::Percent(stats.match_stat_percent(&MatchStat::SkippedEmpty))));
     }
     if stats.match_stat(&MatchStat::NoEdition) > 0 {
-        markdown.push_str(&format!("| {} | {:.2} |\n", "No edition percentage", stats.match_stat_percent(&MatchStat::NoEdition)));
+        rows.push(("No edition percentage (of cards)", StatisticsValue::Percent(stats.card_stat_percent(&MatchStat::NoEdition))));
     }
-    cmdline_to_run(&mut markdown, config);
-    if stats.prompt_used.len() > 0 {
-        prompt_markdown(&mut markdown, &stats.prompt_used);
+    if stats.match_stat(&MatchStat::InvalidJSON) > 0 {
+        rows.push(("Invalid JSON percentage (of cards)", StatisticsValue::Percent(stats.card_stat_percent(&MatchStat::InvalidJSON))));
     }
+    rows
+}
 
-    markdown
+// Inline weights as "part=weight" pairs sorted by part, or "none"
+fn inline_weights_markdown(config: &Config) -> String {
+    if config.options.inline_weights.is_empty() {
+        return "none".to_string();
+    }
+    let mut pairs = config.options.inline_weights.iter().map(|(part, weight)| format!("{}={}", part, weight)).collect::<Vec<String>>();
+    pairs.sort();
+    pairs.join(", ")
+}
+
+fn gold_markdown(markdown: &mut String, gold: &GoldEvaluation) {
+    markdown.push('\n');
+    markdown.push_str("## Gold standard evaluation\n\n");
+    markdown.push_str(&format!("| {} | {} |\n", "Field", "Value"));
+    markdown.push_str("| --- | --- |\n");
+    markdown.push_str(&format!("| {} | {} |\n", "Cards in gold file and input", gold.cards));
+    markdown.push_str(&format!("| {} | {} |\n", "Expected matches", gold.expected_matches));
+    markdown.push_str(&format!("| {} | {} |\n", "Predicted matches", gold.predicted_matches));
+    markdown.push_str(&format!("| {} | {} |\n", "Correct matches", gold.correct_matches));
+    markdown.push_str(&format!("| {} | {} |\n", "Correct no matches", gold.correct_no_matches));
+    markdown.push_str(&format!("| {} | {:.4} |\n", "Precision", gold.precision));
+    markdown.push_str(&format!("| {} | {:.4} |\n", "Recall", gold.recall));
+    markdown.push_str(&format!("| {} | {:.4} |\n", "Accuracy", gold.accuracy));
+    for (k, hit_rate) in &gold.top_k_hit_rate {
+        markdown.push_str(&format!("| Top-{} hit rate | {:.4} |\n", k, hit_rate));
+    }
 }
 
 fn prompt_markdown(markdown: &mut String, prompt: &str) {
@@ -276,21 +705,60 @@ fn prompt_markdown(markdown: &mut String, prompt: &str) {
     }
 }
 
+// Row in the options table, with where the option was set (see ConfigOptions::option_sources)
+fn option_row(markdown: &mut String, config: &Config, name: &str, value: impl std::fmt::Display) {
+    markdown.push_str(&format!("| {} | {} | {} |\n", name, value, option_source(config, name)));
+}
+
+fn option_source(config: &Config, name: &str) -> &'static str {
+    let sources = &config.options.option_sources;
+    let source = match name {
+        // Given as one -O weight-<part>=N per part, always on the command line
+        "inline_weights" => sources.keys().find(|key| key.starts_with("weight-")).and_then(|key| sources.get(key)),
+        "exclude_files" => sources.get("exclude-file"),
+        "input_exclude_files" => sources.get("input-exclude-file"),
+        _ => sources.get(&name.replace('_', "-")),
+    };
+    match source {
+        Some(OptionSource::CommandLine) => "command line",
+        Some(OptionSource::ConfigFile) => "config file",
+        None => "default",
+    }
+}
+
+// Options from the config file are left out of the command line, since -C gives them
+fn set_in_config_file(config: &Config, option: &str) -> bool {
+    let name = match option.strip_prefix("-O ") {
+        Some(option) => option.split(['=', ' ']).next().unwrap_or(""),
+        None => return false,
+    };
+    config.options.option_sources.get(name) == Some(&OptionSource::ConfigFile)
+}
+
 // Replicate a cargo run command line from the config
 fn cmdline_to_run(markdown: &mut String, config: &Config) {
+    let cmdline = format!("cargo run --release -- {}", command_line_options(config));
+    markdown.push_str("\n");
+    markdown.push_str("## Command line\n\n");
+    markdown.push_str(&format!("```\n{}\n```\n", cmdline));
+}
+
+// The arguments to run the same command again, leaving out defaults and what is set in the config file
+pub fn command_line_options(config: &Config) -> String {
     let command = format!("-c {}", config.cmd);
     let source = format!("-s {}", config.source);
-    let input = format!("-i {}", config.input);
+    let input = config.inputs.iter().map(|input| format!("-i {}", input)).collect::<Vec<String>>().join(" ");
     let output = match &config.output {
         Output::Stdout => "".to_string(),
         Output::File(filename) => format!("-o {}", filename),
     };
     let output_format = format!("-F {}", config.output_format);
     let vocab_file = if config.default_args.contains_key("vocab-file") { "".to_string() } else {format!("-V {}", config.vocab_file) };
-    let vector_file = if config.default_args.contains_key("dataset-vector-file") { "".to_string() } else {format!("-D {}", config.dataset_vector_file) };
-    let source_data_file = if config.default_args.contains_key("source-data-file") { "".to_string() } else {format!("-S {}", config.source_data_file) };
+    let vector_file = if config.default_args.contains_key("dataset-vector-file") { "".to_string() } else { config.dataset_vector_files.iter().map(|file| format!("-D {}", file)).collect::<Vec<String>>().join(" ") };
+    let source_data_file = if config.default_args.contains_key("source-data-file") { "".to_string() } else { config.source_data_files.iter().map(|file| format!("-S {}", file)).collect::<Vec<String>>().join(" ") };
     let force_year = if config.options.force_year { "-O force-year".to_string() } else { "".to_string() };
     let mut year_tolerance = "".to_string();
+    let empty_author_penalty = if config.options.empty_author_penalty > 0.0 { format!("-O empty-author-penalty={}", config.options.empty_author_penalty) } else { "".to_string() };
     let mut year_tolerance_penalty = "".to_string();
     if config.options.force_year {
         if let Some(tol) = config.options.year_tolerance {
@@ -300,17 +768,91 @@ fn cmdline_to_run(markdown: &mut String, config: &Config) {
             }
         }
     }
+    let parse_year_ranges = if config.options.parse_year_ranges { "-O parse-year-ranges".to_string() } else { "".to_string() };
+    let use_first_parsed_year = if config.options.use_first_parsed_year { "-O use-first-parsed-year".to_string() } else { "".to_string() };
     let include_source_data = if config.options.include_source_data { "-O include-source-data".to_string() } else { "".to_string() };
+    let require_type_match = if config.options.require_type_match { "-O require-type-match".to_string() } else { "".to_string() };
+    let text_table = if config.options.text_table { "-O text-table".to_string() } else { "".to_string() };
     let similarity_threshold = config.options.similarity_threshold.map_or("".to_string(), |x| format!("-O similarity-threshold={}", x));
+    let title_threshold = config.options.title_threshold.map_or("".to_string(), |x| format!("-O title-threshold={}", x));
+    let author_threshold = config.options.author_threshold.map_or("".to_string(), |x| format!("-O author-threshold={}", x));
     let z_threshold = config.options.z_threshold.map_or("".to_string(), |x| format!("-O z-threshold={}", x));
+    let adaptive_z = config.options.adaptive_z.map_or("".to_string(), |x| format!("-O adaptive-z={}", x));
+    let min_pool_for_z = config.options.min_pool_for_z.map_or("".to_string(), |x| format!("-O min-pool-for-z={}", x));
+    let zscore_method = if config.options.zscore_method == ZscoreMethod::Mean { "".to_string() } else { format!("-O zscore-method={}", config.options.zscore_method) };
+    let score_transform = if config.options.score_transform == ScoreTransform::None { "".to_string() } else { format!("-O score-transform={}", config.options.score_transform) };
     let min_single_similarity = config.options.min_single_similarity.map_or("".to_string(), |x| format!("-O min-single-similarity={}", x));
     let min_multiple_similarity = config.options.min_multiple_similarity.map_or("".to_string(), |x| format!("-O min-multiple-similarity={}", x));
+    let fail_if_nomatch_above = config.options.fail_if_nomatch_above.map_or("".to_string(), |x| format!("-O fail-if-nomatch-above={}", x));
+    let quiet = if config.options.quiet { "-O quiet".to_string() } else { "".to_string() };
+    let ambiguous_spread = config.options.ambiguous_spread.map_or("".to_string(), |x| format!("-O ambiguous-spread={}", x));
+    let detect_exact = if config.options.detect_exact { "-O detect-exact".to_string() } else { "".to_string() };
     let weights_file = config.options.weights_file.as_ref().map_or("".to_string(), |x| format!("-O weights-file={}", x));
+    let weights_profiles = config.options.weights_profiles.as_ref().map_or("".to_string(), |x| format!("-O weights-profiles={}", x));
+    let mut inline_weight_parts = config.options.inline_weights.keys().cloned().collect::<Vec<String>>();
+    inline_weight_parts.sort();
+    let inline_weights = inline_weight_parts.iter().map(|part| format!("-O weight-{}={}", part, config.options.inline_weights[part])).collect::<Vec<String>>().join(" ");
+    let use_combined_only = if config.options.use_combined_only { "-O use-combined-only".to_string() } else { "".to_string() };
+    let all_fallback = config.options.all_fallback.map_or("".to_string(), |x| format!("-O all-fallback={}", x));
+    let length_normalize = if config.options.length_normalize { "-O length-normalize".to_string() } else { "".to_string() };
     let extended_output = if config.options.extended_output { "-O extended-output".to_string() } else { "".to_string() };
+    let explain = if config.options.explain { "-O explain".to_string() } else { "".to_string() };
+    let output_combined_text = if config.options.output_combined_text { "-O output-combined-text".to_string() } else { "".to_string() };
+    let xlsx_summary_sheet = if config.options.xlsx_summary_sheet { "-O xlsx-summary-sheet".to_string() } else { "".to_string() };
+    let json_envelope = if config.options.json_envelope { "-O json-envelope".to_string() } else { "".to_string() };
+    let output_part_similarities = if config.options.output_part_similarities { "-O output-part-similarities".to_string() } else { "".to_string() };
+    let confidence_score = if config.options.confidence_score { "-O confidence-score".to_string() } else { "".to_string() };
+    let confidence_midpoint = if config.options.confidence_midpoint != 0.5 { format!("-O confidence-midpoint={}", config.options.confidence_midpoint) } else { "".to_string() };
+    let confidence_steepness = if config.options.confidence_steepness != 10.0 { format!("-O confidence-steepness={}", config.options.confidence_steepness) } else { "".to_string() };
+    let confidence_margin_weight = if config.options.confidence_margin_weight != 0.0 { format!("-O confidence-margin-weight={}", config.options.confidence_margin_weight) } else { "".to_string() };
+    let confidence_z_weight = if config.options.confidence_z_weight != 0.0 { format!("-O confidence-z-weight={}", config.options.confidence_z_weight) } else { "".to_string() };
+    let normalize_output_ids = match (config.options.normalize_output_ids, config.options.lowercase_output_ids) {
+        (_, true) => "-O normalize-output-ids=lowercase".to_string(),
+        (true, false) => "-O normalize-output-ids".to_string(),
+        _ => "".to_string(),
+    };
+    let matched_id_sep = if config.options.matched_id_sep == "/" { "".to_string() } else { format!("-O matched-id-sep={}", config.options.matched_id_sep) };
+    let merge_editions = if config.options.merge_editions { "-O merge-editions".to_string() } else { "".to_string() };
+    let report = if config.options.report == ReportKind::Both { "".to_string() } else { format!("-O report={}", config.options.report) };
+    let max_rows_per_card = match config.options.max_rows_per_card {
+        Some(max_rows) => format!("-O max-rows-per-card={}", max_rows),
+        None => "".to_string(),
+    };
+    let output_batch_size = config.options.output_batch_size.map_or("".to_string(), |x| format!("-O output-batch-size={}", x));
+    let edition_in_card_id = if config.options.edition_in_card_id { "-O edition-in-card-id".to_string() } else { "".to_string() };
+    let resume_from = config.options.resume_from.as_ref().map_or("".to_string(), |x| format!("-O resume-from={}", x));
+    let output_precision = config.options.output_precision.map_or("".to_string(), |x| format!("-O output-precision={}", x));
+    let candidates_dump = config.options.candidates_dump.map_or("".to_string(), |x| format!("-O candidates-dump={}", x));
+    let trace_filtering = config.options.trace_filtering.map_or("".to_string(), |x| format!("-O trace-filtering={}", x));
+    let dump_query_vectors = config.options.dump_query_vectors.as_ref().map_or("".to_string(), |x| format!("-O dump-query-vectors={}", x));
+    let skip_source_check = if config.options.skip_source_check { "-O skip-source-check".to_string() } else { "".to_string() };
+    let lazy_vectors = if config.options.lazy_vectors { "-O lazy-vectors".to_string() } else { "".to_string() };
+    let no_vector_cache = if config.options.no_vector_cache { "-O no-vector-cache".to_string() } else { "".to_string() };
+    let tokenizer = if config.options.tokenizer == TokenizerMode::Ngram { "".to_string() } else { format!("-O tokenizer={}", config.options.tokenizer) };
+    let year_granularity = if config.options.year_granularity == YearGranularity::Year { "".to_string() } else { format!("-O year-granularity={}", config.options.year_granularity) };
+    let sentinels = if config.options.sentinels { "".to_string() } else { "-O sentinels=off".to_string() };
+    let author_normalize = if config.options.author_normalize == AuthorNormalize::None { "".to_string() } else { format!("-O author-normalize={}", config.options.author_normalize) };
+    let numeric_tokens = if config.options.numeric_tokens { "-O numeric-tokens".to_string() } else { "".to_string() };
+    let clean_year = if config.options.clean_year { "-O clean-year".to_string() } else { "".to_string() };
+    let part_idf = if config.options.part_idf { "-O part-idf".to_string() } else { "".to_string() };
+    let combined_order = if config.options.combined_order == elastic::DEFAULT_COMBINED_ORDER { "".to_string() } else { format!("-O combined-order={}", config.options.combined_order.join(",")) };
+    let location_gazetteer = config.options.location_gazetteer.as_ref().map_or("".to_string(), |x| format!("-O location-gazetteer={}", x));
+    let strip_leading_articles = config.options.strip_leading_articles.as_ref().map_or("".to_string(), |x| format!("-O strip-leading-articles={}", x));
+    let acronym_file = config.options.acronym_file.as_ref().map_or("".to_string(), |x| format!("-O acronym-file={}", x));
+    let rerank_table = config.options.rerank_table.as_ref().map_or("".to_string(), |x| format!("-O rerank-table={}", x));
+    let gold_file = config.options.gold_file.as_ref().map_or("".to_string(), |x| format!("-O gold-file={}", x));
+    let known_ids_file = config.options.known_ids_file.as_ref().map_or("".to_string(), |x| format!("-O known-ids-file={}", x));
+    let prompt_file = config.options.prompt_file.as_ref().map_or("".to_string(), |x| format!("-O prompt-file={}", x));
     let add_author_to_title = if config.options.add_author_to_title { "-O add-author-to-title".to_string() } else { "".to_string() };
+    let skip_empty_title = if config.options.skip_empty_title { "-O skip-empty-title".to_string() } else { "".to_string() };
     let exclude_files = if config.options.exclude_files.is_empty() { "".to_string() } else { config.options.exclude_files.iter().map(|f| format!("-O exclude-file={}", f)).collect::<Vec<String>>().join(" ") };
+    let exclude_where = if config.options.exclude_where.is_empty() { "".to_string() } else { config.options.exclude_where.iter().map(|predicate| format!("-O exclude-where='{}'", predicate)).collect::<Vec<String>>().join(" ") };
+    let exclude_id_field = if config.options.exclude_id_field == "matched_id" { "".to_string() } else { format!("-O exclude-id-field={}", config.options.exclude_id_field) };
     let input_exclude_files = if config.options.input_exclude_files.is_empty() { "".to_string() } else { config.options.input_exclude_files.iter().map(|f| format!("-O input-exclude-file={}", f)).collect::<Vec<String>>().join(" ") };
     let overlap_adjustment = config.options.overlap_adjustment.map_or("".to_string(), |x| format!("-O overlap-adjustment={}", x));
+    let overlap_field = if config.options.overlap_field == OverlapField::Title { "".to_string() } else { format!("-O overlap-field={}", config.options.overlap_field) };
+    let overlap_max_len = config.options.overlap_max_len.map_or("".to_string(), |x| format!("-O overlap-max-len={}", x));
+    let overlap_min_title_len = if config.options.overlap_min_title_len == 0 { "".to_string() } else { format!("-O overlap-min-title-len={}", config.options.overlap_min_title_len) };
     let jaro_winkler_adjustment = if config.options.jaro_winkler_adjustment { "-O jaro-winkler-adjustment".to_string() } else { "".to_string() };
     let jaro_winkler_author_adjustment = if config.options.jaro_winkler_author_adjustment { "-O jaro-winkler-author-adjustment".to_string() } else { "".to_string() };
     let jaro_winkler_truncate = if let JaroTruncate::None = config.options.jaro_winkler_truncate {
@@ -318,16 +860,16 @@ fn cmdline_to_run(markdown: &mut String, config: &Config) {
     } else {
         format!("-O jaro-winkler-truncate={}", config.options.jaro_winkler_truncate)
     };
-    let json_schema_version = if config.options.json_schema_version != 1 { format!("-O json-schema-version={}", config.options.json_schema_version) } else { "".to_string() };
+    let dedup_input = if config.options.dedup_input == DedupInput::Warn { "".to_string() } else { format!("-O dedup-input={}", config.options.dedup_input) };
+    let json_schema_version = if config.options.json_schema_version != 1 { format!("-O json-schema-version={}", json_schema_version_name(config.options.json_schema_version)) } else { "".to_string() };
     let dataset_dir = if config.options.dataset_dir != "data" { format!("-O dataset-dir={}", config.options.dataset_dir) } else { "".to_string() };
     let verbose = if config.verbose { "-v".to_string() } else { "".to_string() };
+    let config_file = config.config_file.as_ref().map_or("".to_string(), |x| format!("-C {}", x));
     // Combine them in order above
-    let combined_options = vec![command, source, input, output, output_format, vocab_file, vector_file, source_data_file, force_year, year_tolerance, year_tolerance_penalty, include_source_data, similarity_threshold, z_threshold, min_single_similarity, min_multiple_similarity, weights_file, extended_output, add_author_to_title, overlap_adjustment, jaro_winkler_adjustment, jaro_winkler_author_adjustment, jaro_winkler_truncate, json_schema_version, dataset_dir, exclude_files, input_exclude_files, verbose];
-    let options = combined_options.iter().filter(|x| x.len() > 0).map(|x| x.to_string()).collect::<Vec<String>>().join(" ");
-    let cmdline = format!("cargo run --release -- {}", options);
-    markdown.push_str("\n");
-    markdown.push_str("## Command line\n\n");
-    markdown.push_str(&format!("```\n{}\n```\n", cmdline));
+    let combined_options = vec![command, source, input, output, output_format, vocab_file, vector_file, source_data_file, config_file, force_year, year_tolerance, year_tolerance_penalty, empty_author_penalty, parse_year_ranges, use_first_parsed_year, include_source_data, text_table, require_type_match, similarity_threshold, title_threshold, author_threshold, z_threshold, adaptive_z, min_pool_for_z, zscore_method, score_transform, min_single_similarity, min_multiple_similarity, ambiguous_spread, detect_exact, fail_if_nomatch_above, quiet, weights_file, weights_profiles, inline_weights, use_combined_only, all_fallback, length_normalize, extended_output, explain, output_combined_text, xlsx_summary_sheet, json_envelope, output_part_similarities, confidence_score, confidence_midpoint, confidence_steepness, confidence_margin_weight, confidence_z_weight, normalize_output_ids, matched_id_sep, merge_editions, max_rows_per_card, output_batch_size, edition_in_card_id, resume_from, output_precision, report, candidates_dump, trace_filtering, dump_query_vectors, skip_source_check, lazy_vectors, no_vector_cache, location_gazetteer, strip_leading_articles, acronym_file, tokenizer, year_granularity, sentinels, numeric_tokens, clean_year, part_idf, author_normalize, combined_order, add_author_to_title, skip_empty_title, overlap_adjustment, overlap_min_title_len, overlap_field, overlap_max_len, jaro_winkler_adjustment, jaro_winkler_author_adjustment, jaro_winkler_truncate, rerank_table, json_schema_version, dedup_input, dataset_dir, exclude_files, exclude_where, exclude_id_field, input_exclude_files, gold_file, known_ids_file, prompt_file, verbose];
+    combined_options.iter()
+        .filter(|x| x.len() > 0 && !set_in_config_file(config, x))
+        .map(|x| x.to_string()).collect::<Vec<String>>().join(" ")
 }
 
 // #[derive(Debug)]
@@ -338,4 +880,52 @@ fn cmdline_to_run(markdown: &mut String, config: &Config) {
 //     pub z_threshold: Option<f32>,
 //     pub min_single_similarity: Option<f32>,
 //     pub weights_file: Option<String>,
-// }
\ No newline at end of file
+// }
+
+#[cfg(test)]
+mod tests {
+    use super::{gold_evaluation, statistics_rows};
+    use crate::args::Config;
+    use crate::matcher::{MatchStat, MatchStatistics};
+    use rustc_hash::FxHashMap;
+
+    #[test]
+    fn statistics_rows_of_occurring_match_types() {
+        let mut statistics = MatchStatistics::default();
+        statistics.update(&MatchStat::SingleMatch, "001_1.json");
+        statistics.update(&MatchStat::SingleMatch, "001_2.json");
+        statistics.update(&MatchStat::NoMatch, "001_3.json");
+        let rows = statistics_rows(&statistics).into_iter()
+            .map(|(label, value)| format!("{}: {}", label, value))
+            .collect::<Vec<String>>();
+        assert_eq!(rows, vec![
+            "Number of cards: 3",
+            "Number of match entities: 3",
+            "Input warnings (see -warnings.json): 0",
+            "Number of single matches: 2",
+            "Number of no matches: 1",
+            "Single match percentage: 66.67",
+            "No match percentage: 33.33",
+        ]);
+    }
+
+    #[test]
+    fn gold_evaluation_counts_per_card() {
+        let gold_ids = [("001_1", "a"), ("001_2", "b"), ("001_3", ""), ("001_4", "d"), ("002_9", "x")]
+            .iter().map(|(card, id)| (card.to_string(), id.to_string())).collect::<FxHashMap<String, String>>();
+        let ranked_ids = [("001_1", vec!["libris/a", "c"]), ("001_2", vec!["c", "b"]), ("001_3", vec![]), ("001_4", vec![]), ("003_1", vec!["e"])]
+            .iter().map(|(card, ids)| (card.to_string(), ids.iter().map(|id| id.to_string()).collect())).collect::<FxHashMap<String, Vec<String>>>();
+        let config = Config::from_args(&["-c", "match-single-json", "-s", "test"]);
+        let evaluation = gold_evaluation(&config, &gold_ids, &ranked_ids);
+        // 002_9 is not in the input and 003_1 is not in the gold file
+        assert_eq!(evaluation.cards, 4);
+        assert_eq!(evaluation.expected_matches, 3);
+        assert_eq!(evaluation.predicted_matches, 2);
+        assert_eq!(evaluation.correct_matches, 1);
+        assert_eq!(evaluation.correct_no_matches, 1);
+        assert_eq!(evaluation.precision, 0.5);
+        assert_eq!(evaluation.accuracy, 0.5);
+        assert_eq!(evaluation.top_k_hit_rate[&1], 1.0 / 3.0);
+        assert_eq!(evaluation.top_k_hit_rate[&5], 2.0 / 3.0);
+    }
+}
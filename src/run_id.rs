@@ -0,0 +1,89 @@
+use crate::args::Config;
+use crate::hash::StableHasher;
+use std::io::Read;
+use std::time::UNIX_EPOCH;
+
+// Identifier tying the output file to its report: the latest modification time of the inputs
+// and a short hash of the resolved config and the input, e.g. "20240131T120000Z-1a2b3c4d".
+// Matching the same input with the same config always gives the same run id.
+pub struct RunId {
+    hasher: StableHasher,
+}
+
+impl RunId {
+    pub fn new(config: &Config) -> RunId {
+        let mut hasher = StableHasher::new();
+        hasher.update(format!("{:?}", config).as_bytes());
+        RunId { hasher }
+    }
+
+    // Hash the content of an input file (zip or JSON Lines) instead of its records,
+    // since the records are read while matching
+    pub fn add_file(&mut self, path: &str) {
+        if let Ok(mut file) = std::fs::File::open(path) {
+            let mut buffer = vec![0; 1 << 16];
+            while let Ok(count) = file.read(&mut buffer) {
+                if count == 0 {
+                    break;
+                }
+                self.hasher.update(&buffer[..count]);
+            }
+        }
+    }
+
+    // Hash the names and content of the files of a directory input, in name order
+    pub fn add_directory(&mut self, path: &str) {
+        let mut paths = std::fs::read_dir(path)
+            .map(|entries| entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).filter(|path| path.is_file()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        paths.sort();
+        for path in paths {
+            self.hasher.update(path.file_name().unwrap_or_default().as_encoded_bytes());
+            self.add_file(&path.to_string_lossy());
+        }
+    }
+
+    pub fn finish(self, inputs: &[String]) -> String {
+        format!("{}-{:08x}", input_timestamp(inputs), self.hasher.finish() as u32)
+    }
+}
+
+// Latest modification time of the input files or directories in UTC, zero if none can be read (stdin)
+fn input_timestamp(inputs: &[String]) -> String {
+    let seconds = inputs.iter()
+        .filter_map(|input| std::fs::metadata(input).and_then(|metadata| metadata.modified()).ok())
+        .filter_map(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .max()
+        .unwrap_or(0);
+    format_utc(seconds)
+}
+
+// Seconds since the Unix epoch as YYYYMMDDTHHMMSSZ
+fn format_utc(seconds: u64) -> String {
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+    // Days to civil date, from Howard Hinnant's date algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", year, month, day, time / 3600, (time % 3600) / 60, time % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_utc;
+
+    #[test]
+    fn utc_timestamp_format() {
+        assert_eq!(format_utc(0), "19700101T000000Z");
+        assert_eq!(format_utc(951782400), "20000229T000000Z");
+        assert_eq!(format_utc(1706702400 + 3723), "20240131T130203Z");
+    }
+}
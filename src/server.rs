@@ -0,0 +1,116 @@
+use crate::args::Config;
+use crate::matcher::MatchContext;
+use crate::output::json;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+
+// Default address for the serve command
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:7878";
+
+// Load vocab, vectors and source data once and then match JSON records sent over a socket.
+// The protocol is line based: each request is one JSON record (schema version 1 or 2) on a single line,
+// and each reply is one line with the same JSON as -F json, or {"error": "..."} if the record could not be matched.
+// Connections are handled one at a time.
+pub fn serve(config: &Config) {
+    let context = MatchContext::load(config);
+    let listen = config.options.listen.clone().unwrap_or(DEFAULT_LISTEN.to_string());
+    if let Some(path) = listen.strip_prefix("unix:") {
+        serve_unix(config, &context, &listen, path);
+    } else {
+        let listener = match TcpListener::bind(&listen) {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("Failed to listen on {}: {}", listen, e);
+                std::process::exit(1);
+            }
+        };
+        eprintln!("Listening on {}", listen);
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => handle_connection(config, &context, &stream, &stream),
+                Err(e) => eprintln!("Failed to accept connection: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn serve_unix(config: &Config, context: &MatchContext, listen: &str, path: &str) {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    // Remove a stale socket file from an earlier run, but never anything else.
+    // A socket is only stale if no server accepts connections on it any more.
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            eprintln!("Failed to listen on {}: address in use by a file that is not a socket", listen);
+            std::process::exit(1);
+        }
+        match UnixStream::connect(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {},
+            _ => {
+                eprintln!("Failed to listen on {}: address in use", listen);
+                std::process::exit(1);
+            }
+        }
+        if let Err(e) = std::fs::remove_file(path) {
+            eprintln!("Failed to remove stale socket {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    let listener = match UnixListener::bind(path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to listen on {}: {}", listen, e);
+            std::process::exit(1);
+        }
+    };
+    eprintln!("Listening on {}", listen);
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => handle_connection(config, context, &stream, &stream),
+            Err(e) => eprintln!("Failed to accept connection: {}", e),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn serve_unix(_config: &Config, _context: &MatchContext, listen: &str, _path: &str) {
+    eprintln!("Failed to listen on {}: unix sockets are not supported on this platform", listen);
+    std::process::exit(1);
+}
+
+fn handle_connection<R: std::io::Read, W: Write>(config: &Config, context: &MatchContext, reader: R, mut writer: W) {
+    let reader = BufReader::new(reader);
+    let mut request_number = 0;
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("Failed to read request: {}", e);
+                return;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        request_number += 1;
+        let reply = match_request(config, context, &format!("request-{}.json", request_number), &line);
+        if let Err(e) = writer.write_all(reply.as_bytes()).and_then(|_| writer.write_all(b"\n")).and_then(|_| writer.flush()) {
+            eprintln!("Failed to write reply: {}", e);
+            return;
+        }
+    }
+}
+
+fn match_request(config: &Config, context: &MatchContext, card: &str, content: &str) -> String {
+    if let Err(e) = serde_json::from_str::<serde_json::Value>(content) {
+        return error_reply(&format!("Invalid JSON: {}", e));
+    }
+    let output_records = context.match_json(config, card, content);
+    json::records_to_json_line(config, &output_records)
+}
+
+fn error_reply(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
@@ -1,9 +1,12 @@
 use crate::args::Config;
+use crate::build::BuildProgress;
+use crate::cmd::{self, Cmd};
 use crate::output::Output;
 use crate::elastic::{self, Pagination};
 // use std::collections::HashMap;
 use rustc_hash::FxHashMap;
 use serde::{Serialize, Deserialize};
+use std::fmt::{self, Display, Formatter};
 
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -19,19 +22,247 @@ pub struct SourceRecord {
     pub author: String,
     pub location: String,
     pub year: String,
+    // Last year of a record covering a span of years (e.g. serials), empty if the record has a single year
+    pub last_year: String,
+    // Publication type from -O publication-type-field, empty if not configured (see -O require-type-match)
+    pub publication_type: String,
+}
+
+// Source data files built before publication_type was added to SourceRecord
+#[derive(Debug, Deserialize)]
+struct UntypedSourceData {
+    source: String,
+    records: FxHashMap<String, UntypedSourceRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UntypedSourceRecord {
+    id: String,
+    title: String,
+    author: String,
+    location: String,
+    year: String,
+    last_year: String,
+}
+
+impl From<UntypedSourceData> for SourceData {
+    fn from(untyped: UntypedSourceData) -> Self {
+        let records = untyped.records.into_iter().map(|(key, record)| {
+            (key, SourceRecord {
+                id: record.id,
+                title: record.title,
+                author: record.author,
+                location: record.location,
+                year: record.year,
+                last_year: record.last_year,
+                publication_type: String::new(),
+            })
+        }).collect();
+        SourceData {
+            source: untyped.source,
+            records,
+        }
+    }
+}
+
+// Source data files built before last_year was added to SourceRecord
+#[derive(Debug, Deserialize)]
+struct LegacySourceData {
+    source: String,
+    records: FxHashMap<String, LegacySourceRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LegacySourceRecord {
+    id: String,
+    title: String,
+    author: String,
+    location: String,
+    year: String,
+}
+
+impl From<LegacySourceData> for SourceData {
+    fn from(legacy: LegacySourceData) -> Self {
+        let records = legacy.records.into_iter().map(|(key, record)| {
+            (key, SourceRecord {
+                id: record.id,
+                title: record.title,
+                author: record.author,
+                location: record.location,
+                year: record.year,
+                last_year: String::new(),
+                publication_type: String::new(),
+            })
+        }).collect();
+        SourceData {
+            source: legacy.source,
+            records,
+        }
+    }
+}
+
+// Fields that SourceRecord::field_value can read
+pub const SOURCE_RECORD_FIELDS: [&str; 6] = ["id", "matched_id", "title", "author", "location", "year"];
+
+impl SourceRecord {
+    // The value of a field by name, where "matched_id" is the last part of the id after the last separator
+    // (same as the matched_ID column in extended output)
+    pub fn field_value(&self, field: &str, matched_id_sep: &str) -> &str {
+        match field {
+            "id" => &self.id,
+            "matched_id" => crate::output::matched_id(&self.id, matched_id_sep),
+            "title" => &self.title,
+            "author" => &self.author,
+            "location" => &self.location,
+            "year" => &self.year,
+            _ => "",
+        }
+    }
+
+    // The span of years covered by the record, (year, year) unless last_year is set and not before year
+    pub fn year_span(&self) -> Option<(i32, i32)> {
+        let first_year = self.year.parse::<i32>().ok()?;
+        match self.last_year.parse::<i32>() {
+            Ok(last_year) if last_year >= first_year => Some((first_year, last_year)),
+            _ => Some((first_year, first_year)),
+        }
+    }
+
+    // Number of years between year and the span of the record, 0 if year is within the span
+    pub fn year_distance(&self, year: i32) -> Option<i32> {
+        let (first_year, last_year) = self.year_span()?;
+        if year < first_year {
+            Some(first_year - year)
+        } else if year > last_year {
+            Some(year - last_year)
+        } else {
+            Some(0)
+        }
+    }
+}
+
+// Predicate on a field of the source records (-O exclude-where), "<field><op><value>" where op is one of
+// = and != (text, ignoring case), ~ (contains the text, ignoring case) or <, <=, > and >= (whole numbers,
+// records where the field is not a number never match)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldPredicate {
+    pub field: String,
+    pub op: PredicateOp,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PredicateOp {
+    Equal,
+    NotEqual,
+    Contains,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+// Two character operators first, so that "<=" is not read as "<" followed by "=..."
+const PREDICATE_OPS: [(&str, PredicateOp); 7] = [
+    ("!=", PredicateOp::NotEqual),
+    ("<=", PredicateOp::LessOrEqual),
+    (">=", PredicateOp::GreaterOrEqual),
+    ("=", PredicateOp::Equal),
+    ("~", PredicateOp::Contains),
+    ("<", PredicateOp::Less),
+    (">", PredicateOp::Greater),
+];
+
+impl Display for FieldPredicate {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let op = PREDICATE_OPS.iter().find(|(_, op)| *op == self.op).map(|(text, _)| *text).unwrap_or("=");
+        write!(f, "{}{}{}", self.field, op, self.value)
+    }
+}
+
+impl FieldPredicate {
+    pub fn parse(text: &str) -> Result<FieldPredicate, String> {
+        // The operator is the first operator character, the field name cannot contain any of them
+        let Some(position) = text.find(['!', '<', '>', '=', '~']) else {
+            return Err(format!("no operator in {} (available: =, !=, ~, <, <=, >, >=)", text));
+        };
+        let (field, rest) = text.split_at(position);
+        let Some((op_text, op)) = PREDICATE_OPS.iter().find(|(op_text, _)| rest.starts_with(op_text)) else {
+            return Err(format!("unknown operator in {} (available: =, !=, ~, <, <=, >, >=)", text));
+        };
+        let field = field.trim().to_string();
+        let value = rest[op_text.len()..].trim().to_string();
+        if !SOURCE_RECORD_FIELDS.contains(&field.as_str()) {
+            return Err(format!("unknown field {} in {} (available: {})", field, text, SOURCE_RECORD_FIELDS.join(", ")));
+        }
+        let numeric = matches!(op, PredicateOp::Less | PredicateOp::LessOrEqual | PredicateOp::Greater | PredicateOp::GreaterOrEqual);
+        if numeric && value.parse::<i64>().is_err() {
+            return Err(format!("{} compares numbers, but {} is not a number", op_text, value));
+        }
+        Ok(FieldPredicate { field, op: *op, value })
+    }
+
+    pub fn matches(&self, record: &SourceRecord, matched_id_sep: &str) -> bool {
+        let field_value = record.field_value(&self.field, matched_id_sep).trim();
+        match self.op {
+            PredicateOp::Equal => field_value.to_lowercase() == self.value.to_lowercase(),
+            PredicateOp::NotEqual => field_value.to_lowercase() != self.value.to_lowercase(),
+            PredicateOp::Contains => field_value.to_lowercase().contains(&self.value.to_lowercase()),
+            _ => {
+                let (Ok(field_number), Ok(value)) = (field_value.parse::<i64>(), self.value.parse::<i64>()) else {
+                    return false;
+                };
+                match self.op {
+                    PredicateOp::Less => field_number < value,
+                    PredicateOp::LessOrEqual => field_number <= value,
+                    PredicateOp::Greater => field_number > value,
+                    _ => field_number >= value,
+                }
+            }
+        }
+    }
 }
 
 impl SourceData {
     pub fn save(&self, path: &str) {
-        let file = std::fs::File::create(path).unwrap();
-        bincode::serialize_into(file, self).unwrap();
+        let mut writer = cmd::create_data_file(path);
+        bincode::serialize_into(&mut writer, self).unwrap();
+        writer.finish().unwrap();
     }
 
     pub fn load(path: &str) -> Self {
-        println!("Loading source data from {}", path);
-        let file = std::fs::File::open(path).unwrap();
-        bincode::deserialize_from(file).unwrap()
-    }}
+        eprintln!("Loading source data from {}", path);
+        let file = cmd::open_data_file(path, "source data", Cmd::BuildSourceData);
+        match bincode::deserialize_from(file) {
+            Ok(source_data) => source_data,
+            Err(_) => {
+                // Fall back to the layout without publication_type, then to the layout without last_year
+                let file = cmd::open_data_file(path, "source data", Cmd::BuildSourceData);
+                if let Ok(untyped) = bincode::deserialize_from::<_, UntypedSourceData>(file) {
+                    return untyped.into();
+                }
+                let file = cmd::open_data_file(path, "source data", Cmd::BuildSourceData);
+                let legacy: LegacySourceData = bincode::deserialize_from(file)
+                    .unwrap_or_else(|_| cmd::invalid_data_file(path, "source data", Cmd::BuildSourceData));
+                legacy.into()
+            }
+        }
+    }
+}
+
+impl From<elastic::Record> for SourceRecord {
+    fn from(record: elastic::Record) -> Self {
+        SourceRecord {
+            id: record.id,
+            title: record.title,
+            author: record.author,
+            location: record.location,
+            year: record.year,
+            last_year: record.last_year,
+            publication_type: record.publication_type,
+        }
+    }
+}
 
 pub fn build_source_data(config: &Config) {
     let source_data = process_source(config, &config.source);
@@ -40,6 +271,7 @@ pub fn build_source_data(config: &Config) {
 
 fn process_source(config: &Config, source: &str) -> SourceData {
     let mut counter = 0;
+    let progress = BuildProgress::start();
     let mut source_records = FxHashMap::default();
     let mut records = elastic::fetch_source(config, source, Pagination::Initial, 0);
     loop {
@@ -49,25 +281,15 @@ fn process_source(config: &Config, source: &str) -> SourceData {
         if let Ok((new_records, new_pagination, total_count)) = records {
             counter += new_records.len() as u32;
             if counter % 10000 == 0 {
-                println!("Processing {} records from {}", counter, config.options.output_source_name);
-                // if counter >= 100000 {
-                //     return counter;
-                // }
+                println!("Processing {} records from {} ({})", counter, config.options.output_source_name, progress.summary(counter));
             }
             for record in new_records {
-                let source_record = SourceRecord {
-                    id: record.id.clone(),
-                    title: record.title,
-                    author: record.author,
-                    location: record.location,
-                    year: record.year,
-                };
-                source_records.insert(record.id, source_record);
+                source_records.insert(record.id.clone(), record.into());
             }
             records = elastic::fetch_source(config, source, new_pagination, total_count);
         }
     }
-    println!("Processed {} records in {}", counter, config.options.output_source_name);
+    println!("Processed {} records in {} ({})", counter, config.options.output_source_name, progress.summary(counter));
     SourceData {
         source: config.options.output_source_name.clone(),
         records: source_records,
@@ -87,4 +309,35 @@ pub fn dump_source_data(config: &Config) {
     } else {
         println!("No file specified for output. Skipping dump.");
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FieldPredicate, SourceRecord};
+
+    #[test]
+    fn exclude_where_predicates() {
+        let record = SourceRecord {
+            id: "libris/123".to_string(),
+            title: "Röda rummet".to_string(),
+            author: "Strindberg, August".to_string(),
+            location: "Okänd ort".to_string(),
+            year: "1879".to_string(),
+            last_year: "".to_string(),
+            publication_type: "".to_string(),
+        };
+        let matches = |text: &str| FieldPredicate::parse(text).unwrap().matches(&record, "/");
+        assert!(matches("year<1880"));
+        assert!(!matches("year<1879"));
+        assert!(matches("year<=1879"));
+        assert!(matches("year>=1800"));
+        assert!(matches("location=okänd ort"));
+        assert!(!matches("location!=Okänd ort"));
+        assert!(matches("author~strindberg"));
+        assert!(matches("matched_id=123"));
+        assert_eq!(FieldPredicate::parse("year >= 1800").unwrap().to_string(), "year>=1800");
+        assert!(FieldPredicate::parse("publisher=Bonnier").is_err());
+        assert!(FieldPredicate::parse("year<abc").is_err());
+        assert!(FieldPredicate::parse("year 1800").is_err());
+    }
+}
@@ -1,19 +1,277 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use rustc_hash::{FxHashMap, FxHashSet};
+use crate::args::{AuthorNormalize, ConfigOptions, TokenizerMode, YearGranularity};
+use crate::hash;
+use crate::elastic;
 
-const STARTSYMBOL: char = '\u{0001}'; // vocab_id 1
-const ENDSYMBOL: char = '\u{0002}';   // vocab_id 2
+pub const STARTSYMBOL: char = '\u{0001}'; // vocab_id 1
+pub const ENDSYMBOL: char = '\u{0002}';   // vocab_id 2
 pub const UNKNOWN: char = '\u{0003}'; // vocab_id 0
 
-// This will tokenize into 2 and 3-grams
-pub fn tokenize_string(string: &str) -> HashMap<String, usize> {
+// Tokenize one part of a record ("author", "title", "location", "year" or "all").
+// Any option used here changes the tokens, so it must be the same when building the vocab,
+// when building the dataset vectors and when matching. See tokenizer_metadata.
+pub fn tokenize_part(options: &ConfigOptions, part: &str, text: &str) -> HashMap<String, usize> {
+    let mut tokens = tokenize_part_text(options, part, text);
+    if options.numeric_tokens && part != "year" {
+        add_numeric_tokens(text, &mut tokens);
+    }
+    tokens
+}
+
+fn tokenize_part_text(options: &ConfigOptions, part: &str, text: &str) -> HashMap<String, usize> {
+    match (part, options.tokenizer) {
+        ("year", _) => tokenize_year(text, options.year_granularity),
+        ("author", _) if options.author_normalize != AuthorNormalize::None => {
+            let authors = normalize_authors(text, options.author_normalize);
+            match options.tokenizer {
+                TokenizerMode::Ngram => tokenize_string(&authors, options.sentinels),
+                TokenizerMode::WordNgram => tokenize_string_within_words(&authors, options.sentinels),
+            }
+        },
+        ("location", TokenizerMode::Ngram) => tokenize_string(&apply_gazetteer(text, &options.location_gazetteer_map), options.sentinels),
+        ("location", TokenizerMode::WordNgram) => tokenize_words(&apply_gazetteer(text, &options.location_gazetteer_map)),
+        ("title", TokenizerMode::Ngram) => tokenize_string(&strip_leading_article(&expand_acronyms(text, &options.acronyms), &options.leading_articles), options.sentinels),
+        ("title", TokenizerMode::WordNgram) => tokenize_string_within_words(&strip_leading_article(&expand_acronyms(text, &options.acronyms), &options.leading_articles), options.sentinels),
+        (_, TokenizerMode::Ngram) => tokenize_string(text, options.sentinels),
+        (_, TokenizerMode::WordNgram) => tokenize_string_within_words(text, options.sentinels),
+    }
+}
+
+// The tokenizer options that differ from the defaults, stored in the vocab when it is built
+// and compared when it is used. Default options give an empty map, same as a vocab built before
+// the metadata was stored.
+pub fn tokenizer_metadata(options: &ConfigOptions) -> BTreeMap<String, String> {
+    let mut metadata = BTreeMap::new();
+    if options.tokenizer != TokenizerMode::Ngram {
+        metadata.insert("tokenizer".to_string(), options.tokenizer.to_string());
+    }
+    if options.year_granularity != YearGranularity::Year {
+        metadata.insert("year_granularity".to_string(), options.year_granularity.to_string());
+    }
+    if !options.sentinels {
+        metadata.insert("sentinels".to_string(), "off".to_string());
+    }
+    if options.numeric_tokens {
+        metadata.insert("numeric_tokens".to_string(), "on".to_string());
+    }
+    if options.clean_year {
+        metadata.insert("clean_year".to_string(), "on".to_string());
+    }
+    if options.part_idf {
+        metadata.insert("part_idf".to_string(), "on".to_string());
+    }
+    if options.author_normalize != AuthorNormalize::None {
+        metadata.insert("author_normalize".to_string(), options.author_normalize.to_string());
+    }
+    if options.combined_order != elastic::DEFAULT_COMBINED_ORDER {
+        metadata.insert("combined_order".to_string(), options.combined_order.join(","));
+    }
+    if !options.location_gazetteer_map.is_empty() {
+        metadata.insert("location_gazetteer_hash".to_string(), lookup_hash(&options.location_gazetteer_map));
+    }
+    if !options.acronyms.is_empty() {
+        metadata.insert("acronym_hash".to_string(), lookup_hash(&options.acronyms));
+    }
+    if !options.leading_articles.is_empty() {
+        let mut articles = options.leading_articles.iter().cloned().collect::<Vec<String>>();
+        articles.sort();
+        metadata.insert("leading_articles".to_string(), articles.join(","));
+    }
+    metadata
+}
+
+// Put the names of each author in the same order. Authors are separated by "&" or ";".
+// An author with a comma is "Surname, Given names" (anything after a second comma, such as
+// life years, is kept last), an author without a comma is "Given names Surname".
+// "Petersen, Ib Hoy & Naae, Viggo" gives "Ib Hoy Petersen; Viggo Naae" with given-first.
+pub fn normalize_authors(text: &str, mode: AuthorNormalize) -> String {
+    if mode == AuthorNormalize::None {
+        return text.to_string();
+    }
+    text.split(['&', ';'])
+        .map(str::trim)
+        .filter(|author| !author.is_empty())
+        .map(|author| normalize_author(author, mode))
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+fn normalize_author(author: &str, mode: AuthorNormalize) -> String {
+    let mut parts = author.split(',').map(str::trim);
+    let first = parts.next().unwrap_or_default();
+    let (surname, given) = match parts.next() {
+        Some(given) => (first, given),
+        None => match first.rsplit_once(' ') {
+            Some((given, surname)) => (surname.trim(), given.trim()),
+            None => (first, ""),
+        },
+    };
+    let rest = parts.filter(|part| !part.is_empty()).collect::<Vec<&str>>().join(", ");
+    let name = match (mode, given.is_empty()) {
+        (_, true) => surname.to_string(),
+        (AuthorNormalize::SurnameFirst, false) => format!("{}, {}", surname, given),
+        (_, false) => format!("{} {}", given, surname),
+    };
+    if rest.is_empty() {
+        name
+    } else {
+        format!("{}, {}", name, rest)
+    }
+}
+
+// Normalized articles from the content of a leading articles file, one article per line.
+// Empty lines and lines starting with # are ignored.
+pub fn leading_articles(content: &str) -> FxHashSet<String> {
+    content.lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .map(normalize)
+        .filter(|article| !article.is_empty())
+        .collect()
+}
+
+// Remove a leading article from the title ("The Art of..." gives "art of..."),
+// unless the article is the only word. The text is normalized when articles are given.
+pub fn strip_leading_article(text: &str, articles: &FxHashSet<String>) -> String {
+    if articles.is_empty() {
+        return text.to_string();
+    }
+    let normalized = normalize(text);
+    if let Some((first_word, rest)) = normalized.split_once(' ') {
+        if articles.contains(first_word) {
+            return rest.trim_start().to_string();
+        }
+    }
+    normalized
+}
+
+// Build a lookup map of normalized variant => normalized canonical name from a gazetteer of canonical name => variants.
+// The canonical name is also a variant of itself.
+pub fn gazetteer_variants(gazetteer: &FxHashMap<String, Vec<String>>) -> FxHashMap<String, String> {
+    let mut variants = FxHashMap::default();
+    for (canonical, canonical_variants) in gazetteer {
+        let normalized_canonical = normalize(canonical);
+        for variant in canonical_variants.iter().chain(std::iter::once(canonical)) {
+            let normalized_variant = normalize(variant);
+            if !normalized_variant.is_empty() {
+                variants.insert(normalized_variant, normalized_canonical.clone());
+            }
+        }
+    }
+    variants
+}
+
+// Replace known place name variants with their canonical name.
+// The text is normalized and matched word by word, preferring the longest variant (in words) at each position.
+pub fn apply_gazetteer(text: &str, variants: &FxHashMap<String, String>) -> String {
+    if variants.is_empty() {
+        return text.to_string();
+    }
+    let max_variant_words = variants.keys().map(|variant| variant.split_whitespace().count()).max().unwrap_or(1);
+    let normalized = normalize(text);
+    let words: Vec<&str> = normalized.split_whitespace().collect();
+    let mut result: Vec<String> = vec![];
+    let mut i = 0;
+    while i < words.len() {
+        let mut matched = false;
+        for n in (1..=max_variant_words.min(words.len() - i)).rev() {
+            if let Some(canonical) = variants.get(&words[i..i + n].join(" ")) {
+                result.push(canonical.clone());
+                i += n;
+                matched = true;
+                break;
+            }
+        }
+        if !matched {
+            result.push(words[i].to_string());
+            i += 1;
+        }
+    }
+    result.join(" ")
+}
+
+// Build a lookup map of acronym => expansion from the acronym file, with the punctuation
+// removed from the acronyms ("U.N." is stored as "UN"). The case is kept.
+pub fn acronym_lookup(acronyms: &FxHashMap<String, String>) -> FxHashMap<String, String> {
+    acronyms.iter()
+        .map(|(acronym, expansion)| (strip_punctuation(acronym), expansion.trim().to_string()))
+        .filter(|(acronym, expansion)| !acronym.is_empty() && !expansion.is_empty())
+        .collect()
+}
+
+// Expand known acronyms in the text ("UN" gives "United Nations").
+// Words are compared without punctuation but case-sensitively, so "U.N." is expanded
+// while the word "un" (as in "un livre") is not. Text without acronyms is returned unchanged.
+pub fn expand_acronyms(text: &str, acronyms: &FxHashMap<String, String>) -> String {
+    if acronyms.is_empty() || !text.split_whitespace().any(|word| acronyms.contains_key(&strip_punctuation(word))) {
+        return text.to_string();
+    }
+    text.split_whitespace().map(|word| {
+        match acronyms.get(&strip_punctuation(word)) {
+            Some(expansion) => expansion.as_str(),
+            None => word,
+        }
+    }).collect::<Vec<&str>>().join(" ")
+}
+
+fn strip_punctuation(word: &str) -> String {
+    word.chars().filter(|c| c.is_alphanumeric()).collect()
+}
+
+fn lookup_hash(lookup: &FxHashMap<String, String>) -> String {
+    let mut pairs: Vec<String> = lookup.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+    pairs.sort();
+    hash::stable_hash_hex(pairs.join("\n").as_bytes())
+}
+
+// This will tokenize into 2 and 3-grams, with the start and end symbols around the string unless sentinels is false
+pub fn tokenize_string(string: &str, sentinels: bool) -> HashMap<String, usize> {
     let string = normalize(string);
-    let string = add_surrounding_tokens(&string);
+    let string = if sentinels { add_surrounding_tokens(&string) } else { string };
     let mut tokens = HashMap::new();
     tokenize_ngram(&string, 2, &mut tokens);
     tokenize_ngram(&string, 3, &mut tokens);
     tokens
 }
 
+// Same 2 and 3-grams as tokenize_string, but within each word, so that no n-gram crosses
+// a space or hyphen ("läkare-vetenskapens" gives the n-grams of "läkare" and "vetenskapens").
+pub fn tokenize_string_within_words(string: &str, sentinels: bool) -> HashMap<String, usize> {
+    let string = normalize(string);
+    let mut tokens = HashMap::new();
+    for word in split_words(&string) {
+        let word = if sentinels { add_surrounding_tokens(word) } else { word.to_string() };
+        tokenize_ngram(&word, 2, &mut tokens);
+        tokenize_ngram(&word, 3, &mut tokens);
+    }
+    tokens
+}
+
+// Whole words as tokens, for short fields where n-grams add little (location)
+pub fn tokenize_words(string: &str) -> HashMap<String, usize> {
+    let string = normalize(string);
+    let mut tokens = HashMap::new();
+    for word in split_words(&string) {
+        let count = tokens.entry(word.to_string()).or_insert(0);
+        *count += 1;
+    }
+    tokens
+}
+
+// Each run of digits as a token of its own, in addition to the n-grams ("Bd. 1742" gives "1742"),
+// so that volume and catalog numbers are not only mixed into the n-grams of the surrounding text
+fn add_numeric_tokens(string: &str, tokens: &mut HashMap<String, usize>) {
+    for number in string.split(|c: char| !c.is_ascii_digit()).filter(|number| !number.is_empty()) {
+        let count = tokens.entry(number.to_string()).or_insert(0);
+        *count += 1;
+    }
+}
+
+fn split_words(string: &str) -> impl Iterator<Item = &str> {
+    string.split([' ', '-']).filter(|word| !word.is_empty())
+}
+
 // Split the string into n-grams and tokenize each n-gram
 fn tokenize_ngram(string: &str, n: usize, tokens: &mut HashMap<String, usize>) {
     // Collect n-grams into a vector. This means there's a running window of n characters to collect
@@ -25,8 +283,10 @@ fn tokenize_ngram(string: &str, n: usize, tokens: &mut HashMap<String, usize>) {
     }
 }
 
-// Year is special since it is a 4-digit number and it is its own single token
-pub fn tokenize_year(year: &str) -> HashMap<String, usize> {
+// Year is special since it is a 4-digit number and it is its own single token.
+// With YearGranularity::Decade the decade is an extra token ("1948" also gives "194x"),
+// so that records with years in the same decade share a year token.
+pub fn tokenize_year(year: &str, granularity: YearGranularity) -> HashMap<String, usize> {
     let mut tokens = HashMap::new();
     // Abort if year is not a 4-digit number
     if year.len() != 4 || !year.chars().all(char::is_numeric) {
@@ -34,10 +294,29 @@ pub fn tokenize_year(year: &str) -> HashMap<String, usize> {
     }
     // At this point we have a single 4-digit number, add or update the token count for the year in the tokens hashmap
     tokens.entry(year.to_string()).and_modify(|count| *count += 1).or_insert(1);
+    if granularity == YearGranularity::Decade {
+        tokens.insert(format!("{}x", year.chars().take(3).collect::<String>()), 1);
+    }
     tokens
 }
 
-fn normalize(text: &str) -> String {
+// With -O clean-year, the year of "c. 1948", "ca 1948", "1948?" and "[1948]". The approximation marks
+// ("c.", "ca.", "?" and brackets) are removed, and if a 4-digit number is left it is the year.
+// Anything else is returned as it is, and gets no year token.
+pub fn clean_year(year: &str) -> String {
+    let unmarked = year.replace(['?', '[', ']', '(', ')'], "").trim().to_lowercase();
+    let digits = ["ca.", "ca", "c."].iter()
+        .find_map(|mark| unmarked.strip_prefix(mark))
+        .unwrap_or(&unmarked)
+        .trim();
+    if digits.len() == 4 && digits.chars().all(|c| c.is_ascii_digit()) {
+        digits.to_string()
+    } else {
+        year.to_string()
+    }
+}
+
+pub fn normalize(text: &str) -> String {
     // Downcase text
     let text = text.to_lowercase();
     // Remove punctuation except for - and space
@@ -58,4 +337,123 @@ fn normalize(text: &str) -> String {
     text.push(ENDSYMBOL);
     text
   }
-  
\ No newline at end of file
+
+#[cfg(test)]
+mod tests {
+    use super::{acronym_lookup, add_numeric_tokens, apply_gazetteer, clean_year, normalize_authors, expand_acronyms, gazetteer_variants, leading_articles, strip_leading_article, tokenize_string, tokenize_string_within_words, tokenize_words, tokenize_year};
+    use crate::args::YearGranularity;
+    use rustc_hash::FxHashMap;
+
+    #[test]
+    fn gazetteer_maps_variants_to_canonical() {
+        let mut gazetteer = FxHashMap::default();
+        gazetteer.insert("Stockholm".to_string(), vec!["Sthlm".to_string(), "[Stockholm]".to_string()]);
+        let variants = gazetteer_variants(&gazetteer);
+        assert_eq!(apply_gazetteer("Sthlm", &variants), "stockholm");
+        assert_eq!(apply_gazetteer("[Stockholm]", &variants), "stockholm");
+        assert_eq!(tokenize_string(&apply_gazetteer("Sthlm", &variants), true), tokenize_string(&apply_gazetteer("[Stockholm]", &variants), true));
+        // Unknown places are only normalized
+        assert_eq!(apply_gazetteer("Uppsala", &variants), "uppsala");
+    }
+
+    #[test]
+    fn acronyms_expanded_only_when_written_as_acronym() {
+        let mut file = FxHashMap::default();
+        file.insert("UN".to_string(), "United Nations".to_string());
+        file.insert("K.B.".to_string(), "Kungliga biblioteket".to_string());
+        let acronyms = acronym_lookup(&file);
+        assert_eq!(expand_acronyms("UN yearbook", &acronyms), "United Nations yearbook");
+        assert_eq!(expand_acronyms("Katalog över KB:s samlingar", &acronyms), "Katalog över KB:s samlingar");
+        assert_eq!(expand_acronyms("Tryck från K.B.", &acronyms), "Tryck från Kungliga biblioteket");
+        assert_eq!(tokenize_string(&expand_acronyms("UN yearbook", &acronyms), true), tokenize_string("United Nations yearbook", true));
+        // Words that only equal an acronym when lowercased are not expanded
+        assert_eq!(expand_acronyms("Un livre", &acronyms), "Un livre");
+        assert_eq!(expand_acronyms("un  livre", &acronyms), "un  livre");
+    }
+
+    #[test]
+    fn authors_in_the_same_name_order() {
+        use crate::args::AuthorNormalize;
+        let authors = "Petersen, Ib Hoy & Naae, Viggo";
+        assert_eq!(normalize_authors(authors, AuthorNormalize::GivenFirst), "Ib Hoy Petersen; Viggo Naae");
+        assert_eq!(normalize_authors(authors, AuthorNormalize::SurnameFirst), "Petersen, Ib Hoy; Naae, Viggo");
+        assert_eq!(normalize_authors("Viggo Naae ; Ib Hoy Petersen", AuthorNormalize::SurnameFirst), "Naae, Viggo; Petersen, Ib Hoy");
+        assert_eq!(normalize_authors(authors, AuthorNormalize::None), authors);
+    }
+
+    #[test]
+    fn author_with_several_commas_keeps_the_rest_last() {
+        use crate::args::AuthorNormalize;
+        assert_eq!(normalize_authors("Strindberg, August, 1849-1912", AuthorNormalize::GivenFirst), "August Strindberg, 1849-1912");
+        assert_eq!(normalize_authors("Strindberg, August, 1849-1912", AuthorNormalize::SurnameFirst), "Strindberg, August, 1849-1912");
+        assert_eq!(normalize_authors("Homeros", AuthorNormalize::GivenFirst), "Homeros");
+    }
+
+    #[test]
+    fn numeric_runs_as_tokens() {
+        let mut tokens = tokenize_string("Bd. 1742, nr 3", true);
+        assert!(!tokens.contains_key("1742"));
+        add_numeric_tokens("Bd. 1742, nr 3", &mut tokens);
+        assert_eq!(tokens["1742"], 1);
+        assert_eq!(tokens["3"], 1);
+    }
+
+    #[test]
+    fn sentinels_off_gives_only_inner_ngrams() {
+        let tokens = tokenize_string("Abc", false);
+        let mut keys = tokens.keys().cloned().collect::<Vec<String>>();
+        keys.sort();
+        assert_eq!(keys, vec!["ab", "abc", "bc"]);
+        assert!(tokenize_string("A", false).is_empty());
+        assert_eq!(tokenize_string("A", true).len(), 3);
+    }
+
+    #[test]
+    fn word_ngrams_do_not_cross_hyphens() {
+        let tokens = tokenize_string_within_words("Läkare-vetenskapens", true);
+        assert!(tokens.contains_key("re\u{0002}"));
+        assert!(tokens.contains_key("\u{0001}ve"));
+        assert!(!tokens.contains_key("e-v"));
+        assert!(tokenize_string("Läkare-vetenskapens", true).contains_key("e-v"));
+        let words = tokenize_words("Holmiae, Stockholm");
+        assert_eq!(words.len(), 2);
+        assert_eq!(words["holmiae"], 1);
+    }
+
+    #[test]
+    fn leading_article_gives_same_title_tokens() {
+        let articles = leading_articles("# English\nThe\nA\n\nden\n");
+        assert_eq!(tokenize_string(&strip_leading_article("The great book", &articles), true), tokenize_string(&strip_leading_article("great book", &articles), true));
+        assert_eq!(strip_leading_article("Den stora boken", &articles), "stora boken");
+        // Only a leading article is removed, and never the whole title
+        assert_eq!(strip_leading_article("Boken om den", &articles), "boken om den");
+        assert_eq!(strip_leading_article("The", &articles), "the");
+    }
+
+    #[test]
+    fn decade_tokens_only_shared_within_decade() {
+        // Default is the exact year only
+        assert_eq!(tokenize_year("1949", YearGranularity::Year).len(), 1);
+        let year_1948 = tokenize_year("1948", YearGranularity::Decade);
+        let year_1949 = tokenize_year("1949", YearGranularity::Decade);
+        let year_1950 = tokenize_year("1950", YearGranularity::Decade);
+        assert!(year_1948.contains_key("1948") && year_1948.contains_key("194x"));
+        assert!(year_1949.contains_key("194x"));
+        assert!(year_1950.contains_key("195x"));
+        assert!(!year_1950.keys().any(|token| year_1949.contains_key(token)));
+        assert!(tokenize_year("194", YearGranularity::Decade).is_empty());
+    }
+
+    #[test]
+    fn clean_year_removes_approximation_marks() {
+        assert_eq!(clean_year("c. 1948"), "1948");
+        assert_eq!(clean_year("1948?"), "1948");
+        assert_eq!(clean_year("[1948]"), "1948");
+        assert_eq!(clean_year("[ca. 1948?]"), "1948");
+        assert_eq!(clean_year("1948"), "1948");
+        // Not a single year, left as it is
+        assert_eq!(clean_year("1948-1950"), "1948-1950");
+        assert_eq!(clean_year("194?"), "194?");
+        assert!(!tokenize_year(&clean_year("c. 1948"), YearGranularity::Year).is_empty());
+    }
+}
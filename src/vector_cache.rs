@@ -0,0 +1,131 @@
+use crate::args::Config;
+use crate::hash::StableHasher;
+use rustc_hash::FxHashMap;
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+// Cache of the weighted dataset vectors calculated when matching starts, so that repeated runs
+// with the same vectors file and weights can load them instead of calculating them again.
+// The cache is a single file next to each vectors file and is replaced when the key changes.
+// Disabled with -O no-vector-cache.
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile<T> {
+    key: String,
+    source: String, // Source of the vectors file, for the source check
+    vectors: T,
+}
+
+// "data/libris-dataset-vectors.bin" (or .bin.gz) gives "data/libris-dataset-vectors-weighted.bin"
+pub fn cache_file_name(dataset_vector_file: &str) -> String {
+    let base = dataset_vector_file.trim_end_matches(".gz").trim_end_matches(".bin");
+    format!("{}-weighted.bin", base)
+}
+
+// The key of the cache: the vectors file (its size and modification time, so it is not read
+// to calculate the key) and everything the weighted vectors are calculated from, which is the
+// weights of each profile, length-normalize and the parts kept as vectors of their own.
+// None if the vectors file can not be read.
+pub fn cache_key(config: &Config, dataset_vector_file: &str, weights: &[&FxHashMap<String, f32>], parts: &[&str]) -> Option<String> {
+    let metadata = std::fs::metadata(dataset_vector_file).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(std::time::UNIX_EPOCH).ok()?;
+    let mut hasher = StableHasher::new();
+    hasher.update(format!("{}:{}:{}", metadata.len(), modified.as_secs(), modified.subsec_nanos()).as_bytes());
+    hasher.update(&[config.options.length_normalize as u8]);
+    hasher.update(parts.join(",").as_bytes());
+    for profile_weights in weights {
+        let sorted = profile_weights.iter().collect::<BTreeMap<&String, &f32>>();
+        for (part, weight) in sorted {
+            hasher.update(format!("{}={};", part, weight).as_bytes());
+        }
+        hasher.update(b"|");
+    }
+    Some(format!("{:016x}", hasher.finish()))
+}
+
+// The source and the cached vectors if the cache file exists and has the same key
+pub fn load<T: DeserializeOwned>(config: &Config, dataset_vector_file: &str, key: &str) -> Option<(String, T)> {
+    let filename = cache_file_name(dataset_vector_file);
+    let file = File::open(&filename).ok()?;
+    let cache: CacheFile<T> = match bincode::deserialize_from(BufReader::new(file)) {
+        Ok(cache) => cache,
+        Err(_) => {
+            eprintln!("Ignoring unreadable weighted vectors cache {}", filename);
+            return None;
+        }
+    };
+    if cache.key != key {
+        if config.verbose {
+            eprintln!("Weighted vectors cache {} is for other vectors or weights, recalculating", filename);
+        }
+        return None;
+    }
+    eprintln!("Loaded weighted vectors from cache {}", filename);
+    Some((cache.source, cache.vectors))
+}
+
+// Failing to write the cache is not an error, matching works the same without it.
+// The cache is written to a temporary file next to it and then renamed over it, so that other runs
+// (or a crash while writing) never see a half-written cache.
+pub fn save<T: Serialize>(config: &Config, dataset_vector_file: &str, key: &str, source: &str, vectors: &T) {
+    let filename = cache_file_name(dataset_vector_file);
+    let temporary_filename = format!("{}.{}.tmp", filename, std::process::id());
+    let cache = CacheFile {
+        key: key.to_string(),
+        source: source.to_string(),
+        vectors,
+    };
+    let result = write_cache(&temporary_filename, &cache)
+        .and_then(|()| std::fs::rename(&temporary_filename, &filename).map_err(|e| e.to_string()));
+    match result {
+        Ok(()) => {
+            if config.verbose {
+                eprintln!("Saved weighted vectors cache {}", filename);
+            }
+        },
+        Err(e) => {
+            eprintln!("Warning: failed to write weighted vectors cache {}: {}", filename, e);
+            let _ = std::fs::remove_file(&temporary_filename);
+        }
+    }
+}
+
+fn write_cache<T: Serialize>(filename: &str, cache: &CacheFile<&T>) -> Result<(), String> {
+    let file = File::create(filename).map_err(|e| e.to_string())?;
+    let mut writer = BufWriter::new(file);
+    bincode::serialize_into(&mut writer, cache).map_err(|e| e.to_string())?;
+    let file = writer.into_inner().map_err(|e| e.to_string())?;
+    file.sync_all().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cache_file_name, load, save};
+    use crate::args::Config;
+
+    #[test]
+    fn cache_file_next_to_vectors_file() {
+        assert_eq!(cache_file_name("data/libris-dataset-vectors.bin"), "data/libris-dataset-vectors-weighted.bin");
+        assert_eq!(cache_file_name("data/libris-dataset-vectors.bin.gz"), "data/libris-dataset-vectors-weighted.bin");
+        assert_eq!(cache_file_name("vectors"), "vectors-weighted.bin");
+    }
+
+    #[test]
+    fn cache_is_replaced_without_temporary_files() {
+        let config = Config::from_args(&["-c", "match-single-json", "-s", "test"]);
+        let dir = std::env::temp_dir().join(format!("vector-cache-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let vectors_file = dir.join("vectors.bin");
+        let vectors_file = vectors_file.to_str().unwrap();
+        save(&config, vectors_file, "key1", "test", &vec![1u32, 2]);
+        save(&config, vectors_file, "key2", "test", &vec![3u32]);
+        let loaded = load::<Vec<u32>>(&config, vectors_file, "key2");
+        let files = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded, Some(("test".to_string(), vec![3])));
+        assert_eq!(files, 1);
+    }
+}
@@ -2,14 +2,42 @@ use crate::vocab::Vocab;
 use crate::elastic::{self, Pagination, Record};
 use crate::tokenizer;
 use crate::args::Config;
-use std::collections::HashMap;
+use crate::build::BuildProgress;
+use crate::cmd::{self, Cmd};
+use std::collections::{HashMap, HashSet};
 use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use std::io::Read;
+
+// Number of documents handed over at a time by Vectors::load_chunked (-O lazy-vectors)
+pub const LOAD_CHUNK_SIZE: usize = 10000;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Vectors {
     pub source: String,
     pub total_docs: u32,
     pub documents: Vec<Document>,
+    // Highest value of the date field (-O date-field) among the documents, used by -O append
+    pub max_date: Option<String>,
+}
+
+// Vectors files built before max_date was added to Vectors
+#[derive(Debug, Deserialize)]
+struct LegacyVectors {
+    source: String,
+    total_docs: u32,
+    documents: Vec<Document>,
+}
+
+impl From<LegacyVectors> for Vectors {
+    fn from(legacy: LegacyVectors) -> Self {
+        Vectors {
+            source: legacy.source,
+            total_docs: legacy.total_docs,
+            documents: legacy.documents,
+            max_date: None,
+        }
+    }
 }
 
 impl Vectors {
@@ -18,21 +46,61 @@ impl Vectors {
             source: config.options.output_source_name.clone(),
             total_docs,
             documents: vec![],
+            max_date: None,
         }
     }
 
     pub fn save(&self, file: &str) {
-        let mut writer = std::io::BufWriter::new(std::fs::File::create(file).unwrap());
+        let mut writer = cmd::create_data_file(file);
         bincode::serialize_into(&mut writer, self).unwrap();
+        writer.finish().unwrap();
     }
 
     pub fn load(file: &str) -> Vectors {
-        println!("Loading vectors from {}", file);
-        let reader = std::io::BufReader::new(std::fs::File::open(file).unwrap());
-        bincode::deserialize_from(reader).unwrap()
+        eprintln!("Loading vectors from {}", file);
+        let reader = cmd::open_data_file(file, "dataset vectors", Cmd::BuildDatasetVectors);
+        match bincode::deserialize_from(reader) {
+            Ok(vectors) => vectors,
+            Err(_) => {
+                // Fall back to the layout without max_date
+                let reader = cmd::open_data_file(file, "dataset vectors", Cmd::BuildDatasetVectors);
+                let legacy: LegacyVectors = bincode::deserialize_from(reader)
+                    .unwrap_or_else(|_| cmd::invalid_data_file(file, "dataset vectors", Cmd::BuildDatasetVectors));
+                legacy.into()
+            }
+        }
+    }
+
+    // Read the vectors file one chunk of documents at a time and hand each chunk to process_chunk,
+    // so that all documents never have to be in memory at once (-O lazy-vectors).
+    // bincode writes the documents as their count followed by each document, so the fields
+    // can be read one by one in the order they are serialized in. Returns the source of the vectors,
+    // the max_date after the documents is only needed by append, which loads the whole file.
+    pub fn load_chunked(file: &str, mut process_chunk: impl FnMut(Vec<Document>)) -> String {
+        eprintln!("Loading vectors in chunks from {}", file);
+        let mut reader = cmd::open_data_file(file, "dataset vectors", Cmd::BuildDatasetVectors);
+        let source: String = read_field(&mut reader, file);
+        let _total_docs: u32 = read_field(&mut reader, file);
+        let document_count: u64 = read_field(&mut reader, file);
+        let mut chunk = Vec::with_capacity(LOAD_CHUNK_SIZE);
+        for _ in 0..document_count {
+            chunk.push(read_field(&mut reader, file));
+            if chunk.len() == LOAD_CHUNK_SIZE {
+                process_chunk(std::mem::replace(&mut chunk, Vec::with_capacity(LOAD_CHUNK_SIZE)));
+            }
+        }
+        if !chunk.is_empty() {
+            process_chunk(chunk);
+        }
+        source
     }
 }
 
+fn read_field<T: DeserializeOwned>(reader: &mut impl Read, file: &str) -> T {
+    bincode::deserialize_from(reader)
+        .unwrap_or_else(|_| cmd::invalid_data_file(file, "dataset vectors", Cmd::BuildDatasetVectors))
+}
+
 type VectorIndex = u32;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -46,14 +114,52 @@ pub fn build_dataset_vectors(config: &Config) {
     if config.verbose {
         println!("Loaded vocab from {}", config.vocab_file);
     }
-    let vectors = process_source(config, &config.source, &vocab);
+    vocab.check_metadata(config);
+    let vectors = if config.options.append {
+        append_source(config, &config.source, &vocab)
+    } else {
+        process_source(config, &config.source, &vocab, Vectors::new(config, 0), None)
+    };
     vectors.save(&config.dataset_vector_file);
 }
 
-fn process_source(config: &Config, source: &str, vocab: &Vocab) -> Vectors {
-    let mut vectors = Vectors::new(config, 0);
+// Build the dataset vectors with a vocab that is already in memory (build-all)
+pub fn build_dataset_vectors_with_vocab(config: &Config, vocab: &Vocab) {
+    let vectors = process_source(config, &config.source, vocab, Vectors::new(config, 0), None);
+    vectors.save(&config.dataset_vector_file);
+}
+
+// Load the existing vectors file and add the records with a date field value after its max_date
+fn append_source(config: &Config, source: &str, vocab: &Vocab) -> Vectors {
+    let date_field = match &config.options.date_field {
+        Some(date_field) => date_field.clone(),
+        None => {
+            eprintln!("Option date-field is required with append");
+            std::process::exit(1);
+        }
+    };
+    let vectors = Vectors::load(&config.dataset_vector_file);
+    let max_date = match &vectors.max_date {
+        Some(max_date) => max_date.clone(),
+        None => {
+            eprintln!("Vectors file {} has no stored max date, rebuild it once with -O date-field={} before using append", config.dataset_vector_file, date_field);
+            std::process::exit(1);
+        }
+    };
+    println!("Appending records from {} with {} after {}", config.options.output_source_name, date_field, max_date);
+    let filter = elastic::RangeFilter {
+        field: date_field,
+        greater_than: max_date,
+    };
+    process_source(config, source, vocab, vectors, Some(&filter))
+}
+
+fn process_source(config: &Config, source: &str, vocab: &Vocab, mut vectors: Vectors, filter: Option<&elastic::RangeFilter>) -> Vectors {
+    // Records already in the vectors (when appending) are skipped
+    let existing_ids = vectors.documents.iter().map(|document| document.id.clone()).collect::<HashSet<String>>();
     let mut counter = 0;
-    let mut records = elastic::fetch_source(config, source, Pagination::Initial, 0);
+    let progress = BuildProgress::start();
+    let mut records = elastic::fetch_source_filtered(config, source, Pagination::Initial, 0, filter);
     loop {
         if let Ok((_, Pagination::Done, _)) = records {
             break;
@@ -61,41 +167,49 @@ fn process_source(config: &Config, source: &str, vocab: &Vocab) -> Vectors {
         if let Ok((new_records, new_pagination, total_count)) = records {
             counter += new_records.len() as u32;
             if counter % 10000 == 0 {
-                println!("Processing {} records from {}", counter, config.options.output_source_name);
-                // if counter >= 100000 {
-                //     return counter;
-                // }
+                println!("Processing {} records from {} ({})", counter, config.options.output_source_name, progress.summary(counter));
             }
 
-            // if counter >= 100000 {
-            //     break;
-            // }
-
             for record in new_records {
                 // println!("Record: {:?}", record);
-                let doc = process_record(&record, vocab);
+                if existing_ids.contains(&record.id) {
+                    continue;
+                }
+                if !record.date.is_empty() && vectors.max_date.as_ref().is_none_or(|max_date| is_later_date(&record.date, max_date)) {
+                    vectors.max_date = Some(record.date.clone());
+                }
+                let doc = process_record(config, &record, vocab);
                 vectors.documents.push(doc);
                 // println!("Document: {:?}", doc);
                 // std::process::exit(1);
             }
-            records = elastic::fetch_source(config, source, new_pagination, total_count);
+            records = elastic::fetch_source_filtered(config, source, new_pagination, total_count, filter);
         }
     }
-    println!("Processed {} records in {}", counter, config.options.output_source_name);
-    vectors.total_docs = counter;
+    println!("Processed {} records in {} ({})", counter, config.options.output_source_name, progress.summary(counter));
+    vectors.total_docs = vectors.documents.len() as u32;
     vectors
 }
 
+// Whether a value of -O date-field is after the max_date so far. Numbers (such as epoch milliseconds) are
+// compared as numbers, anything else as text, which is only right for ISO 8601 dates in one format (see README).
+fn is_later_date(date: &str, max_date: &str) -> bool {
+    match (date.parse::<f64>(), max_date.parse::<f64>()) {
+        (Ok(date), Ok(max_date)) => date > max_date,
+        _ => date > max_date,
+    }
+}
+
 // Tokenize each of author, title, location, year and combined (all)
 // Calculate the tf-idf for each word in each part
 // There should be a tf-idf vector for each part
-pub fn process_record(record: &Record, vocab: &Vocab) -> Document {
+pub fn process_record(config: &Config, record: &Record, vocab: &Vocab) -> Document {
     let id = record.id.clone();
-    let author_vec = process_part("author", &tokenizer::tokenize_string(&record.author), vocab);
-    let title_vec = process_part("title", &tokenizer::tokenize_string(&record.title), vocab);
-    let location_vec = process_part("location", &tokenizer::tokenize_string(&record.location), vocab);
-    let year_vec = process_part("year", &tokenizer::tokenize_year(&record.year), vocab);
-    let all_vec = process_part("all", &tokenizer::tokenize_string(&record.combined()), vocab);
+    let author_vec = process_part("author", &tokenizer::tokenize_part(&config.options, "author", &record.author), vocab);
+    let title_vec = process_part("title", &tokenizer::tokenize_part(&config.options, "title", &record.title), vocab);
+    let location_vec = process_part("location", &tokenizer::tokenize_part(&config.options, "location", &record.location), vocab);
+    let year_vec = process_part("year", &tokenizer::tokenize_part(&config.options, "year", &record.year), vocab);
+    let all_vec = process_part("all", &tokenizer::tokenize_part(&config.options, "all", &record.combined(&config.options.combined_order)), vocab);
     let mut vectors = HashMap::new();
     vectors.insert("author".to_string(), author_vec);
     vectors.insert("title".to_string(), title_vec);
@@ -150,4 +264,38 @@ fn tfmax(vector: &mut Vec<f64>) {
     for value in vector.iter_mut() {
         *value = 0.5 + 0.5 * (*value / max);
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::{is_later_date, Document, Vectors};
+    use std::collections::HashMap;
+
+    #[test]
+    fn chunked_load_reads_same_documents() {
+        let vectors = Vectors {
+            source: "test".to_string(),
+            total_docs: 3,
+            documents: (0..3).map(|index| Document {
+                id: index.to_string(),
+                vectors: HashMap::from([("all".to_string(), vec![(index, 1.0)])]),
+            }).collect(),
+            max_date: Some("2024-01-01".to_string()),
+        };
+        let path = std::env::temp_dir().join(format!("vectors-test-{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+        vectors.save(path);
+        let mut ids = vec![];
+        let source = Vectors::load_chunked(path, |documents| ids.extend(documents.into_iter().map(|document| document.id)));
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(ids, vec!["0", "1", "2"]);
+        assert_eq!(source, "test");
+    }
+
+    #[test]
+    fn later_dates_as_numbers_or_text() {
+        assert!(is_later_date("1700000000000", "999999999999"));
+        assert!(is_later_date("2024-02-01", "2024-01-31"));
+        assert!(is_later_date("2024-01-31T10:00:00Z", "2024-01-31"));
+        assert!(!is_later_date("2024-01-31", "2024-01-31"));
+    }
+}
@@ -24,16 +24,46 @@
 use crate::tokenizer;
 use crate::elastic;
 use crate::elastic::Pagination;
-use crate::args::Config;
-use std::collections::HashMap;
+use crate::args::{Config, OutputFormat};
+use crate::build::BuildProgress;
+use crate::cmd::{self, Cmd};
+use crate::output::Output;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
 use serde::{Serialize, Deserialize};
 
+// Number of most and least frequent tokens per part shown by inspect-vocab
+const INSPECT_TOKEN_COUNT: usize = 10;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Vocab {
     pub source: String,
     pub total_docs: TotalDocs,
     pub words: Vec<String>,
     pub vocab_parts: HashMap<String, VocabPart>,
+    // Tokenizer options used when building the vocab (see tokenizer::tokenizer_metadata)
+    pub metadata: BTreeMap<String, String>,
+}
+
+// Vocab files built before metadata was added to Vocab
+#[derive(Debug, Deserialize)]
+struct LegacyVocab {
+    source: String,
+    total_docs: TotalDocs,
+    words: Vec<String>,
+    vocab_parts: HashMap<String, VocabPart>,
+}
+
+impl From<LegacyVocab> for Vocab {
+    fn from(legacy: LegacyVocab) -> Self {
+        Vocab {
+            source: legacy.source,
+            total_docs: legacy.total_docs,
+            words: legacy.words,
+            vocab_parts: legacy.vocab_parts,
+            metadata: BTreeMap::new(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -66,9 +96,19 @@ impl VocabPart {
     }
 }
 
-impl Vocab {
-    pub fn new(config: &Config, source: &str) -> Vocab {
-        let mut words_vec = vec![tokenizer::UNKNOWN.to_string()];
+// Accumulates the tokens of records one at a time, used by Vocab::new and by build-all
+// (which shares the Elastic scan with the source data build).
+pub struct VocabBuilder {
+    words_vec: Vec<String>,
+    words_map: HashMap<String, usize>,
+    vocab_parts: HashMap<String, VocabPart>,
+    total_docs: TotalDocs,
+    part_docs: HashMap<String, TotalDocs>, // Documents with a value in each part, for -O part-idf
+}
+
+impl VocabBuilder {
+    pub fn new() -> VocabBuilder {
+        let words_vec = vec![tokenizer::UNKNOWN.to_string()];
         let mut words_map = HashMap::new();
         words_map.insert(tokenizer::UNKNOWN.to_string(), 0);
         let mut vocab_parts = HashMap::new();
@@ -77,28 +117,89 @@ impl Vocab {
         vocab_parts.insert("location".to_string(), VocabPart::new(VocabPartType::Ngram));
         vocab_parts.insert("year".to_string(), VocabPart::new(VocabPartType::Year));
         vocab_parts.insert("all".to_string(), VocabPart::new(VocabPartType::Ngram));
-        let total_docs = process_source(config, source, &mut words_vec, &mut words_map, &mut vocab_parts);
-        // Loop through the vocab_parts hashmap to calculate the idf for each part
-        for (_, vocab_part) in vocab_parts.iter_mut() {
-            vocab_part.idf = calculate_idf(words_vec.len(), total_docs, &vocab_part.tokens);
+        let part_docs = vocab_parts.keys().map(|part| (part.clone(), 0)).collect();
+        VocabBuilder {
+            words_vec,
+            words_map,
+            vocab_parts,
+            total_docs: 0,
+            part_docs,
+        }
+    }
+
+    pub fn add_record(&mut self, config: &Config, record: &elastic::Record) {
+        process_record(config, record, &mut self.words_vec, &mut self.words_map, &mut self.vocab_parts, &mut self.part_docs);
+        self.total_docs += 1;
+    }
+
+    // Calculate the idf for each part once all records have been added
+    pub fn finish(mut self, config: &Config) -> Vocab {
+        for (part, vocab_part) in self.vocab_parts.iter_mut() {
+            let total_docs = idf_total_docs(config.options.part_idf, self.total_docs, self.part_docs[part]);
+            vocab_part.idf = calculate_idf(self.words_vec.len(), total_docs, &vocab_part.tokens);
         }
         Vocab {
             source: config.options.output_source_name.clone(),
-            total_docs,
-            words: words_vec,
-            vocab_parts,
+            total_docs: self.total_docs,
+            words: self.words_vec,
+            vocab_parts: self.vocab_parts,
+            metadata: tokenizer::tokenizer_metadata(&config.options),
         }
     }
+}
+
+impl Vocab {
+    pub fn new(config: &Config, source: &str) -> Vocab {
+        let mut builder = VocabBuilder::new();
+        process_source(config, source, &mut builder);
+        builder.finish(config)
+    }
 
     pub fn save(&self, path: &str) {
-        let file = std::fs::File::create(path).unwrap();
-        bincode::serialize_into(file, self).unwrap();
+        let mut writer = cmd::create_data_file(path);
+        bincode::serialize_into(&mut writer, self).unwrap();
+        writer.finish().unwrap();
     }
 
     pub fn load(path: &str) -> Vocab {
-        println!("Loading vocab from {}", path);
-        let file = std::fs::File::open(path).unwrap();
-        bincode::deserialize_from(file).unwrap()
+        eprintln!("Loading vocab from {}", path);
+        let file = cmd::open_data_file(path, "vocab", Cmd::BuildVocab);
+        match bincode::deserialize_from(file) {
+            Ok(vocab) => vocab,
+            Err(_) => {
+                // Fall back to the layout without metadata
+                let file = cmd::open_data_file(path, "vocab", Cmd::BuildVocab);
+                let legacy: LegacyVocab = bincode::deserialize_from(file)
+                    .unwrap_or_else(|_| cmd::invalid_data_file(path, "vocab", Cmd::BuildVocab));
+                legacy.into()
+            }
+        }
+    }
+
+    // Compare the tokenizer options the vocab was built with to the current options.
+    // Tokens from different options do not match: a different tokenizer is an error (unless skip-source-check is used),
+    // a different lookup file (the *_hash keys) is only a warning since the files may have been extended.
+    pub fn check_metadata(&self, config: &Config) {
+        let current = tokenizer::tokenizer_metadata(&config.options);
+        let keys = self.metadata.keys().chain(current.keys()).collect::<std::collections::BTreeSet<&String>>();
+        let mut mismatch = false;
+        for key in keys {
+            let built_with = self.metadata.get(key).map(|value| value.as_str()).unwrap_or("default");
+            let used_with = current.get(key).map(|value| value.as_str()).unwrap_or("default");
+            if built_with != used_with {
+                let message = format!("vocab {} was built with {}={}, but the current options give {}={}. Use the same options as when building the vocab.", config.vocab_file, key, built_with, key, used_with);
+                if key.ends_with("_hash") || config.options.skip_source_check {
+                    eprintln!("Warning: {}", message);
+                } else {
+                    eprintln!("Error: {}", message);
+                    mismatch = true;
+                }
+            }
+        }
+        if mismatch {
+            eprintln!("Use -O skip-source-check to continue anyway");
+            std::process::exit(1);
+        }
     }
 
     pub fn print_vocab_stats(&self) {
@@ -111,6 +212,101 @@ impl Vocab {
     }
 }
 
+// Statistics of a vocab file, written by inspect-vocab
+#[derive(Debug, Serialize)]
+struct VocabStats {
+    source: String,
+    total_docs: TotalDocs,
+    words: usize,
+    metadata: BTreeMap<String, String>,
+    parts: BTreeMap<String, VocabPartStats>,
+}
+
+#[derive(Debug, Serialize)]
+struct VocabPartStats {
+    tokens: usize, // Tokens found in at least one document
+    most_frequent: Vec<TokenCount>,
+    least_frequent: Vec<TokenCount>,
+    idf_min: f64,
+    idf_max: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct TokenCount {
+    token: String,
+    documents: DocCount,
+}
+
+impl VocabPartStats {
+    // The unknown token (no documents) is left out. Tokens with the same count are ordered by token,
+    // and the start and end symbols of n-grams are shown as ^ and $.
+    fn new(vocab_part: &VocabPart) -> VocabPartStats {
+        let mut tokens = vocab_part.tokens.iter()
+            .filter(|(_, (_, doc_count))| *doc_count > 0)
+            .collect::<Vec<(&String, &(WordIndex, DocCount))>>();
+        tokens.sort_by(|(token_a, (_, count_a)), (token_b, (_, count_b))| count_b.cmp(count_a).then(token_a.cmp(token_b)));
+        let token_count = |(token, (_, doc_count)): &(&String, &(WordIndex, DocCount))| TokenCount {
+            token: token.replace(tokenizer::STARTSYMBOL, "^").replace(tokenizer::ENDSYMBOL, "$"),
+            documents: *doc_count,
+        };
+        let idfs = tokens.iter().filter_map(|(_, (index, _))| vocab_part.idf.get(*index).copied()).collect::<Vec<f64>>();
+        let idf_min = if idfs.is_empty() { 0.0 } else { idfs.iter().cloned().fold(f64::INFINITY, f64::min) };
+        VocabPartStats {
+            tokens: tokens.len(),
+            most_frequent: tokens.iter().take(INSPECT_TOKEN_COUNT).map(token_count).collect(),
+            least_frequent: tokens.iter().rev().take(INSPECT_TOKEN_COUNT).map(token_count).collect(),
+            idf_min,
+            idf_max: idfs.iter().cloned().fold(0.0, f64::max),
+        }
+    }
+}
+
+// Print the statistics of an existing vocab file (-c inspect-vocab), as text or JSON
+pub fn inspect_vocab(config: &Config) {
+    let vocab = Vocab::load(&config.vocab_file);
+    let stats = VocabStats {
+        source: vocab.source.clone(),
+        total_docs: vocab.total_docs,
+        words: vocab.words.len(),
+        metadata: vocab.metadata.clone(),
+        parts: vocab.vocab_parts.iter().map(|(part, vocab_part)| (part.clone(), VocabPartStats::new(vocab_part))).collect(),
+    };
+    let mut writer: Box<dyn Write> = match &config.output {
+        Output::Stdout => Box::new(std::io::stdout()),
+        Output::File(filename) => {
+            let file = std::fs::File::create(filename).unwrap_or_else(|e| {
+                eprintln!("Failed to create {}: {}", filename, e);
+                std::process::exit(1);
+            });
+            Box::new(std::io::BufWriter::new(file))
+        }
+    };
+    let result = match config.output_format {
+        OutputFormat::Json => serde_json::to_writer_pretty(&mut writer, &stats).map_err(std::io::Error::from),
+        _ => write_vocab_stats_text(&mut writer, &stats),
+    };
+    if let Err(e) = result.and_then(|_| writer.flush()) {
+        eprintln!("Failed to write vocab statistics: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn write_vocab_stats_text(writer: &mut dyn Write, stats: &VocabStats) -> std::io::Result<()> {
+    writeln!(writer, "Source: {}", stats.source)?;
+    writeln!(writer, "Total documents: {}", stats.total_docs)?;
+    writeln!(writer, "Total words: {}", stats.words)?;
+    for (key, value) in &stats.metadata {
+        writeln!(writer, "Built with {}={}", key, value)?;
+    }
+    for (part, part_stats) in &stats.parts {
+        writeln!(writer, "\nPart {}: {} tokens, idf {:.4} - {:.4}", part, part_stats.tokens, part_stats.idf_min, part_stats.idf_max)?;
+        let format_tokens = |tokens: &[TokenCount]| tokens.iter().map(|token| format!("{} ({})", token.token, token.documents)).collect::<Vec<String>>().join(", ");
+        writeln!(writer, "  Most frequent: {}", format_tokens(&part_stats.most_frequent))?;
+        writeln!(writer, "  Least frequent: {}", format_tokens(&part_stats.least_frequent))?;
+    }
+    Ok(())
+}
+
 pub fn build_vocab(config: &Config) {
     let source = &config.source;
     let output_filename = &config.vocab_file;
@@ -119,6 +315,17 @@ pub fn build_vocab(config: &Config) {
     vocab.save(output_filename);
 }
 
+// Number of documents the idf of a part is calculated from. All documents by default, with -O part-idf
+// only the documents with a value in the part, so that tokens of a part that is empty in most documents
+// are not given a high idf only because the part is rarely filled in.
+fn idf_total_docs(part_idf: bool, total_docs: TotalDocs, part_docs: TotalDocs) -> TotalDocs {
+    if part_idf {
+        part_docs
+    } else {
+        total_docs
+    }
+}
+
 fn calculate_idf(vocab_size: usize, total_docs: TotalDocs, doc_counts: &HashMap<String, (WordIndex, DocCount)>) -> Vec<f64> {
     let mut idfs = vec![0.0; vocab_size];
     for (_, (index, doc_count)) in doc_counts.iter() {
@@ -135,11 +342,17 @@ fn calculate_single_idf(total_docs: TotalDocs, doc_count: DocCount) -> f64 {
     let doc_count = doc_count as f64;
     let total_docs = total_docs as f64;
     let idf = total_docs / doc_count;
-    idf.log10()
+    // With -O part-idf the empty value token can be in more documents than have a value in the part
+    idf.log10().max(0.0)
 }
 
-fn process_source(config: &Config, source: &str, words_vec: &mut Vec<String>, words_map: &mut HashMap<String, usize>, vocab_parts: &mut HashMap<String, VocabPart>) -> TotalDocs {
+// The only token of an empty (or punctuation only) value with sentinels. It does not count
+// as a value in the part for -O part-idf.
+const EMPTY_VALUE_TOKEN: &str = "\u{0001}\u{0002}";
+
+fn process_source(config: &Config, source: &str, builder: &mut VocabBuilder) {
     let mut counter = 0;
+    let progress = BuildProgress::start();
     let mut records = elastic::fetch_source(config, source, Pagination::Initial, 0);
     loop {
         if let Ok((_, Pagination::Done, _)) = records {
@@ -148,35 +361,29 @@ fn process_source(config: &Config, source: &str, words_vec: &mut Vec<String>, wo
         if let Ok((new_records, new_pagination, total_count)) = records {
             counter += new_records.len() as u32;
             if counter % 10000 == 0 {
-                println!("Processing {} records from {}", counter, config.options.output_source_name);
-                // if counter >= 100000 {
-                //     return counter;
-                // }
+                println!("Processing {} records from {} ({})", counter, config.options.output_source_name, progress.summary(counter));
             }
             for record in new_records {
-                process_record(&record, words_vec, words_map, vocab_parts);
+                builder.add_record(config, &record);
             }
             records = elastic::fetch_source(config, source, new_pagination, total_count);
         }
     }
-    println!("Processed {} records in {}", counter, config.options.output_source_name);
-    counter
+    println!("Processed {} records in {} ({})", counter, config.options.output_source_name, progress.summary(counter));
 }
 
-fn process_record(record: &elastic::Record, words_vec: &mut Vec<String>, words_map: &mut HashMap<String, usize>, vocab_parts: &mut HashMap<String, VocabPart>) {
-    process_record_part(&record.author, words_vec, words_map, vocab_parts.get_mut("author").unwrap());
-    process_record_part(&record.title, words_vec, words_map, vocab_parts.get_mut("title").unwrap());
-    process_record_part(&record.location, words_vec, words_map, vocab_parts.get_mut("location").unwrap());
-    process_record_part(&record.year, words_vec, words_map, vocab_parts.get_mut("year").unwrap());
-    process_record_part(&record.combined(), words_vec, words_map, vocab_parts.get_mut("all").unwrap());
+fn process_record(config: &Config, record: &elastic::Record, words_vec: &mut Vec<String>, words_map: &mut HashMap<String, usize>, vocab_parts: &mut HashMap<String, VocabPart>, part_docs: &mut HashMap<String, TotalDocs>) {
+    let combined = record.combined(&config.options.combined_order);
+    for (part, record_part) in [("author", &record.author), ("title", &record.title), ("location", &record.location), ("year", &record.year), ("all", &combined)] {
+        process_record_part(config, part, record_part, words_vec, words_map, vocab_parts.get_mut(part).unwrap(), part_docs.get_mut(part).unwrap());
+    }
 }
 
-fn process_record_part(record_part: &str, words_vec: &mut Vec<String>, words_map: &mut HashMap<String, usize>, vocab_part: &mut VocabPart) {
-    let tokens_count = 
-        match vocab_part.part_type {
-            VocabPartType::Ngram => tokenizer::tokenize_string(record_part),
-            VocabPartType::Year => tokenizer::tokenize_year(record_part),
-        };
+fn process_record_part(config: &Config, part: &str, record_part: &str, words_vec: &mut Vec<String>, words_map: &mut HashMap<String, usize>, vocab_part: &mut VocabPart, part_docs: &mut TotalDocs) {
+    let tokens_count = tokenizer::tokenize_part(&config.options, part, record_part);
+    if tokens_count.keys().any(|token| token != EMPTY_VALUE_TOKEN) {
+        *part_docs += 1;
+    }
     // Loop through the tokens_count hashmap.
     // For each token, check if it exists in the words vector and get its index.
     // If it doesn't exist, add it to the words vector and get its index.
@@ -197,3 +404,74 @@ fn process_record_part(record_part: &str, words_vec: &mut Vec<String>, words_map
         *doc_count += 1;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{calculate_idf, idf_total_docs, VocabBuilder, VocabPart, VocabPartStats, VocabPartType, EMPTY_VALUE_TOKEN};
+    use crate::args::Config;
+    use crate::elastic::Record;
+
+    #[test]
+    fn part_stats_order_tokens_by_document_count() {
+        let mut part = VocabPart::new(VocabPartType::Ngram);
+        part.tokens.insert("\u{0001}st".to_string(), (1, 8));
+        part.tokens.insert("sto".to_string(), (2, 3));
+        part.tokens.insert("ock".to_string(), (3, 3));
+        part.tokens.insert("lm\u{0002}".to_string(), (4, 1));
+        part.idf = calculate_idf(5, 10, &part.tokens);
+        let stats = VocabPartStats::new(&part);
+        // The unknown token is not counted
+        assert_eq!(stats.tokens, 4);
+        let most = stats.most_frequent.iter().map(|token| token.token.as_str()).collect::<Vec<&str>>();
+        assert_eq!(most, vec!["^st", "ock", "sto", "lm$"]);
+        assert_eq!(stats.least_frequent[0].token, "lm$");
+        assert_eq!(stats.idf_max, 1.0);
+        assert!((stats.idf_min - (10.0_f64 / 8.0).log10()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn part_idf_for_frequently_empty_part() {
+        // 100 documents, the location is only filled in for 10 of them,
+        // "sto" is in 5 of those and "ma" in all 10
+        let mut part = VocabPart::new(VocabPartType::Ngram);
+        part.tokens.insert("sto".to_string(), (1, 5));
+        part.tokens.insert("ma".to_string(), (2, 10));
+        let global = calculate_idf(3, idf_total_docs(false, 100, 10), &part.tokens);
+        let per_part = calculate_idf(3, idf_total_docs(true, 100, 10), &part.tokens);
+        assert!((global[1] - 20.0_f64.log10()).abs() < 1e-9);
+        assert!((global[2] - 1.0).abs() < 1e-9);
+        // A token in every document with a location is not informative at all
+        assert!((per_part[1] - 2.0_f64.log10()).abs() < 1e-9);
+        assert_eq!(per_part[2], 0.0);
+    }
+
+    #[test]
+    fn part_idf_ignores_empty_values() {
+        let config = Config::from_args(&["-c", "build-vocab", "-s", "test", "-O", "part-idf"]);
+        let record = |id: &str, location: &str| Record {
+            id: id.to_string(),
+            source: "test".to_string(),
+            title: "Titel".to_string(),
+            author: String::new(),
+            location: location.to_string(),
+            year: "1950".to_string(),
+            last_year: String::new(),
+            date: String::new(),
+            publication_type: String::new(),
+        };
+        // The location is filled in for 2 of 10 documents, empty or only punctuation in the rest
+        let mut builder = VocabBuilder::new();
+        builder.add_record(&config, &record("1", "Stockholm"));
+        builder.add_record(&config, &record("2", "Lund"));
+        for n in 3..=10 {
+            builder.add_record(&config, &record(&n.to_string(), if n % 2 == 0 { "" } else { "[.]" }));
+        }
+        let vocab = builder.finish(&config);
+        let location = &vocab.vocab_parts["location"];
+        let idf = |token: &str| location.idf[location.tokens[token].0];
+        assert_eq!(location.tokens[EMPTY_VALUE_TOKEN].1, 8);
+        assert_eq!(idf(EMPTY_VALUE_TOKEN), 0.0);
+        assert!((idf("sto") - 2.0_f64.log10()).abs() < 1e-9);
+        assert!(location.idf.iter().all(|idf| *idf >= 0.0));
+    }
+}
@@ -1,10 +1,13 @@
-use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::Read;
+use std::path::PathBuf;
+use std::io::{BufRead, BufReader, Read};
 use zip::read::ZipArchive;
 use crate::matcher::JsonRecord;
-use crate::args::Config;
+use crate::args::{Config, DedupInput, JSON_SCHEMA_VERSION_AUTO};
+use crate::tokenizer;
+use rustc_hash::{FxHashMap, FxHashSet};
 use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
 use pest::Parser;
 use pest_derive::Parser;
 use pest::iterators::Pairs;
@@ -132,20 +135,164 @@ pub struct JsonEditionLoaderV2 {
     pub serial_titles: Vec<String>,
 }
 
-pub fn read_zip_file(config: &Config, file_path: &str, schema_version: i32) -> (String, Vec<(String, JsonRecord)>) {
-    let inputdata = read_input_to_btreemap(file_path);
-    if schema_version == 2 {
-        return convert_to_jsonarray_v2(config, inputdata);
-    } else {
-        return convert_to_jsonarray(inputdata);
+// Version 3 of the JSON input format, with lists of titles and authors and a language code.
+// Records are converted to JsonRecordLoaderV2 and from there handled as version 2 records:
+// all titles are joined with " " into one title (the first title is the main title, the rest are
+// variant titles that add their words to the vectors), all authors are joined with "; ",
+// which is where -O author-normalize splits a list of authors.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JsonRecordLoaderV3 {
+    #[serde(default)]
+    pub schema_version: Option<u32>,
+    #[serde(default)]
+    pub title: JsonMultiValueV3, // title in the vectors, joined with " "
+    #[serde(default)]
+    pub author: JsonMultiValueV3, // author in the vectors, joined with "; "
+    #[serde(default)]
+    pub language: Option<String>, // not used for matching
+    #[serde(default)]
+    pub publication_type: Option<String>, // not used for matching
+    #[serde(default)]
+    pub is_reference_card: bool, // not used for matching
+    pub editions: Vec<JsonEditionLoaderV3>, // Partially used. If there are multiple editions, it is treated as if there are multiple records
+    #[serde(default)]
+    pub invalid_json: bool, // if true, this record is invalid and should be skipped
+}
+
+// The editions are unchanged from version 2
+pub type JsonEditionLoaderV3 = JsonEditionLoaderV2;
+
+// A list of values, a single string is also accepted
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum JsonMultiValueV3 {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl Default for JsonMultiValueV3 {
+    fn default() -> Self {
+        JsonMultiValueV3::Multiple(Vec::new())
     }
 }
 
-fn read_input_to_btreemap(path: &str) -> BTreeMap<String, String> {
-    if is_directory(path) {
-        read_directory_to_btreemap(path)
-    } else {
-        read_zip_to_btreemap(path)
+impl JsonMultiValueV3 {
+    // The non-empty values joined with separator, None if there are none (reported as a missing field)
+    fn joined(&self, separator: &str) -> Option<String> {
+        let values = match self {
+            JsonMultiValueV3::Single(value) => vec![value.trim()],
+            JsonMultiValueV3::Multiple(values) => values.iter().map(|value| value.trim()).collect(),
+        };
+        let values = values.into_iter().filter(|value| !value.is_empty()).collect::<Vec<&str>>();
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.join(separator))
+        }
+    }
+}
+
+impl From<JsonRecordLoaderV3> for JsonRecordLoaderV2 {
+    fn from(record: JsonRecordLoaderV3) -> Self {
+        JsonRecordLoaderV2 {
+            schema_version: record.schema_version,
+            title: record.title.joined(" "),
+            author: record.author.joined("; "),
+            publication_type: record.publication_type,
+            is_reference_card: record.is_reference_card,
+            editions: record.editions,
+            invalid_json: record.invalid_json,
+        }
+    }
+}
+
+// Problem found while loading the input files, collected on MatchStatistics
+// and written to the -warnings.json file next to the report
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputWarning {
+    pub file: String,
+    pub kind: InputWarningKind,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputWarningKind {
+    SkippedFile, // Not a .json or .prompt file
+    UnparseableFilename, // Not of the form box_card.json
+    InvalidJson,
+    MissingField,
+    InvalidYear,
+    NotUtf8, // Decoded as Latin-1 instead
+    DuplicateCard, // Same card name as another file, see -O dedup-input
+}
+
+impl InputWarning {
+    fn new(file: &str, kind: InputWarningKind, message: String) -> Self {
+        InputWarning {
+            file: file.to_string(),
+            kind,
+            message,
+        }
+    }
+}
+
+// Content of an input file (from a ZIP file or a directory) as a string.
+// Files that are not valid UTF-8 are most likely older exports in a legacy encoding, they are
+// decoded as Latin-1 (every byte is the character with the same code point) with a warning,
+// instead of replacing the invalid bytes and failing later with a confusing JSON error.
+fn decode_content(filename: &str, bytes: Vec<u8>, warnings: &mut Vec<InputWarning>) -> String {
+    match String::from_utf8(bytes) {
+        Ok(content) => content,
+        Err(e) => {
+            let error = e.utf8_error();
+            warnings.push(InputWarning::new(filename, InputWarningKind::NotUtf8, format!("Not valid UTF-8 ({}), decoded as Latin-1", error)));
+            e.into_bytes().iter().map(|byte| *byte as char).collect()
+        }
+    }
+}
+
+// JSON Lines input (.jsonl), one JSON record per line instead of one file per record
+pub fn is_json_lines(path: &str) -> bool {
+    path.ends_with(".jsonl")
+}
+
+// Records and input warnings of each line of a JSON Lines file, read one line at a time.
+// Each line is converted like a JSON file (one record per edition), where the card name
+// is the "id" field of the record, or line_<line number> if it has none, with .json appended.
+pub fn read_json_lines<'a>(config: &'a Config, path: &str, schema_version: i32) -> impl Iterator<Item = (Vec<(String, JsonRecord)>, Vec<InputWarning>)> + 'a {
+    let file = File::open(path).unwrap_or_else(|e| {
+        eprintln!("Failed to open JSON Lines file {}: {}", path, e);
+        std::process::exit(1);
+    });
+    let path = path.to_string();
+    BufReader::new(file).lines().enumerate().filter_map(move |(index, line)| {
+        let line = line.unwrap_or_else(|e| {
+            eprintln!("Failed to read line {} of {}: {}", index + 1, path, e);
+            std::process::exit(1);
+        });
+        if line.trim().is_empty() {
+            return None;
+        }
+        let card = json_line_card_name(&line, index + 1);
+        let mut warnings = vec![];
+        let records = convert_json_file(config, &card, &line, schema_version, &mut warnings);
+        Some((records, warnings))
+    })
+}
+
+fn json_line_card_name(line: &str, line_number: usize) -> String {
+    let id = match serde_json::from_str::<serde_json::Value>(line) {
+        Ok(value) => match &value["id"] {
+            serde_json::Value::String(id) if !id.trim().is_empty() => Some(id.trim().to_string()),
+            serde_json::Value::Number(id) => Some(id.to_string()),
+            _ => None,
+        },
+        Err(_) => None,
+    };
+    match id {
+        Some(id) => format!("{}.json", id),
+        None => format!("line_{}.json", line_number),
     }
 }
 
@@ -158,230 +305,409 @@ pub fn is_directory(path: &str) -> bool {
     false
 }
 
-fn read_zip_to_btreemap(file_path: &str) -> BTreeMap<String, String> {
-    // Open the ZIP file
-    let file = File::open(file_path).expect("Failed to open file");
-    let mut archive = ZipArchive::new(file).expect("Failed to open ZIP file");
-
-    // Initialize the BTreeMap to store filenames and their contents
-    let mut file_contents_map = BTreeMap::new();
+// A ZIP file or a directory of JSON files, read one file at a time while matching so that
+// only the content of one card is in memory, however many files there are.
+// The file names are read when the input is opened and sorted, since the files are matched
+// in name order. The prompt (a .prompt file) is read then as well, since the report needs it.
+pub struct JsonFileInput {
+    files: JsonFiles,
+    names: Vec<String>,
+    prompt: String,
+}
 
-    // Iterate through each file in the ZIP archive
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i).expect("Failed to get file from ZIP archive");
-        if file.is_file() {
-            // Read the file's content into a buffer
-            let mut buffer = Vec::new();
-            file.read_to_end(&mut buffer).expect("Failed to read file");
-            let buffer = String::from_utf8_lossy(&buffer).to_string();
+enum JsonFiles {
+    Zip(ZipArchive<File>, FxHashMap<String, usize>), // Index in the archive of each name
+    Directory(PathBuf),
+}
 
-            // Insert the filename and its content into the BTreeMap
-            file_contents_map.insert(file.name().to_string(), buffer);
+impl JsonFileInput {
+    pub fn open(path: &str, warnings: &mut Vec<InputWarning>) -> JsonFileInput {
+        let (files, mut names) = if is_directory(path) {
+            (JsonFiles::Directory(PathBuf::from(path)), directory_file_names(path))
+        } else {
+            let file = File::open(path).expect("Failed to open file");
+            let archive = ZipArchive::new(file).expect("Failed to open ZIP file");
+            let indexes = zip_file_indexes(&archive);
+            let names = indexes.keys().cloned().collect();
+            (JsonFiles::Zip(archive, indexes), names)
+        };
+        names.sort();
+        // Skip any path that starts with __MACOSX or .DS_Store, and take the (last) prompt file out
+        names.retain(|name| !name.starts_with("__MACOSX") && !name.starts_with(".DS_Store"));
+        let mut input = JsonFileInput {
+            files,
+            names: vec![],
+            prompt: String::new(),
+        };
+        for name in names.iter().filter(|name| name.ends_with(".prompt")) {
+            input.prompt = input.read(name, warnings);
         }
+        names.retain(|name| !name.ends_with(".prompt"));
+        input.names = names;
+        input
     }
 
-    file_contents_map
-}
+    pub fn prompt(&self) -> &str {
+        &self.prompt
+    }
 
-// Read all files (no subdirectories) from a directory into a BTreeMap
-fn read_directory_to_btreemap(dir_path: &str) -> BTreeMap<String, String> {
-    let mut file_contents_map = BTreeMap::new();
-    let entries = std::fs::read_dir(dir_path).expect("Failed to read directory");
-    for entry in entries {
-        let entry = entry.expect("Failed to get directory entry");
-        let path = entry.path();
-        if path.is_file() {
-            let filename = path.file_name().unwrap().to_string_lossy().to_string();
-            // Only parse .json and .prompt files
-            if !filename.ends_with(".json") && !filename.ends_with(".prompt") {
-                continue;
+    // Number of files given by records, one batch each
+    pub fn number_of_files(&self) -> usize {
+        self.names.len()
+    }
+
+    fn read(&mut self, name: &str, warnings: &mut Vec<InputWarning>) -> String {
+        let buffer = match &mut self.files {
+            JsonFiles::Zip(archive, indexes) => {
+                let mut file = archive.by_index(indexes[name]).expect("Failed to get file from ZIP archive");
+                let mut buffer = Vec::new();
+                file.read_to_end(&mut buffer).expect("Failed to read file");
+                buffer
+            },
+            JsonFiles::Directory(dir_path) => std::fs::read(dir_path.join(name)).expect("Failed to read file"),
+        };
+        decode_content(name, buffer, warnings)
+    }
+
+    // Records and input warnings of each file, in name order. Every JSON file is converted according to
+    // schema_version (or its own "schema_version" field when schema_version is auto), other files are skipped.
+    pub fn records<'a>(mut self, config: &'a Config, schema_version: i32) -> impl Iterator<Item = (Vec<(String, JsonRecord)>, Vec<InputWarning>)> + 'a {
+        let names = std::mem::take(&mut self.names);
+        let duplicates = duplicate_basenames(names.iter());
+        // Checked before any record is matched, so that no output is written
+        if config.options.dedup_input == DedupInput::Error {
+            if let Some((filename, first)) = self.first_duplicate_card(&names, schema_version, &duplicates) {
+                eprintln!("Card name of {} is also used by {} in the input", filename, first);
+                eprintln!("Use -O dedup-input=first or -O dedup-input=keep-path");
+                std::process::exit(1);
             }
-            let content = std::fs::read_to_string(&path).expect("Failed to read file");
-            file_contents_map.insert(filename, content);
         }
+        let mut seen_cards = FxHashSet::default();
+        names.into_iter().map(move |filename| {
+            let mut warnings = vec![];
+            // Only handle files with the .json extension
+            if !filename.ends_with(".json") {
+                warnings.push(InputWarning::new(&filename, InputWarningKind::SkippedFile, "Not a .json or .prompt file".to_string()));
+                return (vec![], warnings);
+            }
+            let content = self.read(&filename, &mut warnings);
+            let records = convert_input_file(config, &filename, &content, schema_version, &duplicates, &mut seen_cards, &mut warnings);
+            (records, warnings)
+        })
     }
-    file_contents_map
-}
 
-// Return (systemprompt, Vec<JsonRecord>)
-fn convert_to_jsonarray(inputdata: BTreeMap<String, String>) -> (String, Vec<(String, JsonRecord)>) {
-    let mut jsonarray = Vec::new();
-    let mut systemprompt = String::new();
-    for (filename, content) in inputdata {
-        // First check if the file is the system prompt (a file with the extension .prompt)
-        if filename.ends_with(".prompt") {
-            systemprompt = content;
-            continue;
-        }
-        // Only handle files with the .json extension
-        if !filename.ends_with(".json") {
-            continue;
+    // The first file (and the earlier file) that gives the same card name as an earlier file, for -O dedup-input=error.
+    // Only version 2 and later use the file name without directory as card name, so with json-schema-version=auto
+    // the files with a duplicate file name (usually few) are read to find their version.
+    fn first_duplicate_card(&mut self, names: &[String], schema_version: i32, duplicates: &FxHashSet<String>) -> Option<(String, String)> {
+        if schema_version == 1 {
+            return None;
         }
-        // Skip any path that starts with __MACOSX
-        if filename.starts_with("__MACOSX") {
-            continue;
+        let mut seen_cards: FxHashMap<&str, &str> = FxHashMap::default();
+        for filename in names.iter().filter(|filename| filename.ends_with(".json")) {
+            let basename = filename.split('/').next_back().unwrap_or(filename);
+            if !duplicates.contains(basename) {
+                continue;
+            }
+            if schema_version == JSON_SCHEMA_VERSION_AUTO && detect_schema_version(&self.read(filename, &mut vec![])) == Ok(1) {
+                continue;
+            }
+            if let Some(first) = seen_cards.insert(basename, filename) {
+                return Some((filename.clone(), first.to_string()));
+            }
         }
-        // Skip any path that starts with .DS_Store
-        if filename.starts_with(".DS_Store") {
-            continue;
+        None
+    }
+}
+
+// Names of the files (not directories) in a ZIP file, with their index in the archive
+fn zip_file_indexes(archive: &ZipArchive<File>) -> FxHashMap<String, usize> {
+    (0..archive.len())
+        .filter(|index| archive.name_for_index(*index).is_some_and(|name| !name.ends_with('/')))
+        .filter_map(|index| archive.name_for_index(index).map(|name| (name.to_string(), index)))
+        .collect()
+}
+
+// Names of the .json and .prompt files (no subdirectories) in a directory
+fn directory_file_names(dir_path: &str) -> Vec<String> {
+    let entries = std::fs::read_dir(dir_path).expect("Failed to read directory");
+    entries.filter_map(|entry| {
+        let path = entry.expect("Failed to get directory entry").path();
+        if !path.is_file() {
+            return None;
         }
-        let record: JsonRecordLoader = match serde_json::from_str(&content) {
-            Ok(record) => record,
-            Err(e) => {
-                // Try to load as a JsonRecordArrayLoader and if there is one and only one record,
-                // use that record, otherwise panic for every other reason.
-                if let Ok(mut json_array) = serde_json::from_str::<Vec<JsonRecordLoader>>(&content) {
-                    if json_array.len() == 1 {
-                        json_array.pop().unwrap() // At this point we know there is exactly one record
-                    } else {
-                        panic!("Expected one record in JSON array, found {}", json_array.len());
-                    }
-                } else {
-                    panic!("Failed to parse JSON file {}: {}", filename, e);
+        let filename = path.file_name().unwrap().to_string_lossy().to_string();
+        // Only parse .json and .prompt files
+        (filename.ends_with(".json") || filename.ends_with(".prompt")).then_some(filename)
+    }).collect()
+}
+
+// The records of one JSON file of a ZIP file or directory.
+// The card name of version 2 and later is the file name without directory, which may be shared by
+// several files (duplicates), those are handled according to -O dedup-input
+// (dedup-input=error is checked by JsonFileInput::records before any file is converted).
+fn convert_input_file(config: &Config, filename: &str, content: &str, schema_version: i32, duplicates: &FxHashSet<String>, seen_cards: &mut FxHashSet<String>, warnings: &mut Vec<InputWarning>) -> Vec<(String, JsonRecord)> {
+    // The output splits the card name into box and card at the first "_"
+    let basename = filename.split('/').next_back().unwrap_or(filename);
+    if !basename.contains('_') {
+        warnings.push(InputWarning::new(filename, InputWarningKind::UnparseableFilename, "Card filename is not of the form box_card.json".to_string()));
+    }
+    let mut records = convert_json_file(config, filename, content, schema_version, warnings);
+    let card = records.first().map(|(card, _)| card.clone()).unwrap_or_default();
+    if duplicates.contains(basename) && card == basename {
+        let first = seen_cards.insert(card.clone());
+        match config.options.dedup_input {
+            DedupInput::First if !first => {
+                warnings.push(InputWarning::new(filename, InputWarningKind::DuplicateCard, format!("Card name {} is used by an earlier file, skipped", card)));
+                return vec![];
+            },
+            DedupInput::KeepPath => {
+                warnings.push(InputWarning::new(filename, InputWarningKind::DuplicateCard, format!("Card name {} is used by more than one file, using {} instead", card, filename)));
+                for record in records.iter_mut() {
+                    record.0 = filename.to_string();
                 }
-            }
-        };
-        for (edition_idx, edition) in record.editions.iter().enumerate() {
-            let jsonrecord = JsonRecord {
-                edition: edition_idx,
-                title: record.title.clone().unwrap_or_default(),
-                author: record.author.clone().unwrap_or_default(),
-                location: edition.place_of_publication.clone().unwrap_or_default(),
-                year: edition.year_of_publication.clone().unwrap_or_default().to_string(),
-                publication_type: record.publication_type.clone().unwrap_or_default(),
-                allowed_years: Vec::new(), // Not used in version 1
-            };
-            jsonarray.push((filename.clone(), jsonrecord));
-        }
-        // Special handling for case where there are no editions. Here we set the edition to 9999999
-        if record.editions.is_empty() {
-            let jsonrecord = JsonRecord {
-                edition: 9999999,
-                title: record.title.clone().unwrap_or_default(),
-                author: record.author.clone().unwrap_or_default(),
-                location: String::new(),
-                year: String::new(),
-                publication_type: record.publication_type.clone().unwrap_or_default(),
-                allowed_years: Vec::new(), // Not used in version 1
-            };
-            jsonarray.push((filename.clone(), jsonrecord));
+            },
+            _ => {
+                warnings.push(InputWarning::new(filename, InputWarningKind::DuplicateCard, format!("Card name {} is used by more than one file", card)));
+            },
         }
     }
-    (systemprompt, jsonarray)
+    records
 }
 
-fn convert_to_jsonarray_v2(config: &Config, inputdata: BTreeMap<String, String>) -> (String, Vec<(String, JsonRecord)>) {
+// File names (without directory) of the .json files that occur in more than one directory
+fn duplicate_basenames<'a>(filenames: impl Iterator<Item = &'a String>) -> FxHashSet<String> {
+    let mut counts: FxHashMap<&str, usize> = FxHashMap::default();
+    for filename in filenames.filter(|filename| filename.ends_with(".json") && !filename.starts_with("__MACOSX")) {
+        *counts.entry(filename.split('/').next_back().unwrap_or(filename)).or_insert(0) += 1;
+    }
+    counts.into_iter().filter(|(_, count)| *count > 1).map(|(basename, _)| basename.to_string()).collect()
+}
+
+// Convert the content of one JSON file to one record per edition,
+// according to schema_version (or the file's own "schema_version" field when schema_version is auto)
+pub fn convert_json_file(config: &Config, filename: &str, content: &str, schema_version: i32, warnings: &mut Vec<InputWarning>) -> Vec<(String, JsonRecord)> {
     let mut jsonarray = Vec::new();
-    let mut systemprompt = String::new();
-    for (filename, content) in inputdata {
-        // First check if the file is the system prompt (a file with the extension .prompt)
-        if filename.ends_with(".prompt") {
-            systemprompt = content;
-            continue;
-        }
-        // Only handle files with the .json extension
-        if !filename.ends_with(".json") {
-            continue;
-        }
-        // Skip any path that starts with __MACOSX
-        if filename.starts_with("__MACOSX") {
-            continue;
+    let record_schema_version = if schema_version == JSON_SCHEMA_VERSION_AUTO {
+        match detect_schema_version(content) {
+            Ok(version) => version,
+            Err(message) => {
+                warnings.push(InputWarning::new(filename, InputWarningKind::InvalidJson, message));
+                push_records_v2(config, filename, create_invalid_json_loader_record_v2(), 2, &mut jsonarray, warnings);
+                return jsonarray;
+            }
         }
-        // Skip any path that starts with .DS_Store
-        if filename.starts_with(".DS_Store") {
-            continue;
+    } else {
+        schema_version
+    };
+    match record_schema_version {
+        2 => convert_record_v2(config, filename, content, &mut jsonarray, warnings),
+        3 => convert_record_v3(config, filename, content, &mut jsonarray, warnings),
+        _ => convert_record_v1(config, filename, content, &mut jsonarray, warnings),
+    }
+    // With -O clean-year the year is cleaned once here, so that every use of it (the year and all parts, force-year)
+    // sees the same year as the source records, which are cleaned when they are fetched
+    if config.options.clean_year {
+        for (_, record) in jsonarray.iter_mut() {
+            record.year = tokenizer::clean_year(&record.year);
         }
-        let record: JsonRecordLoaderV2 = match serde_json::from_str(&content) {
-            Ok(record) => record,
-            Err(e) => {
-                // Try to load as a JsonRecordArrayLoader and if there is one and only one record,
-                // use that record, otherwise panic for every other reason.
-                if let Ok(mut json_array) = serde_json::from_str::<Vec<JsonRecordLoaderV2>>(&content) {
-                    if json_array.len() == 1 {
-                        json_array.pop().unwrap() // At this point we know there is exactly one record
-                    } else {
-                        if config.verbose {
-                            println!("Expected one record in JSON array, found {}", json_array.len());
-                        }
-                        create_invalid_json_loader_record_v2()
-                    }
+    }
+    jsonarray
+}
+
+// Schema version of a single JSON file from its "schema_version" field (a number or a string with a number), 1 when absent.
+// Files that cannot be parsed at all are treated as version 2 so that they are reported as invalid JSON.
+// Any other schema_version is an error.
+fn detect_schema_version(content: &str) -> Result<i32, String> {
+    let value: serde_json::Value = match serde_json::from_str(content) {
+        Ok(value) => value,
+        Err(_) => return Ok(2),
+    };
+    // A JSON array with a single record is allowed, same as in the converters
+    let record = match &value {
+        serde_json::Value::Array(array) if array.len() == 1 => &array[0],
+        _ => &value,
+    };
+    match &record["schema_version"] {
+        serde_json::Value::Null => Some(1),
+        serde_json::Value::Number(number) => number.as_i64().and_then(|version| i32::try_from(version).ok()),
+        serde_json::Value::String(string) => string.trim().parse::<i32>().ok(),
+        _ => None,
+    }.ok_or_else(|| format!("Invalid schema_version {}", record["schema_version"]))
+}
+
+fn convert_record_v1(config: &Config, filename: &str, content: &str, jsonarray: &mut Vec<(String, JsonRecord)>, warnings: &mut Vec<InputWarning>) {
+    let Some(record) = parse_record::<JsonRecordLoader>(config, filename, content, warnings) else {
+        // Same as the invalid JSON record of version 2, reported with edition 9999998
+        let jsonrecord = JsonRecord {
+            edition: 9999998,
+            title: "INVALID JSON".to_string(),
+            author: "INVALID JSON".to_string(),
+            location: String::new(),
+            year: String::new(),
+            publication_type: "INVALID JSON".to_string(),
+            allowed_years: Vec::new(), // Not used in version 1
+            schema_version: 1,
+        };
+        jsonarray.push((filename.to_string(), jsonrecord));
+        return;
+    };
+    missing_field_warnings(filename, &record.title, &record.author, warnings);
+    for (edition_idx, edition) in record.editions.iter().enumerate() {
+        let jsonrecord = JsonRecord {
+            edition: edition_idx,
+            title: record.title.clone().unwrap_or_default(),
+            author: record.author.clone().unwrap_or_default(),
+            location: edition.place_of_publication.clone().unwrap_or_default(),
+            year: edition.year_of_publication.unwrap_or_default().to_string(),
+            publication_type: record.publication_type.clone().unwrap_or_default(),
+            allowed_years: Vec::new(), // Not used in version 1
+            schema_version: 1,
+        };
+        jsonarray.push((filename.to_string(), jsonrecord));
+    }
+    // Special handling for case where there are no editions. Here we set the edition to 9999999
+    if record.editions.is_empty() {
+        let jsonrecord = JsonRecord {
+            edition: 9999999,
+            title: record.title.clone().unwrap_or_default(),
+            author: record.author.clone().unwrap_or_default(),
+            location: String::new(),
+            year: String::new(),
+            publication_type: record.publication_type.clone().unwrap_or_default(),
+            allowed_years: Vec::new(), // Not used in version 1
+            schema_version: 1,
+        };
+        jsonarray.push((filename.to_string(), jsonrecord));
+    }
+}
+
+fn convert_record_v2(config: &Config, filename: &str, content: &str, jsonarray: &mut Vec<(String, JsonRecord)>, warnings: &mut Vec<InputWarning>) {
+    let record = parse_record::<JsonRecordLoaderV2>(config, filename, content, warnings)
+        .unwrap_or_else(create_invalid_json_loader_record_v2);
+    push_records_v2(config, filename, record, 2, jsonarray, warnings);
+}
+
+fn convert_record_v3(config: &Config, filename: &str, content: &str, jsonarray: &mut Vec<(String, JsonRecord)>, warnings: &mut Vec<InputWarning>) {
+    let record = parse_record::<JsonRecordLoaderV3>(config, filename, content, warnings)
+        .map(JsonRecordLoaderV2::from)
+        .unwrap_or_else(create_invalid_json_loader_record_v2);
+    push_records_v2(config, filename, record, 3, jsonarray, warnings);
+}
+
+// Parse one record of any version, a JSON array with exactly one record is also accepted.
+// Anything else is reported as invalid JSON and None is returned.
+fn parse_record<T: DeserializeOwned>(config: &Config, filename: &str, content: &str, warnings: &mut Vec<InputWarning>) -> Option<T> {
+    match serde_json::from_str(content) {
+        Ok(record) => Some(record),
+        Err(e) => {
+            // Try to load as a JsonRecordArrayLoader and if there is one and only one record,
+            // use that record, otherwise it is invalid.
+            if let Ok(mut json_array) = serde_json::from_str::<Vec<T>>(content) {
+                if json_array.len() == 1 {
+                    json_array.pop() // At this point we know there is exactly one record
                 } else {
                     if config.verbose {
-                        println!("Failed to parse JSON file {}: {}", filename, e);
+                        eprintln!("Expected one record in JSON array, found {}", json_array.len());
                     }
-                    create_invalid_json_loader_record_v2()
+                    warnings.push(InputWarning::new(filename, InputWarningKind::InvalidJson, format!("Expected one record in JSON array, found {}", json_array.len())));
+                    None
                 }
+            } else {
+                if config.verbose {
+                    eprintln!("Failed to parse JSON file {}: {}", filename, e);
+                }
+                warnings.push(InputWarning::new(filename, InputWarningKind::InvalidJson, e.to_string()));
+                None
             }
+        }
+    }
+}
+
+// One JsonRecord per edition of a version 2 record (or a version 3 record converted to version 2)
+fn push_records_v2(config: &Config, filename: &str, record: JsonRecordLoaderV2, schema_version: i32, jsonarray: &mut Vec<(String, JsonRecord)>, warnings: &mut Vec<InputWarning>) {
+    let publication_type_string = match (&record.is_reference_card, &record.publication_type) {
+        (true, _) => "cross-reference".to_string(),
+        (false, Some(pt)) => pt.to_string(),
+        (false, None) => "".to_string(),
+    };
+    let basename = filename.split('/').next_back().unwrap_or(filename).to_string();
+    if !record.invalid_json {
+        missing_field_warnings(filename, &record.title, &record.author, warnings);
+    }
+    for (edition_idx, edition) in record.editions.iter().enumerate() {
+        let edition_years = extract_years(config, edition, filename, warnings);
+        let lowest_non_zero_year = match &edition_years {
+            JsonRecordEditionLoaderYearV2::Single(y) => *y,
+            JsonRecordEditionLoaderYearV2::Multiple(ys) => ys.iter().filter(|y| **y > 0).min().cloned().unwrap_or(0),
+            JsonRecordEditionLoaderYearV2::None => 0,
         };
-        let publication_type_string = match (&record.is_reference_card, &record.publication_type) {
-            (true, _) => "cross-reference".to_string(),
-            (false, Some(pt)) => pt.to_string(),
-            (false, None) => "".to_string(),
-        };
-        let basename = filename.split('/').last().unwrap_or(&filename).to_string();
-        for (edition_idx, edition) in record.editions.iter().enumerate() {
-            let edition_years = extract_years(config, edition);
-            let lowest_non_zero_year = match &edition_years {
-                JsonRecordEditionLoaderYearV2::Single(y) => *y,
-                JsonRecordEditionLoaderYearV2::Multiple(ys) => ys.iter().filter(|y| **y > 0).min().cloned().unwrap_or(0),
-                JsonRecordEditionLoaderYearV2::None => 0,
-            };
-            let year_string = if lowest_non_zero_year > 0 { lowest_non_zero_year.to_string() } else { String::new() };
-            let mut title = record.title.clone().unwrap_or_default();
-            // If option "add_serial_to_title" is set, append "serial_titles" field (array joined with a space) to the title joined with a space
-            if config.options.add_serial_to_title {
-                let serial_titles = edition.serial_titles.join(" ").trim().to_string();
-                if !serial_titles.is_empty() {
-                    title = format!("{} {}", title, serial_titles);
-                }
+        let year_string = if lowest_non_zero_year > 0 { lowest_non_zero_year.to_string() } else { String::new() };
+        let mut title = record.title.clone().unwrap_or_default();
+        // If option "add_serial_to_title" is set, append "serial_titles" field (array joined with a space) to the title joined with a space
+        if config.options.add_serial_to_title {
+            let serial_titles = edition.serial_titles.join(" ").trim().to_string();
+            if !serial_titles.is_empty() {
+                title = format!("{} {}", title, serial_titles);
             }
-            // If option "add_edition_to_title" is set, append "edition_statement" field (Option<String>) to the title joined with a space
-            if config.options.add_edition_to_title {
-                if let Some(edition_str) = &edition.edition_statement {
-                    if !edition_str.trim().is_empty() {
-                        title = format!("{} {}", title, edition_str);
-                    }
+        }
+        // If option "add_edition_to_title" is set, append "edition_statement" field (Option<String>) to the title joined with a space
+        if config.options.add_edition_to_title {
+            if let Some(edition_str) = &edition.edition_statement {
+                if !edition_str.trim().is_empty() {
+                    title = format!("{} {}", title, edition_str);
                 }
             }
-
-            let jsonrecord = JsonRecord {
-                edition: edition_idx,
-                title: title,
-                author: record.author.clone().unwrap_or_default(),
-                location: edition.place_of_publication.iter().map(ToString::to_string).collect::<Vec<String>>().join(" "),
-                year: year_string,
-                publication_type: publication_type_string.clone(),
-                allowed_years: (&edition_years).into(),
-            };
-            jsonarray.push((basename.clone(), jsonrecord));
-        }
-        // Special handling for case where there are no editions. Here we set the edition to 9999999
-        if record.editions.is_empty() && !record.invalid_json {
-            let jsonrecord = JsonRecord {
-                edition: 9999999,
-                title: record.title.clone().unwrap_or_default(),
-                author: record.author.clone().unwrap_or_default(),
-                location: String::new(),
-                year: String::new(),
-                publication_type: publication_type_string.clone(),
-                allowed_years: Vec::new(),
-            };
-            jsonarray.push((basename.clone(), jsonrecord));
         }
-        if record.invalid_json {
-            let jsonrecord = JsonRecord {
-                edition: 9999998,
-                title: record.title.clone().unwrap_or_default(),
-                author: record.author.clone().unwrap_or_default(),
-                location: String::new(),
-                year: String::new(),
-                publication_type: publication_type_string.clone(),
-                allowed_years: Vec::new(),
-            };
-            jsonarray.push((basename.clone(), jsonrecord));
-        }            
-    }
-    (systemprompt, jsonarray)
+
+        let jsonrecord = JsonRecord {
+            edition: edition_idx,
+            title,
+            author: record.author.clone().unwrap_or_default(),
+            location: edition.place_of_publication.iter().map(ToString::to_string).collect::<Vec<String>>().join(" "),
+            year: year_string,
+            publication_type: publication_type_string.clone(),
+            allowed_years: (&edition_years).into(),
+            schema_version,
+        };
+        jsonarray.push((basename.clone(), jsonrecord));
+    }
+    // Special handling for case where there are no editions. Here we set the edition to 9999999
+    if record.editions.is_empty() && !record.invalid_json {
+        let jsonrecord = JsonRecord {
+            edition: 9999999,
+            title: record.title.clone().unwrap_or_default(),
+            author: record.author.clone().unwrap_or_default(),
+            location: String::new(),
+            year: String::new(),
+            publication_type: publication_type_string.clone(),
+            allowed_years: Vec::new(),
+            schema_version,
+        };
+        jsonarray.push((basename.clone(), jsonrecord));
+    }
+    if record.invalid_json {
+        let jsonrecord = JsonRecord {
+            edition: 9999998,
+            title: record.title.clone().unwrap_or_default(),
+            author: record.author.clone().unwrap_or_default(),
+            location: String::new(),
+            year: String::new(),
+            publication_type: publication_type_string.clone(),
+            allowed_years: Vec::new(),
+            schema_version,
+        };
+        jsonarray.push((basename.clone(), jsonrecord));
+    }
+}
+
+fn missing_field_warnings(filename: &str, title: &Option<String>, author: &Option<String>, warnings: &mut Vec<InputWarning>) {
+    if title.is_none() {
+        warnings.push(InputWarning::new(filename, InputWarningKind::MissingField, "Missing title".to_string()));
+    }
+    if author.is_none() {
+        warnings.push(InputWarning::new(filename, InputWarningKind::MissingField, "Missing author".to_string()));
+    }
 }
 
 fn create_invalid_json_loader_record_v2() -> JsonRecordLoaderV2 {
@@ -396,11 +722,14 @@ fn create_invalid_json_loader_record_v2() -> JsonRecordLoaderV2 {
     }
 }
 
-fn extract_years(config: &Config, edition: &JsonEditionLoaderV2) -> JsonRecordEditionLoaderYearV2 {
+fn extract_years(config: &Config, edition: &JsonEditionLoaderV2, filename: &str, warnings: &mut Vec<InputWarning>) -> JsonRecordEditionLoaderYearV2 {
     if config.options.parse_year_ranges {
         if let Some(year_string) = &edition.year_of_publication_compact_string {
             match parse_year_string(year_string) {
                 Ok(years) => {
+                    if config.verbose {
+                        eprintln!("Parsed year string \"{}\" as {:?}", year_string, years);
+                    }
                     if config.options.use_first_parsed_year {
                         if let Some(first_year) = first_year(&years) {
                             return JsonRecordEditionLoaderYearV2::Single(first_year);
@@ -411,7 +740,11 @@ fn extract_years(config: &Config, edition: &JsonEditionLoaderV2) -> JsonRecordEd
                         return JsonRecordEditionLoaderYearV2::Multiple(years);
                     }
                 }
-                Err(_) => {
+                Err(e) => {
+                    if config.verbose {
+                        eprintln!("Failed to parse year string \"{}\" ({}), using yearOfPublication", year_string, e);
+                    }
+                    warnings.push(InputWarning::new(filename, InputWarningKind::InvalidYear, format!("Failed to parse year string \"{}\" ({}), using yearOfPublication", year_string, e)));
                     return edition.year_of_publication.clone();
                 }
             }
@@ -423,43 +756,229 @@ fn extract_years(config: &Config, edition: &JsonEditionLoaderV2) -> JsonRecordEd
     }
 }
 
+// Longest span of years allowed in a single range, longer ranges are treated as malformed
+const MAX_YEAR_SPAN: u32 = 200;
+
+#[derive(Debug)]
+pub enum YearParseError {
+    Syntax(Box<pest::error::Error<Rule>>),
+    InvalidYear(String), // Matched by the grammar, but not a valid number
+    SpanTooLong(u32, u32), // (start_year, end_year) of a range longer than MAX_YEAR_SPAN
+}
+
+impl std::fmt::Display for YearParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            YearParseError::Syntax(error) => write!(f, "syntax error: {}", error.variant.message()),
+            YearParseError::InvalidYear(year) => write!(f, "invalid year: {}", year),
+            YearParseError::SpanTooLong(start_year, end_year) => write!(f, "range {}-{} is longer than {} years", start_year, end_year, MAX_YEAR_SPAN),
+        }
+    }
+}
+
+impl From<pest::error::Error<Rule>> for YearParseError {
+    fn from(error: pest::error::Error<Rule>) -> Self {
+        YearParseError::Syntax(Box::new(error))
+    }
+}
+
 // Parse year string using YearParser.
 // It will return a vec of u32 years from strings of style "1949", "1949-", "1949-1951", and comma-separated combinations of these, e.g. "1949, 1951-1954, 1956-"
 // That example will return 1949, 1951, 1952, 1953, 1954, 1956
-fn parse_year_string(year_string: &str) -> Result<Vec<u32>, pest::error::Error<Rule>> {
-    let pairs = YearParser::parse(Rule::main, &year_string)?;
+// Reversed ranges (e.g. "1954-1951") are skipped, ranges spanning more than MAX_YEAR_SPAN years are an error.
+fn parse_year_string(year_string: &str) -> Result<Vec<u32>, YearParseError> {
+    let pairs = YearParser::parse(Rule::main, year_string)?;
     let mut years = Vec::new();
-    create_year_array(pairs, &mut years);
+    create_year_array(pairs, &mut years)?;
     Ok(years)
 }
 
-fn create_year_array(pairs: Pairs<Rule>, years: &mut Vec<u32>) {
+fn parse_year_pair(pair: Option<pest::iterators::Pair<Rule>>) -> Result<u32, YearParseError> {
+    let year_str = pair.map(|pair| pair.as_str()).unwrap_or_default();
+    year_str.parse::<u32>().map_err(|_| YearParseError::InvalidYear(year_str.to_string()))
+}
+
+fn create_year_array(pairs: Pairs<Rule>, years: &mut Vec<u32>) -> Result<(), YearParseError> {
     for pair in pairs {
         match pair.as_rule() {
             Rule::year => {
-                let year_int = pair.as_str().parse::<u32>().unwrap();
+                let year_int = parse_year_pair(Some(pair))?;
                 years.push(year_int);
             }
             Rule::year_range => {
                 let mut inner_pairs = pair.into_inner();
-                let start_year = inner_pairs.next().unwrap().as_str().parse::<i32>().unwrap();
-                let end_year = inner_pairs.next().unwrap().as_str().parse::<i32>().unwrap();
-                for year in start_year..=end_year {
-                    years.push(year as u32);
+                let start_year = parse_year_pair(inner_pairs.next())?;
+                let end_year = parse_year_pair(inner_pairs.next())?;
+                if end_year < start_year {
+                    continue; // Reversed range, skip it
+                }
+                if end_year - start_year > MAX_YEAR_SPAN {
+                    return Err(YearParseError::SpanTooLong(start_year, end_year));
                 }
+                years.extend(start_year..=end_year);
             }
             // Special case, only use start_year as a single year
             Rule::year_range_open => {
-                let start_year = pair.into_inner().next().unwrap().as_str().parse::<i32>().unwrap();
-                years.push(start_year as u32);
+                let start_year = parse_year_pair(pair.into_inner().next())?;
+                years.push(start_year);
             }
             _ => {
-                create_year_array(pair.into_inner(), years);
+                create_year_array(pair.into_inner(), years)?;
             }
         }
     }
+    Ok(())
 }
 
 fn first_year(year_array: &[u32]) -> Option<u32> {
     year_array.iter().cloned().min()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{convert_json_file, decode_content, detect_schema_version, duplicate_basenames, json_line_card_name, parse_year_string, InputWarningKind, JsonFileInput, JsonRecordLoaderV2, JsonRecordLoaderV3, YearParseError, JSON_SCHEMA_VERSION_AUTO};
+    use crate::args::Config;
+    use std::io::Write;
+
+    #[test]
+    fn schema_version_detection() {
+        assert_eq!(detect_schema_version(r#"{"schema_version": 2, "editions": []}"#), Ok(2));
+        assert_eq!(detect_schema_version(r#"[{"schema_version": 2, "editions": []}]"#), Ok(2));
+        assert_eq!(detect_schema_version(r#"{"schema_version": "2", "editions": []}"#), Ok(2));
+        assert_eq!(detect_schema_version(r#"{"title": "A title", "editions": []}"#), Ok(1));
+        assert_eq!(detect_schema_version("not json"), Ok(2));
+        assert_eq!(detect_schema_version(r#"{"schema_version": "two"}"#), Err(r#"Invalid schema_version "two""#.to_string()));
+        assert_eq!(detect_schema_version(r#"{"schema_version": 2.5}"#), Err("Invalid schema_version 2.5".to_string()));
+    }
+
+    #[test]
+    fn v3_titles_and_authors_are_joined() {
+        let content = r#"{"schema_version": 3, "title": ["Röda rummet", "", "The red room"], "author": ["Strindberg, August", "Lindgren, Astrid"], "language": "swe", "editions": []}"#;
+        let record = JsonRecordLoaderV2::from(serde_json::from_str::<JsonRecordLoaderV3>(content).unwrap());
+        assert_eq!(record.title.as_deref(), Some("Röda rummet The red room"));
+        assert_eq!(record.author.as_deref(), Some("Strindberg, August; Lindgren, Astrid"));
+        // A single string is accepted, an empty list is a missing field
+        let content = r#"{"title": "Röda rummet", "author": [], "editions": []}"#;
+        let record = JsonRecordLoaderV2::from(serde_json::from_str::<JsonRecordLoaderV3>(content).unwrap());
+        assert_eq!(record.title.as_deref(), Some("Röda rummet"));
+        assert_eq!(record.author, None);
+    }
+
+    #[test]
+    fn duplicate_basenames_across_directories() {
+        let filenames = ["box1/001_1.json", "box2/001_1.json", "box2/001_2.json", "001_2.prompt", "__MACOSX/box1/001_2.json"]
+            .iter().map(|filename| filename.to_string()).collect::<Vec<String>>();
+        let duplicates = duplicate_basenames(filenames.iter());
+        assert_eq!(duplicates.len(), 1);
+        assert!(duplicates.contains("001_1.json"));
+    }
+
+    #[test]
+    fn zip_files_are_read_one_at_a_time_in_name_order() {
+        let path = std::env::temp_dir().join(format!("json-file-input-test-{}.zip", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        zip.add_directory("box2/", options).unwrap();
+        for (name, content) in [("box2/002_1.json", "{}"), ("001_1.json", "[]"), ("__MACOSX/001_1.json", ""), ("cards.prompt", "Prompt"), ("readme.txt", "")] {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let mut warnings = vec![];
+        let mut input = JsonFileInput::open(path, &mut warnings);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(input.prompt(), "Prompt");
+        assert_eq!(input.names, vec!["001_1.json", "box2/002_1.json", "readme.txt"]);
+        assert_eq!(input.number_of_files(), 3);
+        // Content is only read when asked for, from the archive that is kept open
+        assert_eq!(input.read("box2/002_1.json", &mut warnings), "{}");
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn duplicate_cards_found_before_reading_records() {
+        let dir = std::env::temp_dir().join(format!("json-file-input-duplicates-{}", std::process::id()));
+        for (name, content) in [("box1/001_1.json", r#"{"schema_version": 2}"#), ("box2/001_1.json", r#"{"schema_version": 2}"#),
+                                ("box1/001_2.json", "{}"), ("box2/001_2.json", "{}"), ("box1/001_3.json", "{}")] {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        let mut input = JsonFileInput::open(dir.to_str().unwrap(), &mut vec![]);
+        let names = ["box1/001_1.json", "box1/001_2.json", "box1/001_3.json", "box2/001_1.json", "box2/001_2.json"]
+            .iter().map(|name| name.to_string()).collect::<Vec<String>>();
+        let duplicates = duplicate_basenames(names.iter());
+        let auto = input.first_duplicate_card(&names, JSON_SCHEMA_VERSION_AUTO, &duplicates);
+        let version_1 = input.first_duplicate_card(&names, 1, &duplicates);
+        std::fs::remove_dir_all(&dir).unwrap();
+        // Version 1 files (001_2.json without schema_version) keep the directory in the card name
+        assert_eq!(auto, Some(("box2/001_1.json".to_string(), "box1/001_1.json".to_string())));
+        assert_eq!(version_1, None);
+    }
+
+    #[test]
+    fn invalid_v1_record_is_reported() {
+        let config = Config::from_args(&["-c", "match-single-json", "-s", "test"]);
+        for content in ["[]", r#"{"title": "Titel"}"#, "not json"] {
+            let mut warnings = vec![];
+            let records = convert_json_file(&config, "001_1.json", content, 1, &mut warnings);
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0].1.edition, 9999998);
+            assert!(matches!(warnings[..], [ref warning] if matches!(warning.kind, InputWarningKind::InvalidJson)));
+        }
+    }
+
+    #[test]
+    fn invalid_schema_version_is_reported() {
+        let config = Config::from_args(&["-c", "match-single-json", "-s", "test"]);
+        let mut warnings = vec![];
+        let records = convert_json_file(&config, "box1/001_1.json", r#"{"schema_version": [2], "editions": []}"#, JSON_SCHEMA_VERSION_AUTO, &mut warnings);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].1.edition, 9999998);
+        assert!(matches!(warnings[..], [ref warning] if matches!(warning.kind, InputWarningKind::InvalidJson)));
+    }
+
+    #[test]
+    fn latin1_content_is_decoded_with_warning() {
+        let mut warnings = vec![];
+        assert_eq!(decode_content("001_1.json", "Malmö".as_bytes().to_vec(), &mut warnings), "Malmö");
+        assert!(warnings.is_empty());
+        assert_eq!(decode_content("001_2.json", b"Malm\xf6".to_vec(), &mut warnings), "Malmö");
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(warnings[0].kind, InputWarningKind::NotUtf8));
+        assert_eq!(warnings[0].file, "001_2.json");
+    }
+
+    #[test]
+    fn year_string_ranges() {
+        assert_eq!(parse_year_string("1949, 1951-1954, 1956-").unwrap(), vec![1949, 1951, 1952, 1953, 1954, 1956]);
+    }
+
+    #[test]
+    fn year_string_reversed_range_is_skipped() {
+        assert_eq!(parse_year_string("1954-1951").unwrap(), Vec::<u32>::new());
+        assert_eq!(parse_year_string("1949, 1954-1951").unwrap(), vec![1949]);
+    }
+
+    #[test]
+    fn year_string_huge_range_is_an_error() {
+        assert!(matches!(parse_year_string("1000-3000"), Err(YearParseError::SpanTooLong(1000, 3000))));
+    }
+
+    #[test]
+    fn year_string_garbage_is_an_error() {
+        assert!(matches!(parse_year_string("c. 19xx"), Err(YearParseError::Syntax(_))));
+        assert!(matches!(parse_year_string(""), Err(YearParseError::Syntax(_))));
+        assert!(matches!(parse_year_string("12345"), Err(YearParseError::Syntax(_))));
+    }
+
+    #[test]
+    fn json_line_card_names() {
+        assert_eq!(json_line_card_name(r#"{"id": "003_00153", "editions": []}"#, 1), "003_00153.json");
+        assert_eq!(json_line_card_name(r#"{"id": 42, "editions": []}"#, 2), "42.json");
+        assert_eq!(json_line_card_name(r#"{"title": "A title", "editions": []}"#, 3), "line_3.json");
+        assert_eq!(json_line_card_name("not json", 4), "line_4.json");
+    }
+}